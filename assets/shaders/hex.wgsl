@@ -0,0 +1,75 @@
+// Cube coordinates on the hexagonal grid, matching `src/game/hex.rs`.
+//
+// Cells are stored in the map texture at the texel given by their x and y coordinates,
+// wrapping around at the edges of the texture. Bounded maps only use the texture's worth
+// of cells starting at their lowest cell.
+
+const S = sqrt(3.0) / 6.0;
+const R = 1.0 / sqrt(3.0);
+
+const POSITION_TO_CUBE: mat2x3<f32> = mat2x3<f32>(
+    vec3<f32>( 1.0,  0.0,-1.0),
+    vec3<f32>(- R ,2.0*R, -R ),
+);
+
+const CUBE_TO_POSITION: mat3x2<f32> = mat3x2<f32>(
+    vec2<f32>( 0.5,    -S),
+    vec2<f32>( 0.0, 2.0*S),
+    vec2<f32>(-0.5,    -S),
+);
+
+const SUM_OTHER: mat3x3<f32> = mat3x3<f32>(
+    vec3<f32>(0.0,1.0,1.0),
+    vec3<f32>(1.0,0.0,1.0),
+    vec3<f32>(1.0,1.0,0.0),
+);
+
+fn round_hex(hex: vec3<f32>) -> vec3<f32> {
+    var res = round(hex);
+    let diff = abs(hex - res);
+    if diff.x > diff.y && diff.x > diff.z {
+        res.x = -res.y -res.z;
+    } else if diff.y > diff.z {
+        res.y = -res.x -res.z;
+    } else {
+        res.z = -res.x -res.y;
+    }
+    return res;
+}
+
+// The texel storing a cell, in a map texture of the given size.
+fn hex_texel(hex: vec3<f32>, size: vec2<u32>) -> vec2<i32> {
+    let dims = vec2<i32>(size);
+    return ((vec2<i32>(hex.xy) % dims) + dims) % dims;
+}
+
+// Whether a cell lies on a bounded map of the given size, whose lowest cell is at `origin`,
+// like `MapShape` in `src/game/hex_map.rs`.
+fn hex_in_bounds(axial: vec2<i32>, origin: vec2<i32>, size: vec2<u32>) -> bool {
+    let dims = vec2<i32>(size);
+    let offset = axial - origin;
+    return all(offset >= vec2<i32>(0)) && all(offset < dims);
+}
+
+// Offsets of the cells within two steps of a cell.
+const SPIRAL_2: array<vec3<f32>, 19> = array<vec3<f32>, 19>(
+    vec3<f32>( 0, 0, 0),
+    vec3<f32>(-1, 1, 0),
+    vec3<f32>( 1,-1, 0),
+    vec3<f32>(-1, 0, 1),
+    vec3<f32>( 1, 0,-1),
+    vec3<f32>( 0,-1, 1),
+    vec3<f32>( 0, 1,-1),
+    vec3<f32>( 2,-1,-1),
+    vec3<f32>(-1, 2,-1),
+    vec3<f32>(-1,-1, 2),
+    vec3<f32>(-2, 1, 1),
+    vec3<f32>( 1,-2, 1),
+    vec3<f32>( 1, 1,-2),
+    vec3<f32>(-2, 2, 0),
+    vec3<f32>( 2,-2, 0),
+    vec3<f32>(-2, 0, 2),
+    vec3<f32>( 2, 0,-2),
+    vec3<f32>( 0,-2, 2),
+    vec3<f32>( 0, 2,-2),
+);
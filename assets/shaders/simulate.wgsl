@@ -1,11 +1,119 @@
-@group(0) @binding(0) var cells: texture_storage_2d<rgba8uint, read_write>;
+// One tick of the world's growth, the same as `step` in `src/game/simulation.rs`.
+//
+// Every cell that a rule could change rolls its xorshift16 generator once per tick, and may
+// change its tile depending on its neighbours. The next state of every cell is written to a
+// second texture, so all cells see their neighbours as they were before the tick.
+
+@group(0) @binding(0) var cells: texture_storage_2d<rgba8uint, read>;
+@group(0) @binding(1) var next: texture_storage_2d<rgba8uint, write>;
+// The rules that depend on the tile catalogue, see `SimulationRules`.
+struct Rules {
+    forest_tile: u32,
+    house_tile: u32,
+    // How each tile takes part in the simulation, indexed by tile id.
+    flags: array<u32>,
+}
+@group(0) @binding(2) var<storage, read> rules: Rules;
+// The chunks to work on, one per workgroup layer. Chunks with zero in z are only copied.
+// The bits of w mark the sides of the chunk on the edge of the map, in the order -x, +x, -y, +y.
+// Cells have no neighbours beyond those sides. If the fifth bit is set, the map is the window of an
+// infinite map, whose neighbours beyond those sides aren't known, so the cells along them are only copied.
+@group(1) @binding(0) var<storage, read> chunks: array<vec4<u32>>;
+
+const GRASS: u32 = 1u;
+const FOREST: u32 = 2u;
+const ROAD: u32 = 4u;
+const SETTLEMENT: u32 = 8u;
+const RIVER: u32 = 16u;
+const ERODES_SHIFT: u32 = 8u;
+
+const CHUNK_SIZE: u32 = #{CHUNK_SIZE};
+const FOREST_CHANCE: u32 = #{FOREST_CHANCE};
+const VILLAGE_CHANCE: u32 = #{VILLAGE_CHANCE};
+const EROSION_CHANCE: u32 = #{EROSION_CHANCE};
+
+// Texel offsets of the six neighbours, see `Hex::DIRECTIONS`.
+const DIRECTIONS: array<vec2<i32>, 6> = array<vec2<i32>, 6>(
+    vec2<i32>( 1, 0),
+    vec2<i32>( 1,-1),
+    vec2<i32>( 0,-1),
+    vec2<i32>(-1, 0),
+    vec2<i32>(-1, 1),
+    vec2<i32>( 0, 1),
+);
+
+fn xorshift16(state: u32) -> u32 {
+    var x = state;
+    x ^= (x << 7u) & 0xffffu;
+    x ^= x >> 9u;
+    x ^= (x << 8u) & 0xffffu;
+    return x;
+}
 
 @compute @workgroup_size(8,8)
 fn main(@builtin(global_invocation_id) gid: vec3<u32>) {
-    var state = textureLoad(cells, vec2<i32>(gid.xy));
-    state.r += gid.x;
-    state.g += gid.y;
-    state.b -= gid.x + gid.y;
-    state = state % 256;
-    //textureStore(cells, vec2<i32>(gid.xy), vec4(state.r, state.g, state.b, 1.0));
+    let chunk = chunks[gid.z];
+    let size = vec2<i32>(textureDimensions(cells));
+    let texel = vec2<i32>(chunk.xy * CHUNK_SIZE + gid.xy);
+    if any(texel >= size) {
+        return;
+    }
+
+    let cell = textureLoad(cells, texel);
+    if chunk.z == 0u {
+        textureStore(next, texel, cell);
+        return;
+    }
+    let flags = rules.flags[cell.r];
+
+    var forests = 0u;
+    var settlements = 0u;
+    var roads = 0u;
+    var rivers = 0u;
+    for (var side = 0; side < 6; side += 1) {
+        let local = vec2<i32>(gid.xy) + DIRECTIONS[side];
+        let crossed = select(0u, 1u, local.x < 0)
+            | select(0u, 2u, local.x >= i32(CHUNK_SIZE))
+            | select(0u, 4u, local.y < 0)
+            | select(0u, 8u, local.y >= i32(CHUNK_SIZE));
+        if (crossed & chunk.w) != 0u {
+            if (chunk.w & 16u) != 0u {
+                textureStore(next, texel, cell);
+                return;
+            }
+            continue;
+        }
+        let neighbour = textureLoad(cells, (texel + DIRECTIONS[side] + size) % size);
+        let other = rules.flags[neighbour.r];
+        forests += u32((other & FOREST) != 0u);
+        settlements += u32((other & SETTLEMENT) != 0u);
+        roads += u32((other & ROAD) != 0u);
+        rivers += u32((other & RIVER) != 0u);
+    }
+
+    let erodes = flags >> ERODES_SHIFT;
+    // Villages need a road to grow along.
+    let village = select(0u, settlements * VILLAGE_CHANCE, roads > 0u);
+    let forest = forests * FOREST_CHANCE;
+    let grows = (flags & GRASS) != 0u && village + forest > 0u;
+    let wears = erodes != 0u && rivers > 0u;
+    if !grows && !wears {
+        textureStore(next, texel, cell);
+        return;
+    }
+
+    let roll = xorshift16((cell.b << 8u) | cell.a);
+    var tile = cell.r;
+    if grows {
+        if roll < village {
+            tile = rules.house_tile;
+        } else if roll < village + forest {
+            tile = rules.forest_tile;
+        }
+    }
+    if wears && roll < rivers * EROSION_CHANCE {
+        tile = erodes - 1u;
+    }
+
+    textureStore(next, texel, vec4<u32>(tile, cell.g, roll >> 8u, roll & 0xffu));
 }
@@ -5,14 +5,30 @@
     position_world_to_clip,
     position_world_to_view,
 }
+#import "shaders/hex.wgsl"::{CUBE_TO_POSITION, POSITION_TO_CUBE, SPIRAL_2, hex_in_bounds, hex_texel, round_hex}
 
-@group(2) @binding(0) var map_texture: texture_storage_2d<rgba8uint, read>;
+// Sampled rather than a storage texture, so that the map can also be drawn where compute shaders aren't available.
+@group(2) @binding(0) var map_texture: texture_2d<u32>;
 @group(2) @binding(1) var tileset_texture: texture_2d<f32>;
 @group(2) @binding(2) var tileset_sampler: sampler;
 @group(2) @binding(3) var<uniform> hover: vec4<f32>;
 @group(2) @binding(4) var<uniform> tilesize: f32;
 @group(2) @binding(5) var<uniform> tilecount: f32;
 @group(2) @binding(6) var<uniform> selected: vec2<u32>;
+@group(2) @binding(7) var<uniform> placeable: u32;
+// Cube coordinates of quest cells, with the kind of quest plus one in w (zero for no quest).
+@group(2) @binding(8) var<uniform> quest_markers: array<vec4<f32>, 3>;
+// Whether the map ends at its edges, beyond which everything is ocean.
+@group(2) @binding(9) var<uniform> bounded: u32;
+@group(2) @binding(10) var<uniform> ocean_tile: u32;
+// The lowest cell of a bounded map. On infinite maps, this tells which chunk each texel holds.
+@group(2) @binding(11) var<uniform> map_origin: vec2<i32>;
+// The tileset with a mip chain, which lags behind the tileset while the camera turns.
+@group(2) @binding(12) var tileset_mips: texture_2d<f32>;
+@group(2) @binding(13) var tileset_mips_sampler: sampler;
+@group(2) @binding(14) var<uniform> mips_ready: u32;
+// Empty pixels around each tile in the tileset.
+@group(2) @binding(15) var<uniform> tile_padding: f32;
 
 struct VertexInput {
     @location(0) clip_pos: vec3<f32>,
@@ -29,25 +45,6 @@ struct FragmentOutput {
     @builtin(frag_depth) depth: f32,
 };
 
-const S = sqrt(3.0) / 6.0;
-const R = 1.0 / sqrt(3.0);
-
-const POSITION_TO_CUBE: mat2x3<f32> = mat2x3<f32>(
-    vec3<f32>( 1.0,  0.0,-1.0),
-    vec3<f32>(- R ,2.0*R, -R ),
-);
-
-const CUBE_TO_POSITION: mat3x2<f32> = mat3x2<f32>(
-    vec2<f32>( 0.5,    -S),
-    vec2<f32>( 0.0, 2.0*S),
-    vec2<f32>(-0.5,    -S),
-);
-const SUM_OTHER: mat3x3<f32> = mat3x3<f32>(
-    vec3<f32>(0.0,1.0,1.0),
-    vec3<f32>(1.0,0.0,1.0),
-    vec3<f32>(1.0,1.0,0.0),
-);
-
 /// Pass-through vertex shader, skipping camera transform.
 /// Used for rendering a full screen triangle.
 @vertex
@@ -77,45 +74,10 @@ fn max3(p: vec3<f32>) -> f32 {
     return max(max(p.x, p.y), p.z);
 }
 
-fn round_hex(hex: vec3<f32>) -> vec3<f32> {
-    var res = round(hex);
-    let diff = abs(hex - res);
-    if diff.x > diff.y && diff.x > diff.z {
-        res.x = -res.y -res.z;
-    } else if diff.y > diff.z {
-        res.y = -res.x -res.z;
-    } else {
-        res.z = -res.x -res.y;
-    }
-    return res;
-}
-
 fn sum(v: vec3<f32>) -> f32 {
     return v.x+v.y+v.z;
 }
 
-const OFFSETS: array<vec3<f32>, 19> = array<vec3<f32>, 19>(
-    vec3<f32>( 0, 0, 0),
-    vec3<f32>(-1, 1, 0),
-    vec3<f32>( 1,-1, 0),
-    vec3<f32>(-1, 0, 1),
-    vec3<f32>( 1, 0,-1),
-    vec3<f32>( 0,-1, 1),
-    vec3<f32>( 0, 1,-1),
-    vec3<f32>( 2,-1,-1),
-    vec3<f32>(-1, 2,-1),
-    vec3<f32>(-1,-1, 2),
-    vec3<f32>(-2, 1, 1),
-    vec3<f32>( 1,-2, 1),
-    vec3<f32>( 1, 1,-2),
-    vec3<f32>(-2, 2, 0),
-    vec3<f32>( 2,-2, 0),
-    vec3<f32>(-2, 0, 2),
-    vec3<f32>( 2, 0,-2),
-    vec3<f32>( 0,-2, 2),
-    vec3<f32>( 0, 2,-2),
-);
-
 fn multiply_alpha(c:vec4<f32>) -> vec4<f32> {
     return vec4(c.rgb * c.a, c.a);
 }
@@ -126,6 +88,23 @@ fn blend(a:vec4<f32>, b:vec4<f32>) -> vec4<f32> {
     return a + b * (1.0 - a.a);
 }
 
+// Tint for the quest attached to the given cell, if any.
+fn quest_tint(hex: vec3<f32>) -> vec4<f32> {
+    let size = textureDimensions(map_texture);
+    let cell = hex_texel(hex, size);
+    for (var i = 0; i < 3; i += 1) {
+        let marker = quest_markers[i];
+        if marker.w == 0.0 || any(cell != hex_texel(marker.xyz, size)) {
+            continue;
+        }
+        if marker.w == 1.0 {
+            return 0.35 * rgb(1.0,0.85,0.2);
+        }
+        return 0.35 * rgb(0.2,0.6,1.0);
+    }
+    return vec4(0.0);
+}
+
 @fragment
 fn fragment(in: VertexOutput) -> FragmentOutput {
     let center_hex = round_hex(in.hexagon);
@@ -138,10 +117,16 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
     // Sample tile texture
     var color = vec4(0.0); // vec4(vec3(edge_color), 1.0);
     var depth = -10.0;
-    var tile_scale = vec2(1.0 / tilecount, 1.0/6.0);
+    let stride = tilesize + 2.0 * tile_padding;
+    let tileset_size = stride * vec2(tilecount, 6.0);
+
+    // Texels of the tileset per pixel on screen. Tiles are two units wide, so the mip level
+    // follows from how many pixels a tile covers on screen.
+    let texels = 0.5 * tilesize * max(length(dpdx(in.view_pos.xy)), length(dpdy(in.view_pos.xy)));
+    let level = clamp(log2(texels), 0.0, f32(textureNumLevels(tileset_mips) - 1u));
 
     for (var i = 0; i < 19; i += 1) {
-        let hex = center_hex + OFFSETS[i];
+        let hex = center_hex + SPIRAL_2[i];
         let hex_position = vec3(CUBE_TO_POSITION * hex, 0.0).xzy;
         let position = in.view_pos - position_world_to_view(hex_position);
         if position.x < -0.6 || 0.6 < position.x || position.y < -0.6 || 0.85 < position.y {
@@ -152,15 +137,31 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
         var tile = selected;
         let is_hover = all(abs(vec4(hex,0.0) - hover) < vec4(0.1));
         if !is_hover {
-            tile = textureLoad(map_texture, vec2<i32>(hex.xy) & vec2(1023)).rg;
+            let size = textureDimensions(map_texture);
+            if bounded != 0u && !hex_in_bounds(vec2<i32>(hex.xy), map_origin, size) {
+                tile = vec2(ocean_tile, 0u);
+            } else {
+                tile = textureLoad(map_texture, hex_texel(hex, size), 0).rg;
+            }
         }
         let tile_id  = f32(tile.r);
         let tile_rot = f32(tile.g);
 
-        var new_color = textureSample(tileset_texture, tileset_sampler, (offset + vec2(tile_id, tile_rot))*tile_scale);
+        let uv = (vec2(tile_id, tile_rot) * stride + tile_padding + offset * tilesize) / tileset_size;
+        var new_color: vec4<f32>;
+        if mips_ready != 0u {
+            new_color = textureSampleLevel(tileset_mips, tileset_mips_sampler, uv, level);
+        } else {
+            new_color = textureSampleLevel(tileset_texture, tileset_sampler, uv, 0.0);
+        }
         if new_color.a > 0.1 && depth < position.y {
+            new_color = blend(quest_tint(hex), new_color);
             if is_hover {
-                new_color = blend(0.2 * rgb(1.0,0.0,1.0), new_color);
+                if placeable != 0u {
+                    new_color = blend(0.2 * rgb(0.0,1.0,0.0), new_color);
+                } else {
+                    new_color = blend(0.3 * rgb(1.0,0.0,0.0), new_color);
+                }
             }
             color = blend(new_color, color);
             depth = position.y;
@@ -0,0 +1,25 @@
+//! Times the noise-driven biome generator on maps of every size that a new game can have.
+//!
+//! Run with `cargo bench --bench biome`. A 1024x1024 map should take well under a second.
+
+use std::time::{Duration, Instant};
+
+use sprawl::game::{generate_biomes, BiomeSettings, MapBoundary, MapShape, TileCatalogue, WorldRng, WorldSeed, MAP_SIZES};
+
+const RUNS: u64 = 3;
+
+fn main() {
+    let catalogue = TileCatalogue::standard();
+    let settings = BiomeSettings::default();
+    for size in MAP_SIZES {
+        let mut total = Duration::ZERO;
+        for seed in 0..RUNS {
+            let mut rng = WorldRng::new(WorldSeed(seed));
+            let start = Instant::now();
+            let map = generate_biomes(MapShape::new(size, MapBoundary::Bounded), &settings, &catalogue, &mut *rng);
+            total += start.elapsed();
+            assert_eq!(map.size(), size);
+        }
+        println!("{size:>4}x{size:<4} {:>10.2?} per map", total / RUNS as u32);
+    }
+}
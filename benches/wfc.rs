@@ -0,0 +1,37 @@
+//! Times wave function collapse on islands of a few sizes, as the map generator does for a new game.
+//!
+//! Run with `cargo bench --bench wfc`.
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use sprawl::game::{HexMap, MapBoundary, MapShape, TileCatalogue, Wfc, WorldRng, WorldSeed};
+
+const RUNS: u64 = 5;
+const MAX_BACKTRACKS: u32 = 10000;
+
+fn main() {
+    let catalogue = TileCatalogue::standard();
+    let wfc = Wfc::new(&catalogue);
+    let ocean = uvec2(catalogue.roles.ocean, 0);
+    for size in [16, 32, 64, 128] {
+        let mut total = Duration::ZERO;
+        let (mut backtracks, mut failures) = (0, 0);
+        for seed in 0..RUNS {
+            let mut rng = WorldRng::new(WorldSeed(seed));
+            let shape = MapShape::new(2 * size, MapBoundary::Bounded);
+            let mut map = HexMap::new(shape, ocean, &catalogue, &mut *rng);
+            let start = Instant::now();
+            match wfc.generate(&mut map, -IVec2::splat(size as i32 / 2), UVec2::splat(size), MAX_BACKTRACKS, &mut *rng) {
+                Ok(count) => backtracks += count,
+                Err(_) => failures += 1,
+            }
+            total += start.elapsed();
+        }
+        println!(
+            "{size:>4}x{size:<4} {:>10.2?} per island, {:.1} backtracks on average, {failures} failed",
+            total / RUNS as u32,
+            backtracks as f64 / RUNS as f64,
+        );
+    }
+}
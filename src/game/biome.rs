@@ -0,0 +1,399 @@
+//! Fast map generation from layered noise.
+//!
+//! Every cell samples elevation, moisture and temperature fields, which decide its biome.
+//! Water and river cells then pick the rotated tile that best connects to the biomes around them.
+
+use bevy::{
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
+use rand::Rng;
+
+use super::{hex_map::{random_prng, Cell}, load_tiles::TileRoles, prelude::*};
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<BiomeSettings>();
+    app.init_resource::<BiomeSettings>();
+}
+
+/// Parameters of the biome generator. Changing these during gameplay regenerates the map.
+#[derive(Resource, Reflect, Clone, Debug)]
+#[reflect(Resource)]
+pub struct BiomeSettings {
+    /// Approximate size of continents, in cells.
+    pub scale: f32,
+    /// Number of noise layers, each with twice the detail of the previous one.
+    pub octaves: u32,
+    /// Elevation below which cells are water.
+    pub sea_level: f32,
+    /// Elevation above which cells are hills.
+    pub hill_level: f32,
+    /// Elevation above which cells are mountains.
+    pub mountain_level: f32,
+    /// Moisture above which grass becomes forest.
+    pub forest_moisture: f32,
+    /// Temperature below which hills are rocky and forests don't grow.
+    pub cold_temperature: f32,
+    /// Approximate distance between rivers, in cells.
+    pub river_scale: f32,
+    /// Chance for a grass cell to have rocks on it.
+    pub rock_chance: f32,
+}
+
+impl Default for BiomeSettings {
+    fn default() -> Self {
+        Self {
+            scale: 128.0,
+            octaves: 5,
+            sea_level: 0.42,
+            hill_level: 0.64,
+            mountain_level: 0.72,
+            forest_moisture: 0.55,
+            cold_temperature: 0.35,
+            river_scale: 48.0,
+            rock_chance: 0.02,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Biome {
+    Water,
+    River,
+    Grass,
+    Forest,
+    Hill,
+    RockyHill,
+    Mountain,
+}
+
+/// Tileable fractal value noise over the map's texels.
+#[derive(Clone)]
+struct Noise {
+    seed: u32,
+    map_size: u32,
+}
+
+impl Noise {
+    fn hash(&self, p: IVec2) -> f32 {
+        let mut h = (p.x as u32).wrapping_mul(0x8da6b343)
+            ^ (p.y as u32).wrapping_mul(0xd8163841)
+            ^ self.seed.wrapping_mul(0xcb1ab31f);
+        h ^= h >> 13;
+        h = h.wrapping_mul(0x5bd1e995);
+        h ^= h >> 15;
+        (h >> 8) as f32 / (1 << 24) as f32
+    }
+
+    /// Smoothly interpolated noise on a lattice that repeats every `period` lattice cells.
+    fn value(&self, p: Vec2, period: i32) -> f32 {
+        let cell = p.floor();
+        let t = p - cell;
+        let t = t * t * (3.0 - 2.0 * t);
+        let corner = |offset: IVec2| self.hash((cell.as_ivec2() + offset).rem_euclid(IVec2::splat(period)));
+        let top = corner(ivec2(0, 0)).lerp(corner(ivec2(1, 0)), t.x);
+        let bottom = corner(ivec2(0, 1)).lerp(corner(ivec2(1, 1)), t.x);
+        top.lerp(bottom, t.y)
+    }
+
+    /// Sum of octaves in `0..1`, with features of about `scale` cells, repeating seamlessly across the map.
+    fn fractal(&self, texel: UVec2, scale: f32, octaves: u32) -> f32 {
+        let mut period = (self.map_size as f32 / scale).round().max(1.0) as i32;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        let mut sum = 0.0;
+        for _ in 0..octaves.max(1) {
+            let p = texel.as_vec2() * period as f32 / self.map_size as f32;
+            sum += amplitude * self.value(p, period);
+            total += amplitude;
+            amplitude *= 0.5;
+            period *= 2;
+        }
+        sum / total
+    }
+}
+
+/// Tile ids of the tiles used by the generator, from the roles in the catalogue.
+#[derive(Clone)]
+struct BiomeTiles {
+    roles: TileRoles,
+    /// The ocean followed by the coasts. The first entry is preferred when several fit equally well.
+    water: Vec<u32>,
+    /// The unrotated edges of every tile.
+    edges: Vec<EdgeSet>,
+}
+
+impl BiomeTiles {
+    fn new(catalogue: &TileCatalogue) -> Self {
+        let roles = catalogue.roles.clone();
+        Self {
+            water: [roles.ocean].into_iter().chain(roles.coast.iter().copied()).collect(),
+            edges: catalogue.tiles.iter().map(|tile| tile.edges).collect(),
+            roles,
+        }
+    }
+
+    /// Picks the rotated tile from `candidates` whose edges best match `desired`.
+    fn best_tile(&self, candidates: &[u32], desired: [Edge; 6]) -> UVec2 {
+        let mut best = (0, uvec2(candidates[0], 0));
+        for &tile in candidates {
+            for rotation in 0..6 {
+                let edges = self.edges[tile as usize].rotated(rotation);
+                let score = (0..6).map(|side| match (edges.get(side), desired[side]) {
+                    (a, b) if a == b => 2,
+                    (Edge::CoastNext | Edge::CoastPrev, Edge::Water | Edge::Grass) => 1,
+                    _ => 0,
+                }).sum();
+                if score > best.0 {
+                    best = (score, uvec2(tile, rotation));
+                }
+            }
+        }
+        best.1
+    }
+}
+
+/// The edge that a cell of biome `cell` should have toward a neighbour of biome `neighbour`.
+fn desired_edge(cell: Biome, neighbour: Biome) -> Edge {
+    match (cell, neighbour) {
+        (Biome::Water, Biome::Water) => Edge::Water,
+        (Biome::Water | Biome::River, Biome::River) => Edge::River,
+        (Biome::River, Biome::Water) => Edge::River,
+        _ => Edge::Grass,
+    }
+}
+
+/// The biome of a cell at elevation `height`. The other samples are only taken when they matter,
+/// as most cells don't need all of them.
+fn classify(
+    settings: &BiomeSettings,
+    height: f32,
+    moisture: impl FnOnce() -> f32,
+    temperature: impl Fn() -> f32,
+    is_river: impl FnOnce() -> bool,
+) -> Biome {
+    if height < settings.sea_level {
+        Biome::Water
+    } else if height < settings.hill_level && is_river() {
+        Biome::River
+    } else if height > settings.mountain_level {
+        Biome::Mountain
+    } else if height > settings.hill_level {
+        if temperature() < settings.cold_temperature {
+            Biome::RockyHill
+        } else {
+            Biome::Hill
+        }
+    } else if moisture() > settings.forest_moisture && temperature() > settings.cold_temperature {
+        Biome::Forest
+    } else {
+        Biome::Grass
+    }
+}
+
+/// Evaluates `f` for the indices `0..count`, spread over the compute task pool.
+fn par_map<T: Send + 'static>(count: u32, f: impl Fn(u32) -> T + Sync) -> Vec<T> {
+    let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+    let chunk = count.div_ceil(4 * pool.thread_num() as u32).max(1);
+    let f = &f;
+    pool.scope(|scope| {
+        for start in (0..count).step_by(chunk as usize) {
+            scope.spawn(async move {
+                (start..(start + chunk).min(count)).map(f).collect::<Vec<T>>()
+            });
+        }
+    }).into_iter().flatten().collect()
+}
+
+/// The noise fields that decide the biomes.
+#[derive(Clone)]
+struct Fields {
+    elevation: Noise,
+    moisture: Noise,
+    temperature: Noise,
+    river: Noise,
+    decoration: Noise,
+}
+
+impl Fields {
+    fn new(map_size: u32, rng: &mut impl Rng) -> Self {
+        let mut noise = || Noise {seed: rng.r#gen(), map_size};
+        Self {
+            elevation: noise(),
+            moisture: noise(),
+            temperature: noise(),
+            river: noise(),
+            decoration: noise(),
+        }
+    }
+
+    /// Rivers follow the contour lines of the river noise, on the high side of the line.
+    fn river_side(&self, settings: &BiomeSettings, texel: UVec2) -> bool {
+        self.river.fractal(texel, settings.river_scale, 3) >= 0.5
+    }
+
+    /// The biome at a texel, where `is_river` tells whether the cell lies along a river.
+    fn biome(&self, settings: &BiomeSettings, texel: UVec2, is_river: impl FnOnce() -> bool) -> Biome {
+        let field = |noise: &Noise| noise.fractal(texel, settings.scale, settings.octaves);
+        classify(settings, field(&self.elevation), || field(&self.moisture), || field(&self.temperature), is_river)
+    }
+
+    /// The tile for a cell of `biome` at a texel, connecting to the biomes of its neighbours.
+    fn tile(&self, settings: &BiomeSettings, tiles: &BiomeTiles, texel: UVec2, biome: Biome, neighbours: [Biome; 6], rotation: u32) -> UVec2 {
+        match biome {
+            Biome::Water | Biome::River => {
+                let desired = neighbours.map(|neighbour| desired_edge(biome, neighbour));
+                if desired == [Edge::Water; 6] {
+                    uvec2(tiles.roles.ocean, rotation)
+                } else if biome == Biome::Water {
+                    tiles.best_tile(&tiles.water, desired)
+                } else {
+                    tiles.best_tile(&tiles.roles.river, desired)
+                }
+            }
+            Biome::Grass if self.decoration.hash(texel.as_ivec2()) < settings.rock_chance => uvec2(tiles.roles.rocks, rotation),
+            Biome::Grass => uvec2(tiles.roles.grassland, rotation),
+            Biome::Forest => uvec2(tiles.roles.forest, rotation),
+            Biome::Hill => uvec2(tiles.roles.hill, rotation),
+            Biome::RockyHill => uvec2(tiles.roles.rocky_hill, rotation),
+            Biome::Mountain => uvec2(tiles.roles.mountain, rotation),
+        }
+    }
+}
+
+/// Generates a map of the given shape. A bounded map gets a coast along its edges.
+///
+/// Infinite maps are generated chunk by chunk with a [`ChunkGenerator`] instead.
+pub fn generate(shape: MapShape, settings: &BiomeSettings, catalogue: &TileCatalogue, rng: &mut impl Rng) -> HexMap {
+    let size = shape.size;
+    let fields = Fields::new(size, rng);
+
+    let river_side = par_map(size * size, |index| fields.river_side(settings, uvec2(index % size, index / size)));
+    let is_river = |index: u32| {
+        river_side[index as usize] && Hex::from_index(index as usize, size).neighbours().iter().any(|hex| !river_side[hex.index(size)])
+    };
+
+    let biomes = par_map(size * size, |index| {
+        let edge = (0..6).any(|side| shape.neighbour(index as usize, side).is_none());
+        if edge {
+            Biome::Water
+        } else {
+            fields.biome(settings, uvec2(index % size, index / size), || is_river(index))
+        }
+    });
+
+    let tiles = BiomeTiles::new(catalogue);
+    let cells = biomes.iter().enumerate().map(|(index, &biome)| {
+        let texel = uvec2(index as u32 % size, index as u32 / size);
+        let neighbours = Hex::from_index(index, size).neighbours().map(|hex| biomes[hex.index(size)]);
+        let tile = fields.tile(settings, &tiles, texel, biome, neighbours, rng.gen_range(0..6));
+        Cell::new(tile, random_prng(rng))
+    }).collect();
+    HexMap::from_cells(shape, cells, catalogue)
+}
+
+/// Infinite maps repeat after this many cells along each axis, which is further than anyone will pan.
+const STREAMED_PERIOD: u32 = 1 << 16;
+
+/// Generates the chunks of an infinite map, so that they connect seamlessly in any order.
+#[derive(Clone)]
+pub struct ChunkGenerator {
+    settings: BiomeSettings,
+    fields: Fields,
+    tiles: BiomeTiles,
+}
+
+impl ChunkGenerator {
+    pub fn new(settings: &BiomeSettings, catalogue: &TileCatalogue, rng: &mut impl Rng) -> Self {
+        Self {
+            settings: settings.clone(),
+            fields: Fields::new(STREAMED_PERIOD, rng),
+            tiles: BiomeTiles::new(catalogue),
+        }
+    }
+
+    /// Generates the `size` by `size` cells starting at axial coordinates `origin`, in row-major order.
+    pub fn generate(&self, origin: IVec2, size: u32, rng: &mut impl Rng) -> Vec<Cell> {
+        let settings = &self.settings;
+        let texel = |hex: IVec2| hex.rem_euclid(IVec2::splat(STREAMED_PERIOD as i32)).as_uvec2();
+        // Rivers depend on the cells next to them, and tiles on the biomes next to them,
+        // so the noise is sampled two cells beyond the chunk.
+        const MARGIN: i32 = 2;
+        let span = size + 2 * MARGIN as u32;
+        let corner = origin - IVec2::splat(MARGIN);
+        let at = |index: u32| corner + ivec2((index % span) as i32, (index / span) as i32);
+        let local = |hex: IVec2| {
+            let offset = hex - corner;
+            (offset.y * span as i32 + offset.x) as usize
+        };
+        let inner = |hex: IVec2| (hex - corner).cmpge(IVec2::ONE).all() && (hex - corner).cmplt(IVec2::splat(span as i32 - 1)).all();
+
+        let river_side = par_map(span * span, |index| self.fields.river_side(settings, texel(at(index))));
+        let biomes = par_map(span * span, |index| {
+            let hex = at(index);
+            self.fields.biome(settings, texel(hex), || {
+                // Cells on the outer ring only matter for the rivers next to them.
+                inner(hex) && river_side[index as usize]
+                    && Hex::from_axial(hex).neighbours().iter().any(|hex| !river_side[local(hex.axial())])
+            })
+        });
+
+        (0..size * size).map(|index| {
+            let hex = origin + ivec2((index % size) as i32, (index / size) as i32);
+            let neighbours = Hex::from_axial(hex).neighbours().map(|hex| biomes[local(hex.axial())]);
+            let tile = self.fields.tile(settings, &self.tiles, texel(hex), biomes[local(hex)], neighbours, rng.gen_range(0..6));
+            Cell::new(tile, random_prng(rng))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::load_tiles::TileCategory, *};
+
+    #[test]
+    fn samples_pick_their_tiles() {
+        let catalogue = TileCatalogue::standard();
+        let settings = BiomeSettings {rock_chance: 0.0, ..default()};
+        let mut rng = WorldRng::new(WorldSeed(1));
+        let fields = Fields::new(64, &mut *rng);
+        let tiles = BiomeTiles::new(&catalogue);
+        let id = |tile: UVec2| catalogue.tiles[tile.x as usize].id();
+
+        // Elevation, moisture, temperature and whether the cell lies along a river.
+        for (height, moisture, temperature, river, expected) in [
+            (0.2, 0.5, 0.5, false, "water"),
+            (0.2, 0.5, 0.5, true, "water"),
+            (0.5, 0.5, 0.5, true, "river"),
+            (0.5, 0.4, 0.5, false, "grass"),
+            (0.5, 0.7, 0.5, false, "grass-forest"),
+            (0.5, 0.7, 0.2, false, "grass"),
+            (0.68, 0.5, 0.5, false, "grass-hill"),
+            (0.68, 0.5, 0.2, false, "stone-hill"),
+            (0.9, 0.5, 0.5, true, "stone-mountain"),
+        ] {
+            let biome = classify(&settings, height, || moisture, || temperature, || river);
+            // Cells surrounded by their own biome, which leaves a river running through.
+            let neighbours = [biome; 6];
+            let tile = fields.tile(&settings, &tiles, UVec2::ZERO, biome, neighbours, 0);
+            if expected == "river" {
+                assert!(tiles.roles.river.contains(&tile.x), "{} at {height}", id(tile));
+            } else {
+                assert_eq!(id(tile), expected, "{height}, {moisture}, {temperature}, {river}");
+            }
+        }
+    }
+
+    #[test]
+    fn bounded_maps_end_in_water() {
+        let catalogue = TileCatalogue::standard();
+        let mut rng = WorldRng::new(WorldSeed(2));
+        let shape = MapShape::new(128, MapBoundary::Bounded);
+        let map = generate(shape, &BiomeSettings::default(), &catalogue, &mut *rng);
+        for index in 0..(shape.size * shape.size) as usize {
+            if (0..6).all(|side| shape.neighbour(index, side).is_some()) {continue}
+            let tile = map.get(shape.hex(index)).tile();
+            assert_eq!(catalogue.tiles[tile.x as usize].category, TileCategory::Water, "{:?}", shape.hex(index));
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! A finite, shuffled deck of tiles that the player draws from.
+//!
+//! The size of the deck and how often each tile occurs are read from `assets/standard.deck.ron`.
+//! Placing a tile consumes the top of the deck, and the game ends once the deck is empty,
+//! see [`game_over`].
+
+use std::{collections::BTreeMap, fmt};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use rand::{distributions::{Distribution, WeightedIndex}, Rng};
+use serde::Deserialize;
+
+use crate::{asset_tracking::LoadResource, theme::prelude::*, PausableSystems};
+
+use super::{history::EditHistory, prelude::*, scoring::TilePlaced};
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<GameMode>();
+    app.register_type::<TileDeck>();
+    app.init_resource::<GameMode>();
+    app.init_resource::<TileDeck>();
+    app.init_asset::<DeckConfig>();
+    app.init_asset_loader::<DeckLoader>();
+    app.register_type::<DeckAssets>();
+    app.load_resource::<DeckAssets>();
+    app.add_observer(draw_placed_tile);
+    app.add_systems(PreUpdate, sync_selected_tile);
+    app.add_systems(Update, (
+        discard_tile.in_set(PausableSystems),
+        (update_preview, update_deck_label).run_if(resource_changed::<TileDeck>.or(resource_changed::<GameMode>)),
+    ));
+}
+
+/// Number of upcoming draws shown next to the tileset preview.
+pub const PREVIEW_COUNT: usize = 3;
+
+/// How the player gets their tiles.
+#[derive(Resource, Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[reflect(Resource)]
+pub enum GameMode {
+    /// Any tile can be chosen, without limit.
+    Sandbox,
+    /// Tiles are drawn from the [`TileDeck`].
+    #[default]
+    Deck,
+}
+
+impl GameMode {
+    /// Whether the player may pick any tile.
+    pub fn free_choice(self) -> bool {
+        self == GameMode::Sandbox
+    }
+
+    /// Whether there is a tile that can be placed.
+    pub fn has_tile(self, deck: &TileDeck) -> bool {
+        self.free_choice() || deck.current().is_some()
+    }
+}
+
+/// Run condition for a game played with a deck that ran out of tiles, which ends the game.
+pub fn game_over(mode: Res<GameMode>, deck: Res<TileDeck>) -> bool {
+    !mode.free_choice() && deck.is_empty()
+}
+
+/// The tiles that are left to be drawn, as model indices.
+#[derive(Resource, Reflect, Clone, Debug, Default)]
+#[reflect(Resource)]
+pub struct TileDeck {
+    /// The top of the deck is at the end.
+    tiles: Vec<u32>,
+}
+
+impl TileDeck {
+    /// Shuffles a new deck according to the given configuration.
+    pub fn new(config: &DeckConfig, catalogue: &TileCatalogue, rng: &mut impl Rng) -> Self {
+        Self {
+            tiles: random_tiles(config, catalogue, config.size, rng),
+        }
+    }
+
+    /// Adds `count` random tiles to the bottom of the deck.
+    pub fn add_random(&mut self, config: &DeckConfig, catalogue: &TileCatalogue, count: u32, rng: &mut impl Rng) {
+        self.tiles.splice(0..0, random_tiles(config, catalogue, count, rng));
+    }
+
+    pub fn from_tiles(tiles: Vec<u32>) -> Self {
+        Self {tiles}
+    }
+
+    /// The tiles from the bottom to the top of the deck.
+    pub fn tiles(&self) -> &[u32] {
+        &self.tiles
+    }
+
+    /// The tile that is up for placement.
+    pub fn current(&self) -> Option<u32> {
+        self.tiles.last().copied()
+    }
+
+    /// The tiles that will be drawn after the current one, in order.
+    pub fn upcoming(&self) -> impl Iterator<Item = u32> + '_ {
+        self.tiles.iter().rev().skip(1).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    pub fn draw(&mut self) -> Option<u32> {
+        self.tiles.pop()
+    }
+
+    /// Puts a drawn tile back on top of the deck.
+    pub fn put_back(&mut self, tile: u32) {
+        self.tiles.push(tile);
+    }
+}
+
+/// Draws `count` tiles from the weighted distribution of the deck file.
+fn random_tiles(config: &DeckConfig, catalogue: &TileCatalogue, count: u32, rng: &mut impl Rng) -> Vec<u32> {
+    let weighted: Vec<(u32, f32)> = config.weights.iter().filter_map(|(name, &weight)| {
+        let Some(tile) = catalogue.find(name) else {
+            warn!("Deck contains unknown tile {name:?}");
+            return None;
+        };
+        Some((tile, weight))
+    }).collect();
+    let distribution = match WeightedIndex::new(weighted.iter().map(|(_, weight)| *weight)) {
+        Ok(distribution) => distribution,
+        Err(err) => {
+            warn!("Invalid deck weights: {err}");
+            return Vec::new();
+        }
+    };
+    (0..count).map(|_| weighted[distribution.sample(rng)].0).collect()
+}
+
+/// The contents of a deck file.
+#[derive(Asset, TypePath, Deserialize, Clone, Debug)]
+pub struct DeckConfig {
+    /// Number of tiles in the deck.
+    pub size: u32,
+    /// Relative frequency of each tile, by the file name of its model.
+    pub weights: BTreeMap<String, f32>,
+}
+
+#[derive(Resource, Asset, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct DeckAssets {
+    #[dependency]
+    pub config: Handle<DeckConfig>,
+}
+
+impl FromWorld for DeckAssets {
+    fn from_world(world: &mut World) -> Self {
+        let assets = world.resource::<AssetServer>();
+        Self {
+            config: assets.load("standard.deck.ron"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DeckLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for DeckLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeckLoaderError::Io(err) => write!(f, "{err}"),
+            DeckLoaderError::Ron(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for DeckLoaderError {}
+
+#[derive(Default)]
+struct DeckLoader;
+
+impl AssetLoader for DeckLoader {
+    type Asset = DeckConfig;
+    type Settings = ();
+    type Error = DeckLoaderError;
+
+    async fn load(&self, reader: &mut dyn Reader, _settings: &(), _load_context: &mut LoadContext<'_>) -> Result<DeckConfig, DeckLoaderError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.map_err(DeckLoaderError::Io)?;
+        ron::de::from_bytes(&bytes).map_err(DeckLoaderError::Ron)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["deck.ron"]
+    }
+}
+
+/// Marks the image showing the `n`th upcoming draw.
+#[derive(Component)]
+pub struct DeckPreview(pub usize);
+
+/// Marks the text showing how many tiles are left.
+#[derive(Component)]
+struct DeckLabel;
+
+/// The text showing how many tiles are left, to be placed in the HUD.
+pub fn deck_label() -> impl Bundle {
+    (
+        widget::label(""),
+        DeckLabel,
+        Pickable::IGNORE,
+    )
+}
+
+/// Only the rotation of the tile can be chosen freely when playing with a deck.
+fn sync_selected_tile(mode: Res<GameMode>, deck: Res<TileDeck>, mut mouse_pos: ResMut<MousePos>) {
+    if mode.free_choice() {return}
+    if let Some(tile) = deck.current() && mouse_pos.selected_tile.x != tile {
+        mouse_pos.selected_tile.x = tile;
+    }
+}
+
+fn draw_placed_tile(
+    _: Trigger<TilePlaced>,
+    mode: Res<GameMode>,
+    mut deck: ResMut<TileDeck>,
+    mut history: ResMut<EditHistory>,
+) {
+    if mode.free_choice() {return}
+    if let Some(tile) = deck.draw() {
+        history.record_draw(tile);
+    }
+}
+
+/// Throws away the current tile, for when it doesn't fit anywhere. Undoing this puts it back.
+fn discard_tile(
+    keys: Res<ButtonInput<KeyCode>>,
+    mode: Res<GameMode>,
+    mut deck: ResMut<TileDeck>,
+    mut history: ResMut<EditHistory>,
+) {
+    if mode.free_choice() || !keys.just_pressed(KeyCode::Backspace) {return}
+    if let Some(tile) = deck.draw() {
+        history.discard(tile);
+    }
+}
+
+fn update_preview(
+    mode: Res<GameMode>,
+    deck: Res<TileDeck>,
+    mut previews: Query<(&DeckPreview, &mut ImageNode, &mut Node)>,
+) {
+    for (preview, mut image, mut node) in previews.iter_mut() {
+        let tile = deck.upcoming().nth(preview.0).filter(|_| !mode.free_choice());
+        node.display = if tile.is_some() {Display::Flex} else {Display::None};
+        if let (Some(tile), Some(atlas)) = (tile, image.texture_atlas.as_mut()) {
+            atlas.index = tile as usize;
+        }
+    }
+}
+
+fn update_deck_label(mode: Res<GameMode>, deck: Res<TileDeck>, mut label: Query<&mut Text, With<DeckLabel>>) {
+    for mut text in label.iter_mut() {
+        text.0 = match (*mode, deck.len()) {
+            (GameMode::Sandbox, _) => String::new(),
+            (GameMode::Deck, 0) => "Game over".to_string(),
+            (GameMode::Deck, 1) => "Last tile".to_string(),
+            (GameMode::Deck, count) => format!("{count} tiles left"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(weights: &[(&str, f32)]) -> DeckConfig {
+        DeckConfig {
+            size: 3000,
+            weights: weights.iter().map(|&(name, weight)| (name.to_string(), weight)).collect(),
+        }
+    }
+
+    #[test]
+    fn random_tiles_follow_the_weights() {
+        let catalogue = TileCatalogue::standard();
+        let config = config(&[("grass", 2.0), ("water", 1.0), ("no-such-tile", 5.0)]);
+        let deck = TileDeck::new(&config, &catalogue, &mut *WorldRng::new(WorldSeed(4)));
+        assert_eq!(deck.len(), 3000);
+
+        let count = |id| deck.tiles().iter().filter(|&&tile| Some(tile) == catalogue.find(id)).count();
+        let (grass, water) = (count("grass"), count("water"));
+        // Unknown tiles are left out, rather than taking their share of the deck.
+        assert_eq!(grass + water, 3000);
+        assert!((1850..2150).contains(&grass), "{grass} grass, {water} water");
+
+        let mut deck = deck;
+        deck.add_random(&config, &catalogue, 10, &mut *WorldRng::new(WorldSeed(5)));
+        assert_eq!(deck.len(), 3010);
+    }
+
+    #[test]
+    fn draws_come_off_the_top() {
+        let mut deck = TileDeck::from_tiles(vec![1, 2, 3]);
+        assert_eq!(deck.current(), Some(3));
+        assert_eq!(deck.upcoming().collect::<Vec<_>>(), [2, 1]);
+        assert_eq!(deck.draw(), Some(3));
+        assert_eq!(deck.draw(), Some(2));
+        deck.put_back(2);
+        assert_eq!(deck.current(), Some(2));
+        assert_eq!(deck.draw(), Some(2));
+        assert_eq!(deck.draw(), Some(1));
+        assert_eq!(deck.draw(), None);
+        assert!(deck.is_empty());
+    }
+}
@@ -0,0 +1,301 @@
+//! Cube coordinates on the hexagonal grid, and conversions to world positions and map texels.
+//!
+//! A cell is stored by its `x` and `y` cube coordinates, which are also its axial coordinates
+//! and its texel in the map texture. The third coordinate follows from `x + y + z = 0`.
+//! Hexagons have a corner pointing along the world's Z axis, and direction 0 points along +X.
+//! The same conventions are used by `shaders/hex.wgsl`.
+
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+use bevy::prelude::*;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Reflect)]
+pub struct Hex {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// Distance from the center of a hexagon to its corners. Neighbours are one unit apart.
+const R: f32 = 0.57735027; // 1.0 / f32::sqrt(3.0);
+/// Maps a world position on the ground plane to fractional cube coordinates.
+const POSITION_TO_CUBE: Mat3 = Mat3::from_cols(
+    vec3( 1.0,  0.0,-1.0),
+    vec3( 0.0,  0.0, 0.0),
+    vec3(- R ,2.0*R, -R ),
+);
+
+impl Hex {
+    pub const ZERO: Hex = Hex::new(0, 0);
+
+    /// Neighbour offsets, indexed by the side of a tile facing them.
+    pub const DIRECTIONS: [Hex; 6] = [
+        Hex::new( 1, 0),
+        Hex::new( 1,-1),
+        Hex::new( 0,-1),
+        Hex::new(-1, 0),
+        Hex::new(-1, 1),
+        Hex::new( 0, 1),
+    ];
+
+    pub const fn new(x: i32, y: i32) -> Self {
+        Self {x, y}
+    }
+
+    pub const fn z(self) -> i32 {
+        -self.x - self.y
+    }
+
+    pub fn from_cube(cube: IVec3) -> Self {
+        debug_assert_eq!(cube.element_sum(), 0, "cube coordinates must sum to zero");
+        Self::new(cube.x, cube.y)
+    }
+
+    pub fn cube(self) -> IVec3 {
+        ivec3(self.x, self.y, self.z())
+    }
+
+    pub fn from_axial(axial: IVec2) -> Self {
+        Self::new(axial.x, axial.y)
+    }
+
+    pub fn axial(self) -> IVec2 {
+        ivec2(self.x, self.y)
+    }
+
+    /// Converts from "odd-r" offset coordinates, where every odd row is shifted right by half a cell.
+    pub fn from_offset(offset: IVec2) -> Self {
+        Self::new(offset.x - (offset.y - (offset.y & 1)) / 2, offset.y)
+    }
+
+    /// Converts to "odd-r" offset coordinates, see [`Hex::from_offset`].
+    pub fn offset(self) -> IVec2 {
+        ivec2(self.x + (self.y - (self.y & 1)) / 2, self.y)
+    }
+
+    /// The texel storing this cell in a map of `size` by `size` cells that wraps around in both directions.
+    pub fn texel(self, size: u32) -> UVec2 {
+        self.axial().rem_euclid(IVec2::splat(size as i32)).as_uvec2()
+    }
+
+    /// Row-major index of [`Hex::texel`], for storing cells in a `Vec`.
+    pub fn index(self, size: u32) -> usize {
+        let texel = self.texel(size);
+        (texel.y * size + texel.x) as usize
+    }
+
+    /// The cell stored at a texel, for iterating over a map.
+    pub fn from_index(index: usize, size: u32) -> Self {
+        Self::new((index % size as usize) as i32, (index / size as usize) as i32)
+    }
+
+    /// Rounds fractional cube coordinates to the cell containing them.
+    pub fn round(cube: Vec3) -> Self {
+        let mut res = cube.round();
+        let diff = (cube - res).abs();
+        if diff.x > diff.y && diff.x > diff.z {
+            res.x = -res.y - res.z;
+        } else if diff.y > diff.z {
+            res.y = -res.x - res.z;
+        }
+        Self::new(res.x as i32, res.y as i32)
+    }
+
+    /// The cell containing a world position, ignoring its height.
+    pub fn from_world(position: Vec3) -> Self {
+        Self::round(POSITION_TO_CUBE * position)
+    }
+
+    /// The center of the cell in world coordinates, at height zero.
+    pub fn to_world(self) -> Vec3 {
+        let cube = self.cube().as_vec3();
+        vec3(0.5 * (cube.x - cube.z), 0.0, cube.y / (2.0 * R))
+    }
+
+    pub fn neighbour(self, side: usize) -> Self {
+        self + Self::DIRECTIONS[side % 6]
+    }
+
+    pub fn neighbours(self) -> [Hex; 6] {
+        Self::DIRECTIONS.map(|offset| self + offset)
+    }
+
+    /// Number of steps from the origin.
+    pub fn length(self) -> u32 {
+        self.cube().abs().max_element() as u32
+    }
+
+    /// Number of steps between two cells, ignoring the wrapping of the map.
+    pub fn distance(self, other: Hex) -> u32 {
+        (self - other).length()
+    }
+
+    /// Number of steps between two cells, taking the shortest way around a map of `size` by `size` cells.
+    pub fn wrapped_distance(self, other: Hex, size: u32) -> u32 {
+        let offset = (other - self).texel(size).as_ivec2();
+        let size = size as i32;
+        [ivec2(0, 0), ivec2(size, 0), ivec2(0, size), ivec2(size, size)].into_iter()
+            .map(|shift| Hex::from_axial(offset - shift).length())
+            .min()
+            .unwrap()
+    }
+
+    /// Rotates around the origin by `turns` sixths of a turn, in the order of [`Hex::DIRECTIONS`].
+    pub fn rotate(self, turns: i32) -> Self {
+        let mut cube = self.cube();
+        for _ in 0..turns.rem_euclid(6) {
+            cube = ivec3(-cube.z, -cube.x, -cube.y);
+        }
+        Self::from_cube(cube)
+    }
+
+    /// The cells at exactly `radius` steps, starting in direction 4 and going around in direction order.
+    pub fn ring(self, radius: u32) -> impl Iterator<Item = Hex> {
+        let start = self + Self::DIRECTIONS[4] * radius as i32;
+        let steps = if radius == 0 {1} else {6 * radius};
+        (0..steps).scan(start, move |hex, step| {
+            let res = *hex;
+            if let Some(side) = step.checked_div(radius) {
+                *hex = hex.neighbour(side as usize);
+            }
+            Some(res)
+        })
+    }
+
+    /// The cells within `radius` steps, from the center outward ring by ring.
+    pub fn spiral(self, radius: u32) -> impl Iterator<Item = Hex> {
+        (0..=radius).flat_map(move |ring| self.ring(ring))
+    }
+
+    /// The cells on a straight line between two cells, including both ends.
+    pub fn line_to(self, other: Hex) -> impl Iterator<Item = Hex> {
+        let steps = self.distance(other);
+        // Nudge the line, so that it doesn't run exactly along the edge between two cells.
+        let from = self.cube().as_vec3() + vec3(1e-6, 2e-6, -3e-6);
+        let to = other.cube().as_vec3() + vec3(1e-6, 2e-6, -3e-6);
+        (0..=steps).map(move |step| {
+            let t = if steps == 0 {0.0} else {step as f32 / steps as f32};
+            Hex::round(from.lerp(to, t))
+        })
+    }
+}
+
+impl Add for Hex {
+    type Output = Hex;
+    fn add(self, other: Hex) -> Hex {
+        Hex::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl AddAssign for Hex {
+    fn add_assign(&mut self, other: Hex) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Hex {
+    type Output = Hex;
+    fn sub(self, other: Hex) -> Hex {
+        Hex::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl SubAssign for Hex {
+    fn sub_assign(&mut self, other: Hex) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Hex {
+    type Output = Hex;
+    fn neg(self) -> Hex {
+        Hex::new(-self.x, -self.y)
+    }
+}
+
+impl Mul<i32> for Hex {
+    type Output = Hex;
+    fn mul(self, factor: i32) -> Hex {
+        Hex::new(self.x * factor, self.y * factor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::{super::seed::{WorldRng, WorldSeed}, *};
+
+    /// Random cells, some of them far from the origin.
+    fn random_hexes(seed: u64) -> impl Iterator<Item = Hex> {
+        let mut rng = WorldRng::new(WorldSeed(seed));
+        (0..1000).map(move |_| Hex::new(rng.gen_range(-1000..=1000), rng.gen_range(-1000..=1000)))
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        for hex in random_hexes(1) {
+            assert_eq!(Hex::from_cube(hex.cube()), hex);
+            assert_eq!(Hex::from_axial(hex.axial()), hex);
+            assert_eq!(Hex::from_offset(hex.offset()), hex);
+            assert_eq!(Hex::from_world(hex.to_world()), hex);
+            for size in [8, 64, 2048] {
+                let texel = hex.texel(size);
+                assert!(texel.cmplt(UVec2::splat(size)).all());
+                assert_eq!(Hex::from_index(hex.index(size), size).texel(size), texel);
+                assert_eq!(Hex::from_axial(texel.as_ivec2()).index(size), hex.index(size));
+            }
+        }
+    }
+
+    #[test]
+    fn six_turns_make_a_full_turn() {
+        for hex in random_hexes(2) {
+            assert_eq!(hex.rotate(6), hex);
+            assert_eq!((0..6).fold(hex, |hex, _| hex.rotate(1)), hex);
+            assert_eq!(hex.rotate(-1), hex.rotate(5));
+            assert_eq!(hex.rotate(1).length(), hex.length());
+        }
+        for (side, direction) in Hex::DIRECTIONS.into_iter().enumerate() {
+            assert_eq!(direction.rotate(1), Hex::DIRECTIONS[(side + 1) % 6]);
+        }
+    }
+
+    #[test]
+    fn distances_obey_the_triangle_inequality() {
+        let hexes: Vec<Hex> = random_hexes(3).collect();
+        for abc in hexes.chunks_exact(3) {
+            let [a, b, c] = [abc[0], abc[1], abc[2]];
+            assert_eq!(a.distance(b), b.distance(a));
+            assert!(a.distance(c) <= a.distance(b) + b.distance(c), "{a:?} {b:?} {c:?}");
+            assert_eq!(a.distance(a), 0);
+        }
+    }
+
+    #[test]
+    fn rings_have_six_cells_per_step() {
+        for center in random_hexes(4).take(20) {
+            for radius in 0..12 {
+                let ring: Vec<Hex> = center.ring(radius).collect();
+                assert_eq!(ring.len() as u32, if radius == 0 {1} else {6 * radius});
+                assert!(ring.iter().all(|hex| hex.distance(center) == radius));
+                let mut unique = ring.clone();
+                unique.sort();
+                unique.dedup();
+                assert_eq!(unique.len(), ring.len());
+            }
+            assert_eq!(center.spiral(5).count(), 1 + 3 * 5 * 6);
+        }
+    }
+
+    #[test]
+    fn lines_step_between_neighbours() {
+        let hexes: Vec<Hex> = random_hexes(5).map(|hex| Hex::new(hex.x / 10, hex.y / 10)).collect();
+        for ends in hexes.chunks_exact(2) {
+            let (from, to) = (ends[0], ends[1]);
+            let line: Vec<Hex> = from.line_to(to).collect();
+            assert_eq!(line.len() as u32, from.distance(to) + 1);
+            assert_eq!((line[0], *line.last().unwrap()), (from, to));
+            assert!(line.windows(2).all(|step| step[0].distance(step[1]) == 1), "{from:?} to {to:?}");
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! The map as the game sees it, on the CPU.
+//!
+//! [`HexMap`] holds every cell of the map and is what gameplay code reads and edits. Edits are
+//! copied to the map texture at the end of the frame, which the renderer and the simulation
+//! kernel work from. [`MapShape`] decides whether the map wraps around, is surrounded by ocean,
+//! or is a window onto an infinite map.
+
+use std::collections::HashSet;
+
+use bevy::{
+    prelude::*,
+    render::extract_resource::{ExtractResource, ExtractResourcePlugin},
+};
+use rand::Rng;
+
+use super::{
+    prelude::*,
+    region::{Feature, RegionId, RegionInfo, Regions},
+    simulation::CHUNK_SIZE,
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<MapShape>();
+    app.init_resource::<MapShape>();
+    app.init_resource::<MapChanges>();
+    app.add_plugins(ExtractResourcePlugin::<MapChanges>::default());
+    app.add_systems(PostUpdate, collect_changes);
+    app.add_systems(Update, relabel_map.run_if(resource_exists_and_changed::<TileCatalogue>));
+}
+
+/// The contents of a single map cell, as stored in one texel of the map texture.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+pub struct Cell {
+    /// Index into the tile models (red channel).
+    pub tile: u8,
+    /// Rotation in sixths of a turn (green channel).
+    pub rotation: u8,
+    /// State of the cell's xorshift16 generator (blue and alpha channels).
+    pub prng: u16,
+}
+
+impl Cell {
+    pub fn new(tile: UVec2, prng: u16) -> Self {
+        Self {
+            tile: tile.x as u8,
+            rotation: tile.y as u8,
+            prng,
+        }
+    }
+
+    /// The tile id and rotation, in the layout used by [`TileCatalogue::edges`].
+    pub fn tile(&self) -> UVec2 {
+        uvec2(self.tile as u32, self.rotation as u32)
+    }
+
+    pub fn to_texel(self) -> [u8; 4] {
+        let [hi, lo] = self.prng.to_be_bytes();
+        [self.tile, self.rotation, hi, lo]
+    }
+
+    pub fn from_texel(texel: [u8; 4]) -> Self {
+        Self {
+            tile: texel[0],
+            rotation: texel[1],
+            prng: u16::from_be_bytes([texel[2], texel[3]]),
+        }
+    }
+}
+
+/// The chunk containing a cell, in units of [`CHUNK_SIZE`] cells.
+pub fn chunk_of(hex: Hex) -> IVec2 {
+    hex.axial().div_euclid(IVec2::splat(CHUNK_SIZE as i32))
+}
+
+/// A random seed for a cell's xorshift16 generator, which must be non-zero.
+pub fn random_prng(rng: &mut impl Rng) -> u16 {
+    rng.gen_range(1..=u16::MAX)
+}
+
+/// What happens at the edges of the map.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Reflect)]
+pub enum MapBoundary {
+    /// Leaving the map on one side enters it again on the opposite side.
+    #[default]
+    Wrapping,
+    /// The map is surrounded by ocean that can't be built on.
+    Bounded,
+    /// The map goes on forever. Only a window of it is kept, which follows the camera,
+    /// see [`streaming`](super::streaming).
+    Infinite,
+}
+
+/// Map sizes that a new game can be started with.
+pub const MAP_SIZES: [u32; 4] = [256, 512, 1024, 2048];
+
+/// The size and boundary of a map. As a resource, the shape of the next map to be generated.
+#[derive(Resource, Reflect, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[reflect(Resource)]
+pub struct MapShape {
+    /// Width and height of the map in cells. Should be a multiple of twice [`CHUNK_SIZE`],
+    /// so that the edges of bounded maps run along the edges of chunks.
+    pub size: u32,
+    pub boundary: MapBoundary,
+    /// The cell with the lowest coordinates on a bounded or infinite map.
+    origin: IVec2,
+}
+
+impl Default for MapShape {
+    fn default() -> Self {
+        Self::new(1024, MapBoundary::Wrapping)
+    }
+}
+
+impl MapShape {
+    /// Bounded and infinite maps start out centered on the origin, so that new games start in the middle.
+    pub fn new(size: u32, boundary: MapBoundary) -> Self {
+        Self {
+            size,
+            boundary,
+            origin: IVec2::splat(-(size as i32 / 2)),
+        }
+    }
+
+    /// The same shape, with its lowest cell at `origin`.
+    pub fn with_origin(self, origin: IVec2) -> Self {
+        Self {origin, ..self}
+    }
+
+    pub fn wraps(self) -> bool {
+        self.boundary == MapBoundary::Wrapping
+    }
+
+    /// The cell with the lowest coordinates, see [`MapShape::contains`].
+    pub fn origin(self) -> IVec2 {
+        self.origin
+    }
+
+    /// Whether the cell is part of the map. Every cell is on a wrapping map.
+    pub fn contains(self, hex: Hex) -> bool {
+        let offset = hex.axial() - self.origin;
+        self.wraps() || (offset.cmpge(IVec2::ZERO).all() && offset.cmplt(IVec2::splat(self.size as i32)).all())
+    }
+
+    /// The cell stored at a texel, within the bounds of the map.
+    pub fn hex(self, index: usize) -> Hex {
+        let texel = Hex::from_index(index, self.size).axial();
+        Hex::from_axial((texel - self.origin).rem_euclid(IVec2::splat(self.size as i32)) + self.origin)
+    }
+
+    /// The storage index of the neighbour on `side` of the cell at `index`,
+    /// or `None` if the neighbour is beyond the edge of a bounded map.
+    pub fn neighbour(self, index: usize, side: usize) -> Option<usize> {
+        let neighbour = self.hex(index).neighbour(side);
+        self.contains(neighbour).then(|| neighbour.index(self.size))
+    }
+
+    /// Number of steps between two cells, going around the map if it wraps.
+    pub fn distance(self, a: Hex, b: Hex) -> u32 {
+        if self.wraps() {a.wrapped_distance(b, self.size)} else {a.distance(b)}
+    }
+}
+
+/// The authoritative state of the map, addressed by cube coordinates.
+///
+/// The map either wraps around in both texture directions, is surrounded by ocean,
+/// or is the window of an infinite map that follows the camera, see [`MapShape`]. Any changes made through [`HexMap::set`] are copied to the map texture
+/// at the end of the frame, and immediately reflected in the map's [`Regions`].
+#[derive(Resource, Clone)]
+pub struct HexMap {
+    shape: MapShape,
+    cells: Vec<Cell>,
+    changed: Vec<UVec2>,
+    regions: Regions,
+    /// What cells beyond the edges of a bounded map read as.
+    outside: Cell,
+    /// Chunks of an infinite map that changed since they were generated, see [`HexMap::move_window`].
+    edited: HashSet<IVec2>,
+}
+
+impl HexMap {
+    /// Creates a map filled with `tile`, giving every cell its own generator seed.
+    pub fn new(shape: MapShape, tile: UVec2, catalogue: &TileCatalogue, rng: &mut impl Rng) -> Self {
+        let cells = (0..shape.size * shape.size).map(|_| Cell::new(tile, random_prng(rng))).collect();
+        Self::from_cells(shape, cells, catalogue)
+    }
+
+    /// Creates a map from its cells in row-major texel order.
+    pub fn from_cells(shape: MapShape, cells: Vec<Cell>, catalogue: &TileCatalogue) -> Self {
+        assert_eq!(cells.len(), (shape.size * shape.size) as usize, "a map needs one cell per texel");
+        Self {
+            shape,
+            regions: Regions::new(shape, &cells, catalogue),
+            cells,
+            changed: Vec::new(),
+            outside: Cell::new(uvec2(catalogue.roles.ocean, 0), 1),
+            edited: HashSet::new(),
+        }
+    }
+
+    /// Creates a map from data in the layout returned by [`HexMap::texture_data`].
+    pub fn from_texture_data(shape: MapShape, data: &[u8], catalogue: &TileCatalogue) -> Option<Self> {
+        if data.len() != (shape.size * shape.size * 4) as usize {
+            return None;
+        }
+        let cells = data.chunks_exact(4).map(|texel| Cell::from_texel([texel[0], texel[1], texel[2], texel[3]])).collect();
+        Some(Self::from_cells(shape, cells, catalogue))
+    }
+
+    /// Width and height of the map in cells.
+    pub fn size(&self) -> u32 {
+        self.shape.size
+    }
+
+    pub fn shape(&self) -> MapShape {
+        self.shape
+    }
+
+    /// Whether the cell is part of the map, rather than beyond the edges of a bounded map.
+    pub fn contains(&self, hex: Hex) -> bool {
+        self.shape.contains(hex)
+    }
+
+    /// The texel that stores the given cell.
+    pub fn texel(&self, hex: Hex) -> UVec2 {
+        hex.texel(self.size())
+    }
+
+    /// The cell at `hex`. Cells beyond the edges of a bounded map are ocean.
+    pub fn get(&self, hex: Hex) -> Cell {
+        if !self.contains(hex) {
+            return self.outside;
+        }
+        self.cells[hex.index(self.size())]
+    }
+
+    /// Changes the cell at `hex`. Cells beyond the edges of a bounded map can't be changed.
+    pub fn set(&mut self, hex: Hex, cell: Cell) {
+        if !self.contains(hex) {return}
+        let index = hex.index(self.size());
+        let old = self.cells[index];
+        if old != cell {
+            self.cells[index] = cell;
+            self.changed.push(self.texel(hex));
+            self.edited.insert(chunk_of(hex));
+            // Regions only depend on the tiles, not on the cells' generators.
+            if old.tile() != cell.tile() {
+                self.regions.update(index, cell);
+            }
+        }
+    }
+
+    /// The region of `feature` that the cell belongs to, if the cell has that feature.
+    pub fn region(&self, hex: Hex, feature: Feature) -> Option<(RegionId, RegionInfo)> {
+        if !self.contains(hex) {
+            return None;
+        }
+        self.regions.get(feature, hex.index(self.size()))
+    }
+
+    /// Returns a bitmask of the sides where `tile` would not fit its neighbours if placed at `hex`.
+    pub fn mismatches(&self, catalogue: &TileCatalogue, hex: Hex, tile: UVec2) -> u32 {
+        let edges = catalogue.edges(tile);
+        let mut res = 0;
+        for (side, neighbour) in hex.neighbours().into_iter().enumerate() {
+            let neighbour = catalogue.edges(self.get(neighbour).tile());
+            if !edges.get(side).fits(neighbour.get(side + 3)) {
+                res |= 1 << side;
+            }
+        }
+        res
+    }
+
+    /// Moves the window of an infinite map, so that its lowest cell is at `origin`, which must lie on
+    /// the corner of a chunk. Chunks that leave the window are passed to `unload` if they were edited,
+    /// and the chunks that enter it are filled by `load`, both with their cells in row-major order.
+    pub fn move_window(
+        &mut self,
+        origin: IVec2,
+        mut load: impl FnMut(IVec2) -> Vec<Cell>,
+        mut unload: impl FnMut(IVec2, Vec<Cell>),
+    ) {
+        debug_assert_eq!(origin % CHUNK_SIZE as i32, IVec2::ZERO, "windows move by whole chunks");
+        let old = self.shape;
+        self.shape = old.with_origin(origin);
+        if old == self.shape {return}
+
+        let size = self.size();
+        let chunks = size / CHUNK_SIZE;
+        let mut replaced = Vec::new();
+        for slot in 0..chunks * chunks {
+            let first = (slot / chunks * CHUNK_SIZE * size + slot % chunks * CHUNK_SIZE) as usize;
+            let (before, after) = (chunk_of(old.hex(first)), chunk_of(self.shape.hex(first)));
+            if before == after {continue}
+            let indices = (0..CHUNK_SIZE * CHUNK_SIZE).map(|cell| first + (cell / CHUNK_SIZE * size + cell % CHUNK_SIZE) as usize);
+            if self.edited.remove(&before) {
+                unload(before, indices.clone().map(|index| self.cells[index]).collect());
+            }
+            for (index, cell) in indices.zip(load(after)) {
+                self.cells[index] = cell;
+                self.changed.push(UVec2::new(index as u32 % size, index as u32 / size));
+                replaced.push(index);
+            }
+        }
+        // The edges of the window moved too, so this isn't a matter of updating single cells.
+        self.regions.replace(self.shape, &self.cells, &replaced);
+    }
+
+    /// Takes over the cells that the compute kernel changed, from a map texture that was read back in the
+    /// layout of [`HexMap::texture_data`]. Texels in `edited` changed on the CPU after the texture was read,
+    /// so they keep their edits. The texture already holds the growth, so it isn't uploaded again.
+    /// Chunks where tiles grew are marked as edited, so that they are kept when they leave the window.
+    pub fn apply_growth(&mut self, texels: &[u8], edited: &HashSet<UVec2>) {
+        let size = self.size();
+        for (index, texel) in texels.chunks_exact(4).enumerate() {
+            let cell = Cell::from_texel([texel[0], texel[1], texel[2], texel[3]]);
+            let old = self.cells[index];
+            if old == cell || edited.contains(&UVec2::new(index as u32 % size, index as u32 / size)) {continue}
+            self.cells[index] = cell;
+            // Generators that merely rolled aren't worth keeping.
+            if old.tile() != cell.tile() {
+                self.regions.update(index, cell);
+                self.edited.insert(chunk_of(self.shape.hex(index)));
+            }
+        }
+    }
+
+    /// Labels the regions again from a changed catalogue, which may have added tiles or changed their edges.
+    pub fn relabel(&mut self, catalogue: &TileCatalogue) {
+        self.regions = Regions::new(self.shape, &self.cells, catalogue);
+        self.outside = Cell::new(uvec2(catalogue.roles.ocean, 0), 1);
+    }
+
+    /// Chunks in the window of an infinite map that changed since they were generated.
+    pub fn edited_chunks(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.edited.iter().copied()
+    }
+
+    /// Marks chunks of an infinite map as edited, so that they are kept when they leave the window.
+    pub fn mark_edited(&mut self, chunks: impl IntoIterator<Item = IVec2>) {
+        self.edited.extend(chunks);
+    }
+
+    /// The full map in the `Rgba8Uint` layout of the map texture.
+    pub fn texture_data(&self) -> Vec<u8> {
+        self.cells.iter().flat_map(|cell| cell.to_texel()).collect()
+    }
+
+    /// Takes the cells that changed since the last call, together with their new texel data.
+    fn take_changes(&mut self) -> Vec<(UVec2, [u8; 4])> {
+        let mut changed = std::mem::take(&mut self.changed);
+        changed.sort_unstable_by_key(|texel| (texel.y, texel.x));
+        changed.dedup();
+        changed.into_iter().map(|texel| {
+            (texel, self.cells[(texel.y * self.size() + texel.x) as usize].to_texel())
+        }).collect()
+    }
+}
+
+fn relabel_map(map: Option<ResMut<HexMap>>, catalogue: Res<TileCatalogue>) {
+    if let Some(mut map) = map {
+        map.relabel(&catalogue);
+    }
+}
+
+/// Texels that need to be written to the map texture this frame.
+#[derive(Resource, Default, Clone, ExtractResource)]
+pub struct MapChanges(pub Vec<(UVec2, [u8; 4])>);
+
+pub(super) fn collect_changes(map: Option<ResMut<HexMap>>, mut changes: ResMut<MapChanges>) {
+    let Some(mut map) = map else {return};
+    if map.is_added() {
+        // A new map is uploaded as a whole when its texture is created.
+        map.changed.clear();
+        return;
+    }
+    if map.changed.is_empty() {return}
+    // Only touch the resource when there is something new, as it is re-extracted whenever it changes.
+    changes.0 = map.take_changes();
+}
@@ -0,0 +1,340 @@
+//! Undo and redo for edits to the map.
+
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::PausableSystems;
+
+use super::{deck::TileDeck, hex_map::Cell, prelude::*, scoring::Score};
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<EditHistory>();
+    app.init_resource::<EditHistory>();
+    app.add_systems(PostUpdate, clear_history);
+    app.add_systems(Update, keyboard_input.in_set(PausableSystems));
+}
+
+/// A change to a single cell, remembering both its old and new contents.
+#[derive(Clone, Copy, Debug)]
+struct CellEdit {
+    hex: Hex,
+    before: Cell,
+    after: Cell,
+}
+
+/// What an undo step did besides changing cells.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StepOutcome {
+    /// The points that were earned.
+    pub points: u32,
+    /// The tile that was drawn from the deck.
+    pub drawn: Option<u32>,
+}
+
+#[derive(Debug)]
+struct Step {
+    edits: Vec<CellEdit>,
+    outcome: StepOutcome,
+}
+
+impl Step {
+    /// Whether every cell of the step still holds what the step left in it, or if `undone`,
+    /// what the step found in it. Otherwise something else changed the cell since.
+    fn matches(&self, map: &HexMap, undone: bool) -> bool {
+        // Only the first or last edit of a cell that the step changed more than once counts.
+        let mut seen = Vec::new();
+        let mut check = |edit: &CellEdit, expected: Cell| {
+            if seen.contains(&edit.hex) {return true}
+            seen.push(edit.hex);
+            map.get(edit.hex) == expected
+        };
+        if undone {
+            self.edits.iter().all(|edit| check(edit, edit.before))
+        } else {
+            self.edits.iter().rev().all(|edit| check(edit, edit.after))
+        }
+    }
+}
+
+/// The edits that were made to the map, so that they can be undone and redone.
+///
+/// All cells changed by one call to [`EditHistory::apply`] form a single undo step.
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct EditHistory {
+    /// Maximum number of steps that can be undone.
+    pub max_depth: usize,
+    #[reflect(ignore)]
+    undo: VecDeque<Step>,
+    #[reflect(ignore)]
+    redo: Vec<Step>,
+}
+
+impl Default for EditHistory {
+    fn default() -> Self {
+        Self {
+            max_depth: 100,
+            undo: VecDeque::new(),
+            redo: Vec::new(),
+        }
+    }
+}
+
+impl EditHistory {
+    /// Writes the given cells to the map as a single undo step.
+    /// Returns false if this didn't change anything, in which case no step is recorded.
+    pub fn apply(&mut self, map: &mut HexMap, cells: impl IntoIterator<Item = (Hex, Cell)>) -> bool {
+        let edits: Vec<CellEdit> = cells.into_iter().map(|(hex, after)| {
+            let before = map.get(hex);
+            map.set(hex, after);
+            CellEdit {hex, before, after}
+        }).collect();
+        if edits.iter().all(|edit| edit.before == edit.after) {return false}
+        self.push(Step {edits, outcome: StepOutcome::default()});
+        true
+    }
+
+    /// Records throwing away a tile from the deck as a step of its own, which changes no cells,
+    /// so that undoing it puts the tile back.
+    pub fn discard(&mut self, tile: u32) {
+        self.push(Step {edits: Vec::new(), outcome: StepOutcome {drawn: Some(tile), ..default()}});
+    }
+
+    fn push(&mut self, step: Step) {
+        self.redo.clear();
+        self.undo.push_back(step);
+        while self.undo.len() > self.max_depth {
+            self.undo.pop_front();
+        }
+    }
+
+    /// Attributes points to the most recent step, so that undoing it takes them back.
+    pub fn award(&mut self, points: u32) {
+        if let Some(step) = self.undo.back_mut() {
+            step.outcome.points += points;
+        }
+    }
+
+    /// Remembers that the most recent step used up a tile from the deck,
+    /// so that undoing it puts the tile back.
+    pub fn record_draw(&mut self, tile: u32) {
+        if let Some(step) = self.undo.back_mut() {
+            step.outcome.drawn = Some(tile);
+        }
+    }
+
+    /// Reverts the most recent step and returns what it did,
+    /// or `None` if there was nothing to undo.
+    ///
+    /// If the cells of the step changed since, for example because the simulation grew a forest
+    /// over them, reverting it would wipe out that change. The step and all before it are
+    /// forgotten instead, and `None` is returned.
+    pub fn undo(&mut self, map: &mut HexMap) -> Option<StepOutcome> {
+        let step = self.undo.pop_back()?;
+        if !step.matches(map, false) {
+            self.undo.clear();
+            return None;
+        }
+        // Restore in reverse, in case a step changed the same cell more than once.
+        for edit in step.edits.iter().rev() {
+            map.set(edit.hex, edit.before);
+        }
+        let outcome = step.outcome;
+        self.redo.push(step);
+        Some(outcome)
+    }
+
+    /// Reapplies the most recently undone step and returns what it did,
+    /// or `None` if there was nothing to redo.
+    ///
+    /// Like [`EditHistory::undo`], this forgets the step and all after it if its cells changed since.
+    pub fn redo(&mut self, map: &mut HexMap) -> Option<StepOutcome> {
+        let step = self.redo.pop()?;
+        if !step.matches(map, true) {
+            self.redo.clear();
+            return None;
+        }
+        for edit in &step.edits {
+            map.set(edit.hex, edit.after);
+        }
+        let outcome = step.outcome;
+        self.undo.push_back(step);
+        Some(outcome)
+    }
+
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+}
+
+/// Edits don't carry over to a new or loaded map.
+fn clear_history(map: Option<Res<HexMap>>, mut history: ResMut<EditHistory>) {
+    if map.is_some_and(|map| map.is_added()) {
+        history.clear();
+    }
+}
+
+fn keyboard_input(
+    keys: Res<ButtonInput<KeyCode>>,
+    map: Option<ResMut<HexMap>>,
+    mut history: ResMut<EditHistory>,
+    mut score: ResMut<Score>,
+    mut deck: ResMut<TileDeck>,
+) {
+    let Some(mut map) = map else {return};
+    let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight, KeyCode::SuperLeft, KeyCode::SuperRight]);
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if !ctrl || !keys.just_pressed(KeyCode::KeyZ) {return}
+    if shift {
+        let Some(outcome) = history.redo(&mut map) else {return};
+        score.total += outcome.points;
+        score.last = outcome.points;
+        if outcome.drawn.is_some() {
+            deck.draw();
+        }
+    } else {
+        let Some(outcome) = history.undo(&mut map) else {return};
+        score.total = score.total.saturating_sub(outcome.points);
+        score.last = 0;
+        if let Some(tile) = outcome.drawn {
+            deck.put_back(tile);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(tile: u32) -> Cell {
+        Cell::new(uvec2(tile, 0), 1)
+    }
+
+    fn setup() -> (HexMap, EditHistory) {
+        let catalogue = TileCatalogue::standard();
+        let map = HexMap::new(MapShape::new(8, MapBoundary::Wrapping), uvec2(0, 0), &catalogue, &mut *WorldRng::new(WorldSeed(1)));
+        (map, EditHistory::default())
+    }
+
+    #[test]
+    fn undo_and_redo_round_trip() {
+        let (mut map, mut history) = setup();
+        let original = map.texture_data();
+        let (a, b) = (Hex::new(1, 2), Hex::new(3, 4));
+        assert!(history.apply(&mut map, [(a, cell(5))]));
+        assert!(history.apply(&mut map, [(b, cell(6))]));
+        let edited = map.texture_data();
+
+        assert!(history.undo(&mut map).is_some());
+        assert_eq!(map.get(b).tile, 0);
+        assert_eq!(map.get(a).tile, 5);
+        assert!(history.undo(&mut map).is_some());
+        assert_eq!(map.texture_data(), original);
+        assert!(history.undo(&mut map).is_none());
+
+        assert!(history.redo(&mut map).is_some());
+        assert!(history.redo(&mut map).is_some());
+        assert!(history.redo(&mut map).is_none());
+        assert_eq!(map.texture_data(), edited);
+
+        // A new edit after undoing forgets what was undone.
+        history.undo(&mut map);
+        history.apply(&mut map, [(a, cell(7))]);
+        assert!(history.redo(&mut map).is_none());
+    }
+
+    #[test]
+    fn the_oldest_steps_are_dropped() {
+        let (mut map, mut history) = setup();
+        history.max_depth = 3;
+        for tile in 1..=5 {
+            history.apply(&mut map, [(Hex::new(tile as i32, 0), cell(tile))]);
+        }
+        let mut undone = 0;
+        while history.undo(&mut map).is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, 3);
+        assert_eq!(map.get(Hex::new(1, 0)).tile, 1);
+        assert_eq!(map.get(Hex::new(2, 0)).tile, 2);
+        assert_eq!(map.get(Hex::new(3, 0)).tile, 0);
+    }
+
+    #[test]
+    fn steps_are_undone_as_a_whole_in_reverse() {
+        let (mut map, mut history) = setup();
+        let original = map.texture_data();
+        let (a, b) = (Hex::new(1, 1), Hex::new(2, 1));
+        // The same cell changes twice, so restoring it in order would leave the intermediate tile.
+        assert!(history.apply(&mut map, [(a, cell(3)), (b, cell(4)), (a, cell(5))]));
+        assert_eq!((map.get(a).tile, map.get(b).tile), (5, 4));
+        history.undo(&mut map);
+        assert_eq!(map.texture_data(), original);
+        history.redo(&mut map);
+        assert_eq!((map.get(a).tile, map.get(b).tile), (5, 4));
+    }
+
+    #[test]
+    fn unchanged_cells_record_nothing() {
+        let (mut map, mut history) = setup();
+        let hex = Hex::new(2, 2);
+        assert!(!history.apply(&mut map, [(hex, map.get(hex))]));
+        assert!(!history.apply(&mut map, []));
+        assert!(history.undo(&mut map).is_none());
+    }
+
+    #[test]
+    fn cells_changed_since_are_left_alone() {
+        let (mut map, mut history) = setup();
+        let (a, b) = (Hex::new(1, 2), Hex::new(3, 4));
+        history.apply(&mut map, [(a, cell(5))]);
+        history.apply(&mut map, [(b, cell(6))]);
+        history.award(4);
+
+        // Something grew over the cell between undoing and redoing the step.
+        history.undo(&mut map);
+        map.set(b, cell(7));
+        assert!(history.redo(&mut map).is_none());
+        assert_eq!(map.get(b).tile, 7);
+        assert!(history.redo(&mut map).is_none());
+
+        // The same when undoing, which also forgets the steps before it.
+        history.apply(&mut map, [(b, cell(8)), (b, cell(9))]);
+        map.set(a, cell(10));
+        history.apply(&mut map, [(b, cell(6))]);
+        assert!(history.undo(&mut map).is_some());
+        assert_eq!(map.get(b).tile, 9);
+        map.set(b, cell(11));
+        assert!(history.undo(&mut map).is_none());
+        assert_eq!((map.get(a).tile, map.get(b).tile), (10, 11));
+        assert!(history.undo(&mut map).is_none());
+    }
+
+    #[test]
+    fn outcomes_come_back() {
+        let (mut map, mut history) = setup();
+        // Nothing to attribute them to yet.
+        history.award(100);
+        history.record_draw(9);
+
+        history.apply(&mut map, [(Hex::new(1, 1), cell(3))]);
+        history.award(2);
+        history.award(3);
+        history.record_draw(3);
+        history.discard(8);
+
+        let discarded = history.undo(&mut map).unwrap();
+        assert_eq!((discarded.points, discarded.drawn), (0, Some(8)));
+        assert_eq!(map.get(Hex::new(1, 1)).tile, 3);
+        let placed = history.undo(&mut map).unwrap();
+        assert_eq!((placed.points, placed.drawn), (5, Some(3)));
+        assert!(history.undo(&mut map).is_none());
+
+        let placed = history.redo(&mut map).unwrap();
+        assert_eq!((placed.points, placed.drawn), (5, Some(3)));
+        let discarded = history.redo(&mut map).unwrap();
+        assert_eq!((discarded.points, discarded.drawn), (0, Some(8)));
+    }
+}
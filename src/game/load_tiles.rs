@@ -1,95 +1,500 @@
+//! The catalogue of tiles that maps are made of, read from `assets/standard.tiles.ron`.
+//!
+//! A tile's id is its index in the catalogue, which is what the map stores in each cell.
+//! Once loaded, the catalogue is a resource, which systems pass on to the map code that needs it.
+//! The tiles that the game itself relies on, such as the ocean or what forests grow into, are
+//! found by their tags, see [`TileRoles`].
+//! Tiles from the packs in the `mods` folder, see [`super::tile_packs`], are appended after the
+//! game's own tiles. In `dev_native` builds, editing any of the files reloads the catalogue
+//! while the game runs.
+
+use std::{collections::HashMap, fmt};
+
 use bevy::{
+    asset::{io::{AssetSourceId, Reader}, AssetLoader, AssetPath, LoadContext, LoadState, ParseAssetPathError},
     render::view::RenderLayers,
     prelude::*,
 };
+use serde::Deserialize;
 
-use super::prelude::*;
+use crate::asset_tracking::LoadResource;
 
-use crate::screens::Screen;
+use super::{prelude::*, tile_packs::TilePacks};
 
 pub(super) fn plugin(app: &mut App) {
-    app.add_systems(OnEnter(Screen::Gameplay), setup);
-}
-
-struct Model {
-    path: &'static str,
-    #[allow(unused)]
-    edges: &'static str,
-}
-
-const fn model(path: &'static str, edges: &'static str) -> Model {
-    Model{path, edges}
-}
-
-pub const TILE_COUNT: u32 = MODELS.len() as u32;
-const MODELS: &[Model] = &[
-    model("models/bridge-path-a.glb",           "......"),
-    model("models/bridge-path-b.glb",           "......"),
-    model("models/building-archery.glb",        "......"),
-    model("models/building-cabin.glb",          "......"),
-    model("models/building-castle-path.glb",    "......"),
-    model("models/building-farm.glb",           "......"),
-    model("models/building-house.glb",          "......"),
-    model("models/building-market.glb",         "......"),
-    model("models/building-mill.glb",           "......"),
-    model("models/building-mine.glb",           "......"),
-    model("models/building-port.glb",           "......"),
-    model("models/building-sheep.glb",          "......"),
-    model("models/building-smelter.glb",        "......"),
-    model("models/building-tower.glb",          "......"),
-    model("models/building-village.glb",        "......"),
-    model("models/building-watermill.glb",      "......"),
-    model("models/building-wizard-tower.glb",   "......"),
-    model("models/grass-forest.glb",            "......"),
-    model("models/grass.glb",                   "......"),
-    model("models/grass-hill.glb",              "......"),
-    model("models/grass-lumber.glb",            "......"),
-    model("models/grass-path-corner.glb",       "......"),
-    model("models/grass-path-intersection.glb", "......"),
-    model("models/grass-path-left.glb",         "......"),
-    model("models/grass-path-right.glb",        "......"),
-    model("models/grass-path-start.glb",        "......"),
-    model("models/grass-path-straight.glb",     "......"),
-    model("models/grass-rocks.glb",             "......"),
-    model("models/river-corner.glb",            "......"),
-    model("models/river-intersection.glb",      "......"),
-    model("models/river-left.glb",              "......"),
-    model("models/river-right.glb",             "......"),
-    model("models/river-start.glb",             "......"),
-    model("models/river-straight.glb",          "......"),
-    model("models/stone-hill.glb",              "......"),
-    model("models/stone-mountain.glb",          "......"),
-    model("models/water-boat.glb",              "......"),
-    model("models/water-corner-in.glb",         "......"),
-    model("models/water-corner-out.glb",        "......"),
-    model("models/water.glb",                   "......"),
-    model("models/water-island.glb",            "......"),
-    model("models/water-river.glb",             "......"),
-    model("models/water-rocks.glb",             "......"),
-    model("models/water-straight.glb",          "......"),
-];
-
-fn setup(
+    app.init_asset::<TileCatalogue>();
+    app.init_asset_loader::<TileCatalogueLoader>();
+    app.register_type::<TileAssets>();
+    app.load_resource::<TileAssets>();
+    app.add_systems(PreUpdate, install_catalogue.run_if(not(resource_exists::<TileCatalogue>)));
+    #[cfg(feature = "dev_native")]
+    app.add_systems(PreUpdate, reload_catalogue);
+    app.add_systems(Update, spawn_tiles.run_if(resource_exists_and_changed::<TileCatalogue>));
+}
+
+/// Tile ids are stored in a byte of the map texture.
+const MAX_TILES: usize = 256;
+/// Save files store the length of each tile id in a byte.
+const MAX_ID_LEN: usize = u8::MAX as usize;
+
+/// The colour map of tiles whose catalogue doesn't name one.
+const DEFAULT_COLORMAP: &str = "images/colormap.png";
+
+/// The kind of terrain that crosses one of the six sides of a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Edge {
+    /// Plain land (`g`).
+    Grass,
+    /// A dirt path running through the middle of the side (`p`).
+    Path,
+    /// A river running through the middle of the side (`r`).
+    River,
+    /// Open water (`w`).
+    Water,
+    /// A coastline with the water toward the next side (`c`).
+    CoastNext,
+    /// A coastline with the water toward the previous side (`C`).
+    CoastPrev,
+    /// Rocky land (`s`).
+    Stone,
+}
+
+impl Edge {
+    pub const ALL: [Edge; 7] = [
+        Edge::Grass,
+        Edge::Path,
+        Edge::River,
+        Edge::Water,
+        Edge::CoastNext,
+        Edge::CoastPrev,
+        Edge::Stone,
+    ];
+
+    fn parse(c: char) -> Option<Edge> {
+        Some(match c {
+            'g' => Edge::Grass,
+            'p' => Edge::Path,
+            'r' => Edge::River,
+            'w' => Edge::Water,
+            'c' => Edge::CoastNext,
+            'C' => Edge::CoastPrev,
+            's' => Edge::Stone,
+            _ => return None,
+        })
+    }
+
+    /// Whether this side can lie against the given side of a neighbouring tile.
+    /// Coastlines are mirrored when seen from the other side of an edge.
+    pub fn fits(self, other: Edge) -> bool {
+        use Edge::*;
+        matches!((self, other),
+            (Grass | Stone, Grass | Stone) |
+            (Path, Path) |
+            (River, River) |
+            (Water, Water) |
+            (CoastNext, CoastPrev) |
+            (CoastPrev, CoastNext)
+        )
+    }
+}
+
+/// The sockets on the six sides of a tile.
+///
+/// Side `k` faces the neighbour in direction `k`, where directions are counted
+/// in the same sense as the rotation applied by [`Tile::rotated`], starting at +X.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct EdgeSet(pub [Edge; 6]);
+
+impl TryFrom<String> for EdgeSet {
+    type Error = String;
+
+    /// Parses a six character socket string, one character per side.
+    fn try_from(edges: String) -> Result<EdgeSet, String> {
+        let mut res = [Edge::Grass; 6];
+        let mut chars = edges.chars();
+        for edge in &mut res {
+            let c = chars.next().ok_or_else(|| format!("{edges:?} has fewer than six sockets"))?;
+            *edge = Edge::parse(c).ok_or_else(|| format!("unknown edge socket {c:?} in {edges:?}"))?;
+        }
+        if chars.next().is_some() {
+            return Err(format!("{edges:?} has more than six sockets"));
+        }
+        Ok(EdgeSet(res))
+    }
+}
+
+impl EdgeSet {
+    /// The edges after rotating the tile by `rotation` sixths of a turn.
+    pub fn rotated(self, rotation: u32) -> EdgeSet {
+        let mut res = self.0;
+        res.rotate_right((rotation % 6) as usize);
+        EdgeSet(res)
+    }
+
+    /// The socket facing `direction`.
+    pub fn get(&self, direction: usize) -> Edge {
+        self.0[direction % 6]
+    }
+}
+
+/// Broad groups of tiles, shown along with their names.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
+pub enum TileCategory {
+    Grass,
+    Stone,
+    Water,
+    River,
+    Path,
+    Building,
+}
+
+impl TileCategory {
+    pub fn label(self) -> &'static str {
+        match self {
+            TileCategory::Grass => "Grass",
+            TileCategory::Stone => "Stone",
+            TileCategory::Water => "Water",
+            TileCategory::River => "River",
+            TileCategory::Path => "Path",
+            TileCategory::Building => "Building",
+        }
+    }
+}
+
+/// One entry of the catalogue.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TileInfo {
+    /// Path of the model, relative to the catalogue file. Once loaded it is a full asset path.
+    pub path: String,
+    pub edges: EdgeSet,
+    pub category: TileCategory,
+    /// How often map generation picks this tile, relative to the others.
+    pub weight: f32,
+    /// The name shown to the player.
+    pub name: String,
+    /// Labels that give tiles a role in the game, such as `"settlement"`, see [`TileRoles`].
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Filled in on load, see [`TileInfo::id`].
+    #[serde(skip)]
+    id: String,
+    /// The texture of the model, filled in on load from [`TileCatalogue::colormap`].
+    #[serde(skip)]
+    pub colormap: Option<String>,
+}
+
+impl TileInfo {
+    /// The file name of the model without its extension, which [`TileCatalogue::find`] looks tiles up by.
+    /// Tiles from packs have the name of the pack in front, as in `"castles/keep"`.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// The tiles that the game relies on, by tile id. Each is the first tile in the catalogue
+/// with the tag of the same name, and the game's own catalogue fails to load without one.
+///
+/// Other tiles with these tags take part in the game the same way, for example every tile
+/// tagged `"forest"` belongs to a forest, but only the first one is grown or generated.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TileRoles {
+    /// Open sea, which also surrounds bounded maps (`"ocean"`).
+    pub ocean: u32,
+    /// Plain land that forests and villages grow into (`"grassland"`).
+    pub grassland: u32,
+    pub forest: u32,
+    /// What villages grow by (`"house"`).
+    pub house: u32,
+    pub rocks: u32,
+    /// Erodes into grassland (`"hill"`).
+    pub hill: u32,
+    /// Erodes into a hill (`"rocky-hill"`).
+    pub rocky_hill: u32,
+    pub mountain: u32,
+    /// Every tile tagged `"coast"`, which map generation picks from for shorelines.
+    pub coast: Vec<u32>,
+    /// Every tile tagged `"river"`, which map generation picks from for rivers.
+    pub river: Vec<u32>,
+}
+
+impl TileRoles {
+    /// Finds the tiles for each role, or returns the tag that no tile has.
+    fn find(tiles: &[TileInfo]) -> Result<Self, &'static str> {
+        let all = |tag: &'static str| {
+            let ids: Vec<u32> = (0..tiles.len() as u32).filter(|&id| tiles[id as usize].has_tag(tag)).collect();
+            if ids.is_empty() {Err(tag)} else {Ok(ids)}
+        };
+        let first = |tag| all(tag).map(|ids| ids[0]);
+        Ok(Self {
+            ocean: first("ocean")?,
+            grassland: first("grassland")?,
+            forest: first("forest")?,
+            house: first("house")?,
+            rocks: first("rocks")?,
+            hill: first("hill")?,
+            rocky_hill: first("rocky-hill")?,
+            mountain: first("mountain")?,
+            coast: all("coast")?,
+            river: all("river")?,
+        })
+    }
+}
+
+/// The contents of a `.tiles.ron` file.
+///
+/// Once loaded it is also inserted as a resource, so systems can react when it changes.
+#[derive(Asset, Resource, TypePath, Deserialize, Clone, Debug)]
+pub struct TileCatalogue {
+    /// The texture shared by the models, relative to the catalogue file.
+    #[serde(default)]
+    pub colormap: Option<String>,
+    pub tiles: Vec<TileInfo>,
+    /// Filled in on load for the game's own catalogue. Packs don't need to have any of these.
+    #[serde(skip)]
+    pub roles: TileRoles,
+}
+
+impl TileCatalogue {
+    /// The game's own catalogue, without any packs, read outside of the asset server for tests and benchmarks.
+    pub fn standard() -> Self {
+        let mut catalogue: TileCatalogue = ron::de::from_str(include_str!("../../assets/standard.tiles.ron")).unwrap();
+        catalogue.resolve(&AssetPath::parse("standard.tiles.ron")).unwrap();
+        catalogue.roles = TileRoles::find(&catalogue.tiles).unwrap();
+        catalogue
+    }
+
+    pub fn len(&self) -> u32 {
+        self.tiles.len() as u32
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// The edges of a tile as placed on the map, with the tile id in `x`
+    /// and the rotation (the map's green channel) in `y`.
+    pub fn edges(&self, tile: UVec2) -> EdgeSet {
+        self.tiles[tile.x as usize].edges.rotated(tile.y)
+    }
+
+    /// Looks up a tile by its [`TileInfo::id`], e.g. `"water"`, or `"castles/keep"` for
+    /// a tile from the `castles` pack.
+    pub fn find(&self, id: &str) -> Option<u32> {
+        self.tiles.iter()
+            .position(|tile| tile.id() == id)
+            .map(|index| index as u32)
+    }
+
+    /// Turns the paths in a freshly loaded catalogue into asset paths, and gives every tile its id.
+    fn resolve(&mut self, file: &AssetPath) -> Result<(), TileLoaderError> {
+        // Tiles from outside the game's own assets are prefixed with the folder of their
+        // catalogue, so that packs can't take each other's ids.
+        let prefix = match file.source() {
+            AssetSourceId::Default => String::new(),
+            AssetSourceId::Name(_) => file.path().parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .map_or(String::new(), |dir| format!("{}/", dir.display())),
+        };
+        let colormap = match &self.colormap {
+            Some(colormap) => Some(file.resolve_embed(colormap).map_err(TileLoaderError::Path)?.to_string()),
+            None => None,
+        };
+        for tile in &mut self.tiles {
+            let path = file.resolve_embed(&tile.path).map_err(TileLoaderError::Path)?;
+            let stem = path.path().file_stem().unwrap_or_default().to_string_lossy();
+            tile.id = format!("{prefix}{stem}");
+            if tile.id.len() > MAX_ID_LEN {
+                return Err(TileLoaderError::IdTooLong(tile.id.clone()));
+            }
+            tile.path = path.to_string();
+            tile.colormap.clone_from(&colormap);
+        }
+        self.colormap = colormap;
+        Ok(())
+    }
+
+    /// Appends the tiles of a pack, skipping those whose id is taken.
+    /// The tiles that are already there keep their ids, and their roles.
+    fn merge(&mut self, pack: &TileCatalogue) {
+        for tile in &pack.tiles {
+            if self.tiles.iter().any(|other| other.id == tile.id) {
+                warn!("Skipping tile {:?}, as there already is a tile with that name", tile.id);
+            } else if self.tiles.len() >= MAX_TILES {
+                warn!("Skipping tile {:?}, as there are already {MAX_TILES} tiles", tile.id);
+            } else {
+                self.tiles.push(tile.clone());
+            }
+        }
+        // The pack's tiles may also be coasts or rivers. Every other role stays with the tile it had.
+        if let Ok(roles) = TileRoles::find(&self.tiles) {
+            self.roles = roles;
+        }
+    }
+}
+
+#[derive(Resource, Asset, Clone, Reflect)]
+#[reflect(Resource)]
+pub struct TileAssets {
+    #[dependency]
+    pub catalogue: Handle<TileCatalogue>,
+}
+
+impl FromWorld for TileAssets {
+    fn from_world(world: &mut World) -> Self {
+        let assets = world.resource::<AssetServer>();
+        Self {
+            catalogue: assets.load("standard.tiles.ron"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TileLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Path(ParseAssetPathError),
+    TooManyTiles(usize),
+    /// A tile's id, made from its pack and file name, doesn't fit in a save file.
+    IdTooLong(String),
+    /// No tile has the tag of one of the [`TileRoles`].
+    MissingRole(&'static str),
+}
+
+impl fmt::Display for TileLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileLoaderError::Io(err) => write!(f, "{err}"),
+            TileLoaderError::Ron(err) => write!(f, "{err}"),
+            TileLoaderError::Path(err) => write!(f, "{err}"),
+            TileLoaderError::TooManyTiles(count) => write!(f, "{count} tiles is more than the limit of {MAX_TILES}"),
+            TileLoaderError::IdTooLong(id) => write!(f, "tile id {id:?} is longer than {MAX_ID_LEN} bytes"),
+            TileLoaderError::MissingRole(tag) => write!(f, "no tile has the tag {tag:?}, which the game needs"),
+        }
+    }
+}
+
+impl std::error::Error for TileLoaderError {}
+
+#[derive(Default)]
+struct TileCatalogueLoader;
+
+impl AssetLoader for TileCatalogueLoader {
+    type Asset = TileCatalogue;
+    type Settings = ();
+    type Error = TileLoaderError;
+
+    async fn load(&self, reader: &mut dyn Reader, _settings: &(), load_context: &mut LoadContext<'_>) -> Result<TileCatalogue, TileLoaderError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await.map_err(TileLoaderError::Io)?;
+        let mut catalogue: TileCatalogue = ron::de::from_bytes(&bytes).map_err(TileLoaderError::Ron)?;
+        if catalogue.tiles.len() > MAX_TILES {
+            return Err(TileLoaderError::TooManyTiles(catalogue.tiles.len()));
+        }
+        catalogue.resolve(load_context.asset_path())?;
+        if *load_context.asset_path().source() == AssetSourceId::Default {
+            catalogue.roles = TileRoles::find(&catalogue.tiles).map_err(TileLoaderError::MissingRole)?;
+        }
+        Ok(catalogue)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tiles.ron"]
+    }
+}
+
+/// The game's own tiles followed by those of every pack that loaded.
+fn merged(assets: &TileAssets, packs: &TilePacks, catalogues: &Assets<TileCatalogue>) -> Option<TileCatalogue> {
+    let mut catalogue = catalogues.get(&assets.catalogue)?.clone();
+    for pack in packs.catalogues.iter().filter_map(|handle| catalogues.get(handle)) {
+        catalogue.merge(pack);
+    }
+    Some(catalogue)
+}
+
+/// Installs the catalogue once the game's tiles have loaded, and every pack has either loaded or failed to.
+fn install_catalogue(
     mut commands: Commands,
+    assets: Option<Res<TileAssets>>,
+    packs: Res<TilePacks>,
+    asset_server: Res<AssetServer>,
+    catalogues: Res<Assets<TileCatalogue>>,
+) {
+    let Some(assets) = assets else {return};
+    let settled = packs.catalogues.iter()
+        .all(|handle| matches!(asset_server.load_state(handle), LoadState::Loaded | LoadState::Failed(_)));
+    if !settled {return}
+    let Some(catalogue) = merged(&assets, &packs, &catalogues) else {return};
+    info!("Loaded {} tiles", catalogue.len());
+    commands.insert_resource(catalogue);
+}
+
+/// Installs the catalogue again after one of its files was edited.
+#[cfg(feature = "dev_native")]
+fn reload_catalogue(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<TileCatalogue>>,
+    installed: Option<Res<TileCatalogue>>,
+    assets: Option<Res<TileAssets>>,
+    packs: Res<TilePacks>,
+    catalogues: Res<Assets<TileCatalogue>>,
+) {
+    let (Some(installed), Some(assets)) = (installed, assets) else {return};
+    if !events.read().any(|event| matches!(event, AssetEvent::Modified {..})) {return}
+    let Some(catalogue) = merged(&assets, &packs, &catalogues) else {return};
+    // Cells refer to tiles by index, so the tiles of the current map must keep theirs.
+    let kept = catalogue.tiles.len() >= installed.tiles.len()
+        && installed.tiles.iter().zip(&catalogue.tiles).all(|(old, new)| old.id == new.id);
+    if !kept {
+        warn!("Ignoring tile catalogue that removes or reorders tiles, restart to apply it");
+        return;
+    }
+    info!("Reloaded tile catalogue with {} tiles", catalogue.len());
+    commands.insert_resource(catalogue);
+}
+
+/// One of the models spawned by [`spawn_tiles`].
+#[derive(Component)]
+struct TileModel;
+
+/// Spawns a model for every tile and rotation, which the tileset camera renders into the atlas.
+fn spawn_tiles(
+    mut commands: Commands,
+    catalogue: Res<TileCatalogue>,
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    existing: Query<Entity, With<TileModel>>,
 ) {
-    // Load tile material
-    let texture: Handle<Image> = asset_server.load("images/colormap.png");
-    let material = materials.add(StandardMaterial {
-        base_color_texture: Some(texture.clone()),
-        ..default()
-    });
+    for entity in &existing {
+        commands.entity(entity).despawn();
+    }
+
+    // Tiles from packs may come with their own colour map, so there is a material for each.
+    let mut tile_materials = HashMap::new();
+
+    // Each tile is two units wide in the tileset, with the padding around it.
+    let padding = 2.0 * TILE_PADDING as f32 / TILE_SIZE as f32;
+    let stride = 2.0 + 2.0 * padding;
 
     // Create tiles
-    for (x,file) in MODELS.iter().enumerate() {
-        let px = x as f32 * 2.0;
+    for (x, tile) in catalogue.tiles.iter().enumerate() {
+        let colormap = tile.colormap.as_deref().unwrap_or(DEFAULT_COLORMAP);
+        let material = tile_materials.entry(colormap).or_insert_with(|| materials.add(StandardMaterial {
+            base_color_texture: Some(asset_server.load(colormap.to_string())),
+            ..default()
+        }));
+        let px = x as f32 * stride + padding;
         let mesh: Handle<Mesh> = asset_server.load(
-            GltfAssetLabel::Primitive{ mesh:0, primitive:0 }.from_asset(file.path)
+            GltfAssetLabel::Primitive{ mesh:0, primitive:0 }.from_asset(tile.path.clone())
         );
         for y in 0..6 {
-            let py = y as f32 * 2.0;
+            let py = y as f32 * stride + padding;
             commands.spawn((
+                TileModel,
                 Tile::rotated(y),
                 Mesh3d(mesh.clone()),
                 MeshMaterial3d(material.clone()),
@@ -99,3 +504,69 @@ fn setup(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_must_fit_in_a_save_file() {
+        let tile = |name: &str| format!(r#"(path: "{name}.glb", edges: "gggggg", category: Grass, weight: 1.0, name: "")"#);
+        let parse = |name: &str| ron::de::from_str::<TileCatalogue>(&format!("(tiles: [{}])", tile(name))).unwrap();
+        let file = AssetPath::parse("packs://long/pack.tiles.ron");
+        let fits = "a".repeat(MAX_ID_LEN - "long/".len());
+        assert!(parse(&fits).resolve(&file).is_ok());
+        let long = fits + "a";
+        assert!(matches!(parse(&long).resolve(&file), Err(TileLoaderError::IdTooLong(id)) if id.len() == MAX_ID_LEN + 1));
+    }
+
+    #[test]
+    fn rotated_edges_face_the_rotated_directions() {
+        let catalogue = TileCatalogue::standard();
+        for (id, tile) in catalogue.tiles.iter().enumerate() {
+            for rotation in 0..6 {
+                let edges = catalogue.edges(uvec2(id as u32, rotation));
+                for side in 0..6 {
+                    // The socket on `side` of the unrotated tile turns along with its neighbour.
+                    let turned = Hex::DIRECTIONS[side].rotate(rotation as i32);
+                    let facing = Hex::DIRECTIONS.iter().position(|&direction| direction == turned).unwrap();
+                    assert_eq!(edges.get(facing), tile.edges.get(side), "{} rotated {rotation}, side {side}", tile.id());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn placement_checks_the_rotated_edges() {
+        let catalogue = TileCatalogue::standard();
+        let ocean = uvec2(catalogue.roles.ocean, 0);
+        let map = HexMap::new(MapShape::new(8, MapBoundary::Wrapping), ocean, &catalogue, &mut *WorldRng::new(WorldSeed(1)));
+        for (id, tile) in catalogue.tiles.iter().enumerate() {
+            for rotation in 0..6 {
+                let expected = (0..6)
+                    .filter(|&side| !tile.edges.get(side + 6 - rotation as usize).fits(Edge::Water))
+                    .fold(0, |mask, side| mask | 1 << side);
+                assert_eq!(map.mismatches(&catalogue, Hex::ZERO, uvec2(id as u32, rotation)), expected, "{} rotated {rotation}", tile.id());
+            }
+        }
+    }
+
+    #[test]
+    fn edge_sets_need_six_known_sockets() {
+        assert_eq!(EdgeSet::try_from("gprwcC".to_string()), Ok(EdgeSet([Edge::Grass, Edge::Path, Edge::River, Edge::Water, Edge::CoastNext, Edge::CoastPrev])));
+        assert!(EdgeSet::try_from("ggggg".to_string()).is_err());
+        assert!(EdgeSet::try_from("ggggggg".to_string()).is_err());
+        assert!(EdgeSet::try_from("gggggx".to_string()).is_err());
+    }
+
+    #[test]
+    fn coasts_fit_mirrored() {
+        for a in Edge::ALL {
+            for b in Edge::ALL {
+                assert_eq!(a.fits(b), b.fits(a), "{a:?} and {b:?}");
+            }
+        }
+        assert!(Edge::CoastNext.fits(Edge::CoastPrev));
+        assert!(!Edge::CoastNext.fits(Edge::CoastNext));
+    }
+}
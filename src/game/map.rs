@@ -1,28 +1,40 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashSet};
 
 use bevy::{
     asset::RenderAssetUsages,
     image::ImageSampler,
+    ecs::system::SystemParam,
     prelude::*,
     render::{
         extract_resource::{ExtractResource, ExtractResourcePlugin},
+        gpu_readback::{Readback, ReadbackComplete},
         graph::CameraDriverLabel,
         mesh::PrimitiveTopology,
         render_asset::RenderAssets,
         render_graph::{Node, RenderGraph, RenderLabel},
-        render_resource::{AsBindGroup, BindGroup, BindGroupLayout, CachedComputePipelineId, ComputePassDescriptor, ComputePipelineDescriptor, Extent3d, Origin3d, PipelineCache, ShaderRef, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages},
-        renderer::{RenderDevice, RenderQueue},
-        storage::GpuShaderStorageBuffer,
+        render_resource::{binding_types::storage_buffer_read_only, AsBindGroup, BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedComputePipelineId, ComputePassDescriptor, ComputePipelineDescriptor, Extent3d, Origin3d, PipelineCache, ShaderRef, ShaderStages, StorageBuffer, TexelCopyBufferLayout, TexelCopyTextureInfo, TextureAspect, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages},
+        renderer::{RenderAdapter, RenderDevice, RenderQueue},
+        storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
         texture::{FallbackImage, GpuImage},
         view::NoFrustumCulling,
         Render, RenderApp, RenderSet
     }
 };
-use rand::Rng;
 
 use crate::screens::Screen;
 
-use super::prelude::*;
+use super::{
+    biome::{self, BiomeSettings},
+    deck::{DeckAssets, DeckConfig, GameMode, TileDeck},
+    hex_map::{self, random_prng, Cell, MapChanges},
+    history::EditHistory,
+    prelude::*,
+    quests::{Quests, MAX_QUESTS},
+    scoring::{RegionsAround, Score, TilePlaced},
+    simulation::{self, SimulationBackend, SimulationChunks, SimulationRules, SimulationTicks, CHUNK_SIZE},
+    streaming::{ChunkSource, ChunkStore},
+    wfc::{Wfc, WfcSettings},
+};
 
 pub(super) struct MapPlugin;
 
@@ -37,6 +49,9 @@ pub struct TileMap;
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<TilemapMaterial>();
+        app.register_type::<MapKind>();
+        app.init_resource::<MapKind>();
+        app.init_resource::<GrowthReadback>();
         app.add_plugins(ExtractResourcePlugin::<ShaderData>::default());
         app.add_plugins(MaterialPlugin::<TilemapMaterial>{
             prepass_enabled: false,
@@ -44,17 +59,31 @@ impl Plugin for MapPlugin {
             ..default()
         });
         app.add_systems(OnEnter(Screen::Gameplay), setup);
-        app.add_systems(Update, update_tile);
+        app.add_systems(Update, (
+            (check_placement, place_tile, update_tile).chain(),
+            regenerate_map.run_if(resource_exists::<TileCatalogue>),
+        ));
+        app.add_systems(PostUpdate, (
+            replace_map_texture,
+            swap_map_buffers,
+            read_back_growth.after(hex_map::collect_changes),
+        ).chain());
     }
 
     fn finish(&self, app: &mut App) {
+        let backend = app.world().get_resource::<RenderAdapter>().map_or(SimulationBackend::Gpu, simulation::supported_backend);
         if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
-            // Add code for updating clicked tiles.
-            render_app.add_systems(Render, handle_click.in_set(RenderSet::Queue));
+            // Add code for copying changed cells to the map texture.
+            render_app.init_resource::<MapChanges>();
+            render_app.add_systems(Render, write_changes.in_set(RenderSet::Queue));
+
+            // Without storage textures the simulation runs on the CPU, and the kernel couldn't even be compiled.
+            if backend == SimulationBackend::Cpu {return}
 
             // Inject the compute kernel.
             render_app.init_resource::<KernelPipeline>();
-            render_app.add_systems(Render, prepare_compute.in_set(RenderSet::Prepare));
+            render_app.init_resource::<ChunkBuffer>();
+            render_app.add_systems(Render, (prepare_compute, prepare_chunks).in_set(RenderSet::Prepare));
 
             // Add our compute kernel to the render graph
             let mut render_graph = render_app.world_mut().get_resource_mut::<RenderGraph>().expect("Should be able to get render graph");
@@ -66,10 +95,10 @@ impl Plugin for MapPlugin {
                 MyRenderLabels::Simulate,
                 CameraDriverLabel,
             );
-            if r.is_err() {
-                println!("{:?}", r);
+            if let Err(err) = r {
+                warn!("Failed to order the simulation before the camera: {err}");
             }
-            println!("KernelPipeline added");
+            debug!("KernelPipeline added");
         }
     }
 }
@@ -79,17 +108,51 @@ impl Plugin for MapPlugin {
  */
 #[derive(Asset, Reflect, AsBindGroup, Debug, Clone)]
 pub struct TilemapMaterial {
-    #[storage_texture(0, image_format=Rgba8Uint, visibility(vertex,fragment), access=ReadOnly)] map: Handle<Image>,
+    #[texture(0, sample_type="u_int", visibility(vertex,fragment))] map: Handle<Image>,
     #[texture(1)] #[sampler(2)] tileset: Handle<Image>,
     #[uniform(3)] hover_tile: Vec4,
     #[uniform(4)] tile_size: f32,
     #[uniform(5)] tile_count: f32,
     #[uniform(6)] selected_tile: UVec2,
+    #[uniform(7)] placeable: u32,
+    #[uniform(8)] quest_markers: [Vec4; MAX_QUESTS],
+    /// Whether cells beyond the edges of the map are drawn as `ocean_tile` instead of wrapping around.
+    #[uniform(9)] bounded: u32,
+    #[uniform(10)] ocean_tile: u32,
+    /// The lowest cell of a bounded or infinite map, see [`MapShape::origin`].
+    #[uniform(11)] map_origin: IVec2,
+    /// The tileset with mips, see [`TilesetMips`]. It is only sampled while `mips_ready` is set.
+    #[texture(12)] #[sampler(13)] tileset_mips: Handle<Image>,
+    #[uniform(14)] mips_ready: u32,
+    #[uniform(15)] tile_padding: f32,
 }
 
+/// Inputs of the simulation kernel. The map is double-buffered: every tick reads
+/// one texture and writes the other, after which the two swap places.
 #[derive(TypePath,AsBindGroup,Resource,Clone,ExtractResource)]
 struct ShaderData {
-    #[storage_texture(0, image_format=Rgba8Uint)] tiles: Handle<Image>,
+    /// The latest state of the map, once this frame's ticks have run.
+    #[storage_texture(0, image_format=Rgba8Uint, access=ReadOnly)] front: Handle<Image>,
+    #[storage_texture(1, image_format=Rgba8Uint, access=WriteOnly)] back: Handle<Image>,
+    #[storage(2, read_only, visibility(compute))] rules: Handle<ShaderStorageBuffer>,
+    /// Number of ticks that run this frame.
+    ticks: u32,
+}
+
+impl ShaderData {
+    /// The texture holding the map before this frame's ticks, which is where edits go.
+    fn start(&self) -> &Handle<Image> {
+        if self.ticks % 2 == 1 {&self.back} else {&self.front}
+    }
+
+    /// The same buffers with their roles reversed.
+    fn swapped(&self) -> Self {
+        Self {
+            front: self.back.clone(),
+            back: self.front.clone(),
+            ..self.clone()
+        }
+    }
 }
 
 impl Material for TilemapMaterial {
@@ -104,9 +167,11 @@ impl Material for TilemapMaterial {
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
-    mut images: ResMut<Assets<Image>>,
     mut materials: ResMut<Assets<TilemapMaterial>>,
     tileset: Res<Tileset>,
+    mips: Res<TilesetMips>,
+    catalogue: Res<TileCatalogue>,
+    mut generator: MapGenerator,
 ) {
     // Fullscreen triangle (covers full screen)
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList, RenderAssetUsages::RENDER_WORLD);
@@ -117,44 +182,8 @@ fn setup(
         [-1.0,  1.0, 0.0],
     ]);
 
-    let size = Extent3d {
-        width: MAP_SIZE,
-        height: MAP_SIZE,
-        ..default()
-    };
-
-    let mut map_data = Vec::<u8>::with_capacity((MAP_SIZE*MAP_SIZE*4) as usize);
-    for _ in 0..MAP_SIZE * MAP_SIZE {
-        map_data.push(rand::thread_rng().gen_range(0..44));
-        map_data.push(rand::thread_rng().gen_range(0..6));
-        // Init xorshift16 with non-zero seed.
-        let prng: u32 = rand::thread_rng().gen_range(1..65536);
-        map_data.push((prng / 256) as u8);
-        map_data.push((prng % 256) as u8);
-    }
-
-    // This is the texture that will be rendered to.
-    let map_image = Image {
-        data: Some(map_data),
-        texture_descriptor: TextureDescriptor {
-            label: None,
-            size,
-            format: TextureFormat::Rgba8Uint,
-            dimension: TextureDimension::D2,
-            mip_level_count: 1,
-            sample_count: 1,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING,
-            view_formats: &[],
-        },
-        sampler: ImageSampler::nearest(),
-        texture_view_descriptor: None,
-        asset_usage: RenderAssetUsages::RENDER_WORLD,
-    };
-
-    let map_handle = images.add(map_image);
-    commands.insert_resource(ShaderData {
-        tiles: map_handle.clone()
-    });
+    // The map texture is created by `replace_map_texture` once the map is inserted.
+    commands.insert_resource(generator.generate());
 
     commands.spawn((
         Name::new("Tilemap"),
@@ -162,12 +191,20 @@ fn setup(
         NoFrustumCulling,
         Mesh3d(meshes.add(mesh)),
         MeshMaterial3d(materials.add(TilemapMaterial{
-            map: map_handle,
+            map: Handle::default(),
             tileset: tileset.0.clone(),
             hover_tile: Vec4::ZERO,
             tile_size: TILE_SIZE as f32,
-            tile_count: TILE_COUNT as f32,
+            tile_count: catalogue.len() as f32,
             selected_tile: UVec2::ZERO,
+            placeable: 0,
+            quest_markers: [Vec4::ZERO; MAX_QUESTS],
+            bounded: 0,
+            ocean_tile: catalogue.roles.ocean,
+            map_origin: IVec2::ZERO,
+            tileset_mips: mips.image.clone(),
+            mips_ready: 0,
+            tile_padding: TILE_PADDING as f32,
         })),
         Transform::IDENTITY,
     )).observe(|trigger: Trigger<Pointer<Move>>, mut mouse_pos: ResMut<MousePos>|{
@@ -178,8 +215,7 @@ fn setup(
         }
 
         // Update hovered hexagon
-        let hex = POSITION_TO_CUBE * trigger.event().hit.position.unwrap();
-        mouse_pos.hex_cell  = round_hex(hex).as_ivec3();
+        mouse_pos.hex_cell = Hex::from_world(trigger.event().hit.position.unwrap());
     }).observe(|_trigger: Trigger<Pointer<Out>>, mut mouse_pos: ResMut<MousePos>|{
         mouse_pos.on_screen = false;
         mouse_pos.click_started = None;
@@ -198,34 +234,275 @@ fn setup(
     });
 }
 
-const R: f32 = 0.57735027; // 1.0 / f32::sqrt(3.0);
-
-const POSITION_TO_CUBE: Mat3 = Mat3::from_cols(
-    vec3( 1.0,  0.0,-1.0),
-    vec3( 0.0,  0.0, 0.0),
-    vec3(- R ,2.0*R, -R )
-);
-
-fn round_hex(hex: Vec3) -> Vec3 {
-    let mut res = Vec3::round(hex);
-    let diff = Vec3::abs(hex - res);
-    if diff.x > diff.y && diff.x > diff.z {
-        res.x = -res.y -res.z;
-    } else if diff.y > diff.z {
-        res.y = -res.x -res.z;
-    } else {
-        res.z = -res.x -res.y;
+/// Which generator creates the map when a game starts.
+#[derive(Resource, Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[reflect(Resource)]
+pub enum MapKind {
+    /// Biomes from layered noise, covering the whole map.
+    #[default]
+    Biomes,
+    /// An island grown with wave function collapse, in the middle of the ocean.
+    Island,
+}
+
+#[derive(SystemParam)]
+struct MapGenerator<'w> {
+    kind: Res<'w, MapKind>,
+    shape: Res<'w, MapShape>,
+    seed: Res<'w, WorldSeed>,
+    rng: ResMut<'w, WorldRng>,
+    biome_settings: Res<'w, BiomeSettings>,
+    wfc_settings: Res<'w, WfcSettings>,
+    score: ResMut<'w, Score>,
+    deck: ResMut<'w, TileDeck>,
+    chunk_source: ResMut<'w, ChunkSource>,
+    chunk_store: ResMut<'w, ChunkStore>,
+    deck_assets: Option<Res<'w, DeckAssets>>,
+    deck_configs: Res<'w, Assets<DeckConfig>>,
+    catalogue: Res<'w, TileCatalogue>,
+}
+
+impl MapGenerator<'_> {
+    /// Whether any of the generator's settings changed since the system last ran.
+    fn is_changed(&self) -> bool {
+        self.kind.is_changed()
+            || self.shape.is_changed()
+            || self.seed.is_changed()
+            || self.biome_settings.is_changed()
+            || self.wfc_settings.is_changed()
+    }
+
+    /// Starts a new world from the seed.
+    fn generate(&mut self) -> HexMap {
+        *self.rng = WorldRng::new(*self.seed);
+        *self.score = Score::default();
+        let rng = &mut **self.rng;
+        // Bounded and infinite maps start out centered on the origin.
+        let shape = MapShape::new(self.shape.size, self.shape.boundary);
+        let infinite = shape.boundary == MapBoundary::Infinite;
+        let catalogue = &*self.catalogue;
+        *self.chunk_source = ChunkSource::new(*self.seed, (*self.kind == MapKind::Biomes).then_some(&*self.biome_settings), catalogue);
+        self.chunk_store.clear();
+        let map = match *self.kind {
+            MapKind::Biomes if infinite => self.chunk_source.generate_map(shape, catalogue),
+            MapKind::Biomes => biome::generate(shape, &self.biome_settings, catalogue, rng),
+            MapKind::Island => {
+                let water = uvec2(catalogue.roles.ocean, 0);
+                let mut map = HexMap::new(shape, water, catalogue, rng);
+                let size = self.wfc_settings.size.min(UVec2::splat(shape.size));
+                match Wfc::new(catalogue).generate(&mut map, -(size / 2).as_ivec2(), size, self.wfc_settings.max_backtracks, rng) {
+                    Ok(backtracks) => info!("Generated {size} island with {backtracks} backtracks"),
+                    Err(err) => warn!("Failed to generate island: {err}"),
+                }
+                map
+            }
+        };
+        let config = self.deck_assets.as_ref().and_then(|assets| self.deck_configs.get(&assets.config));
+        *self.deck = match config {
+            Some(config) => TileDeck::new(config, catalogue, rng),
+            None => TileDeck::default(),
+        };
+        map
+    }
+}
+
+/// Regenerates the map when the generator is tweaked, for example from the inspector.
+fn regenerate_map(mut commands: Commands, map: Option<Res<HexMap>>, mut generator: MapGenerator) {
+    if map.is_none() || !generator.is_changed() {return}
+    commands.insert_resource(generator.generate());
+}
+
+/// Creates a texture holding the given map. Only the compute kernel needs it to be a storage texture.
+fn map_image(map: &HexMap, backend: SimulationBackend) -> Image {
+    let mut usage = TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST;
+    if backend == SimulationBackend::Gpu {
+        usage |= TextureUsages::COPY_SRC | TextureUsages::STORAGE_BINDING;
+    }
+    Image {
+        data: Some(map.texture_data()),
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: map.size(),
+                height: map.size(),
+                ..default()
+            },
+            format: TextureFormat::Rgba8Uint,
+            dimension: TextureDimension::D2,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage,
+            view_formats: &[],
+        },
+        sampler: ImageSampler::nearest(),
+        texture_view_descriptor: None,
+        asset_usage: RenderAssetUsages::RENDER_WORLD,
+    }
+}
+
+/// Uploads the whole map when the [`HexMap`] resource is (re)inserted, for example after loading a save.
+fn replace_map_texture(
+    mut commands: Commands,
+    map: Option<Res<HexMap>>,
+    rules: Res<SimulationRules>,
+    backend: Res<SimulationBackend>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<TilemapMaterial>>,
+) {
+    let Some(map) = map else {return};
+    if !map.is_added() {return}
+
+    let map_handle = images.add(map_image(&map, *backend));
+    commands.insert_resource(ShaderData {
+        front: map_handle.clone(),
+        back: images.add(map_image(&map, *backend)),
+        rules: rules.buffer.clone(),
+        ticks: 0,
+    });
+    for mat in materials.iter_mut() {
+        mat.1.map = map_handle.clone();
+    }
+}
+
+/// Points the map material at the texture that will hold the latest state after this frame's ticks.
+fn swap_map_buffers(
+    ticks: Res<SimulationTicks>,
+    shader_data: Option<ResMut<ShaderData>>,
+    mut materials: ResMut<Assets<TilemapMaterial>>,
+) {
+    let Some(mut shader_data) = shader_data else {return};
+    if !ticks.is_changed() && !shader_data.is_added() {return}
+    if ticks.0 % 2 == 1 {
+        *shader_data = shader_data.swapped();
+        for mat in materials.iter_mut() {
+            mat.1.map = shader_data.front.clone();
+        }
     }
-    res
+    shader_data.ticks = ticks.0;
+}
+
+/// Brings growth from the compute kernel back into the [`HexMap`], see [`read_back_growth`].
+#[derive(Resource, Default)]
+struct GrowthReadback {
+    /// The map texture that is being read back.
+    reading: Option<AssetId<Image>>,
+    /// Whether ticks ran since the last readback was requested.
+    ticked: bool,
+    /// Texels that were edited on the CPU since the readback was requested.
+    edited: HashSet<UVec2>,
 }
 
-fn update_tile(mouse: Res<MousePos>, mut materials: ResMut<Assets<TilemapMaterial>>) {
-    let tile = mouse.hex_cell.as_vec3();
+/// Reads back the map texture after ticks on the GPU, one readback at a time.
+///
+/// Cells that are edited while the texture is on its way keep their edits, as those are uploaded after
+/// the texture was read and would otherwise be reverted.
+fn read_back_growth(
+    mut commands: Commands,
+    backend: Res<SimulationBackend>,
+    ticks: Res<SimulationTicks>,
+    changes: Res<MapChanges>,
+    shader_data: Option<Res<ShaderData>>,
+    mut readback: ResMut<GrowthReadback>,
+) {
+    if *backend != SimulationBackend::Gpu {return}
+    let Some(shader_data) = shader_data else {return};
+    readback.ticked |= ticks.0 > 0;
+    if readback.reading.is_some() {
+        if changes.is_changed() {
+            readback.edited.extend(changes.0.iter().map(|(texel, _)| *texel));
+        }
+        return;
+    }
+    if !readback.ticked {return}
+
+    // The front texture holds the map once this frame's edits and ticks have run.
+    *readback = GrowthReadback {
+        reading: Some(shader_data.front.id()),
+        ticked: false,
+        edited: HashSet::new(),
+    };
+    commands.spawn(Readback::texture(shader_data.front.clone())).observe(apply_growth);
+}
+
+fn apply_growth(
+    trigger: Trigger<ReadbackComplete>,
+    mut commands: Commands,
+    map: Option<ResMut<HexMap>>,
+    shader_data: Option<Res<ShaderData>>,
+    mut readback: ResMut<GrowthReadback>,
+) {
+    commands.entity(trigger.target()).despawn();
+    let Some(texture) = readback.reading.take() else {return};
+    let (Some(mut map), Some(shader_data)) = (map, shader_data) else {return};
+    // The map was replaced while its texture was read back.
+    if shader_data.front.id() != texture && shader_data.back.id() != texture {return}
+
+    // Rows of the texture were padded for the copy.
+    let width = map.size() as usize * 4;
+    let row = trigger.event().0.len() / map.size() as usize;
+    let texels: Vec<u8> = trigger.event().0.chunks_exact(row).flat_map(|line| &line[..width]).copied().collect();
+    map.apply_growth(&texels, &readback.edited);
+}
+
+fn check_placement(
+    mut mouse: ResMut<MousePos>,
+    map: Option<Res<HexMap>>,
+    catalogue: Option<Res<TileCatalogue>>,
+    mode: Res<GameMode>,
+    deck: Res<TileDeck>,
+) {
+    let (Some(map), Some(catalogue)) = (map, catalogue) else {return};
+    let placeable = mode.has_tile(&deck)
+        && map.contains(mouse.hex_cell)
+        && map.mismatches(&catalogue, mouse.hex_cell, mouse.selected_tile) == 0;
+    if mouse.placeable != placeable {
+        mouse.placeable = placeable;
+    }
+}
+
+fn place_tile(
+    mut commands: Commands,
+    mouse: Res<MousePos>,
+    map: Option<ResMut<HexMap>>,
+    mut rng: ResMut<WorldRng>,
+    mut history: ResMut<EditHistory>,
+) {
+    if !mouse.click || !mouse.placeable {return}
+    let Some(mut map) = map else {return};
+    let cell = Cell::new(mouse.selected_tile, random_prng(&mut **rng));
+    let before = RegionsAround::new(&map, mouse.hex_cell);
+    if history.apply(&mut map, [(mouse.hex_cell, cell)]) {
+        commands.trigger(TilePlaced {hex: mouse.hex_cell, before});
+    }
+}
+
+fn update_tile(
+    mouse: Res<MousePos>,
+    quests: Res<Quests>,
+    map: Option<Res<HexMap>>,
+    catalogue: Option<Res<TileCatalogue>>,
+    mips: Res<TilesetMips>,
+    mut materials: ResMut<Assets<TilemapMaterial>>,
+) {
+    let tile = mouse.hex_cell.cube().as_vec3();
+    let markers = quests.markers();
+    let shape = map.map_or_else(MapShape::default, |map| map.shape());
+    let tile_count = catalogue.map_or(0, |catalogue| catalogue.len());
     for mat in materials.iter_mut() {
+        mat.1.tile_count = tile_count as f32;
+        mat.1.mips_ready = mips.ready as u32;
+        // Each view of the tileset has mips of its own.
+        if mat.1.tileset_mips != mips.image {
+            mat.1.tileset_mips = mips.image.clone();
+        }
+        mat.1.bounded = !shape.wraps() as u32;
+        mat.1.map_origin = shape.origin();
         mat.1.hover_tile = tile.extend(
             if mouse.on_screen {0.0} else {1.0}
         );
         mat.1.selected_tile = mouse.selected_tile;
+        mat.1.placeable = mouse.placeable as u32;
+        mat.1.quest_markers = markers;
     }
 }
 
@@ -233,6 +510,8 @@ fn update_tile(mouse: Res<MousePos>, mut materials: ResMut<Assets<TilemapMateria
 struct KernelPipeline {
     pub pipeline: CachedComputePipelineId,
     pub bind_group_layout: BindGroupLayout,
+    /// Layout of the list of chunks to simulate.
+    pub chunk_layout: BindGroupLayout,
 }
 
 impl FromWorld for KernelPipeline {
@@ -245,18 +524,23 @@ impl FromWorld for KernelPipeline {
         // Build shader graph
         let shader: Handle<Shader> = assets.load("shaders/simulate.wgsl");
         let bind_group_layout = ShaderData::bind_group_layout(render_device);
+        let chunk_layout = render_device.create_bind_group_layout(
+            "simulation_chunks_layout",
+            &BindGroupLayoutEntries::single(ShaderStages::COMPUTE, storage_buffer_read_only::<Vec<UVec4>>(false)),
+        );
         let pipeline = cache.queue_compute_pipeline(ComputePipelineDescriptor{
             label: None,
-            layout: vec![bind_group_layout.clone()],
+            layout: vec![bind_group_layout.clone(), chunk_layout.clone()],
             push_constant_ranges: vec![],
             shader,
-            shader_defs: vec![],
+            shader_defs: simulation::shader_defs(),
             entry_point: Cow::from("main"),
             zero_initialize_workgroup_memory: true,
         });
 
         KernelPipeline{
             bind_group_layout,
+            chunk_layout,
             pipeline,
         }
     }
@@ -264,8 +548,13 @@ impl FromWorld for KernelPipeline {
 
 pub struct DispatchKernel;
 
+/// Bind groups for reading either of the map textures and writing the other one.
 #[derive(Debug, Resource)]
-pub struct KernelBindGroup(pub BindGroup);
+pub struct KernelBindGroup {
+    /// The texture read by each bind group.
+    reads: [AssetId<Image>; 2],
+    groups: [BindGroup; 2],
+}
 
 fn prepare_compute<'a>(
     mut commands: Commands,
@@ -279,26 +568,54 @@ fn prepare_compute<'a>(
         Res<'a, RenderAssets<GpuShaderStorageBuffer>>
     ),
 ) {
-    if bind_group.is_some() {return}
-
-    let Some(shader_data) = shader_data else {
-        println!("shader_data not yet available");
-        return
-    };
-    let prepared_result = shader_data.as_bind_group(
-        &pipeline.bind_group_layout,
-        &render_device,
-        &mut param,
-    );
-    if let Ok(prepared_numbers) = prepared_result {
-        commands.insert_resource(KernelBindGroup(prepared_numbers.bind_group));
-        println!("bind success!");
+    let Some(shader_data) = shader_data else {return};
+    // Rebind when the map textures were replaced. Swapping them only changes which bind group runs first.
+    let reads = [shader_data.front.id(), shader_data.back.id()];
+    if bind_group.is_some_and(|bind_group| reads.iter().all(|id| bind_group.reads.contains(id))) {return}
+
+    let front = shader_data.as_bind_group(&pipeline.bind_group_layout, &render_device, &mut param);
+    let back = shader_data.swapped().as_bind_group(&pipeline.bind_group_layout, &render_device, &mut param);
+    if let (Ok(front), Ok(back)) = (front, back) {
+        commands.insert_resource(KernelBindGroup {
+            reads,
+            groups: [front.bind_group, back.bind_group],
+        });
+        debug!("Bound the map textures for the simulation");
     } else {
-        println!("retry next update");
-        // we are retrying every frame regardless
+        // Retried every frame until the textures are uploaded.
+        commands.remove_resource::<KernelBindGroup>();
+        trace!("Map textures not ready for the simulation");
     }
 }
 
+/// The chunks that the kernel runs on, see [`SimulationChunks`].
+#[derive(Resource, Default)]
+struct ChunkBuffer {
+    buffer: StorageBuffer<Vec<UVec4>>,
+    bind_group: Option<BindGroup>,
+    len: u32,
+}
+
+fn prepare_chunks(
+    chunks: Option<Res<SimulationChunks>>,
+    render_device: Res<RenderDevice>,
+    queue: Res<RenderQueue>,
+    pipeline: Res<KernelPipeline>,
+    mut buffer: ResMut<ChunkBuffer>,
+) {
+    let Some(chunks) = chunks else {return};
+    if !chunks.is_changed() {return}
+    buffer.len = chunks.0.len() as u32;
+    // Empty buffers can't be bound, but there is nothing to dispatch then anyway.
+    if chunks.0.is_empty() {return}
+
+    buffer.buffer.set(chunks.0.clone());
+    buffer.buffer.write_buffer(&render_device, &queue);
+    let Some(binding) = buffer.buffer.binding() else {return};
+    let bind_group = render_device.create_bind_group("simulation_chunks", &pipeline.chunk_layout, &BindGroupEntries::single(binding));
+    buffer.bind_group = Some(bind_group);
+}
+
 impl Node for DispatchKernel {
     fn run(
         &self,
@@ -311,7 +628,11 @@ impl Node for DispatchKernel {
         let kernel_pipeline = world.get_resource::<KernelPipeline>();
         let kernel_bind_group = world.get_resource::<KernelBindGroup>();
         let pipeline_cache = world.get_resource::<PipelineCache>();
-        if let (Some(kernel_pipeline), Some(kernel_bind_group), Some(pipeline_cache)) = (kernel_pipeline, kernel_bind_group, pipeline_cache) {
+        let shader_data = world.get_resource::<ShaderData>();
+        let chunks = world.get_resource::<ChunkBuffer>();
+        if let (Some(kernel_pipeline), Some(kernel_bind_group), Some(pipeline_cache), Some(shader_data), Some(chunks)) = (kernel_pipeline, kernel_bind_group, pipeline_cache, shader_data, chunks) {
+            if shader_data.ticks == 0 || chunks.len == 0 {return Ok(())}
+            let Some(chunk_bind_group) = &chunks.bind_group else {return Ok(())};
             let mut pass = render_context
                 .command_encoder()
                 .begin_compute_pass(&ComputePassDescriptor {
@@ -321,58 +642,55 @@ impl Node for DispatchKernel {
             if let Some(real_pipeline) = pipeline_cache.get_compute_pipeline(kernel_pipeline.pipeline) {
                 //println!("dispatch happening");
                 pass.set_pipeline(real_pipeline);
-                pass.set_bind_group(0, &kernel_bind_group.0, &[]);
-                pass.dispatch_workgroups(16, 16, 1);
+                pass.set_bind_group(1, chunk_bind_group, &[]);
+                // Every tick reads the texture written by the previous one, ending up in the front texture.
+                let first = kernel_bind_group.reads.iter().position(|id| *id == shader_data.start().id()).unwrap_or(0);
+                for tick in 0..shader_data.ticks as usize {
+                    pass.set_bind_group(0, &kernel_bind_group.groups[(first + tick) % 2], &[]);
+                    pass.dispatch_workgroups(CHUNK_SIZE / 8, CHUNK_SIZE / 8, chunks.len);
+                }
             }
         }
         Ok(())
     }
 }
 
-fn handle_click(
+fn write_changes(
     queue: Res<RenderQueue>,
     gpu_images: Res<RenderAssets<GpuImage>>,
-    updates: Res<MousePos>,
+    mut changes: ResMut<MapChanges>,
     shader_data: Option<Res<ShaderData>>,
 ) {
-    if !updates.click {return} // Bail out if there was no click.
+    if changes.0.is_empty() {return} // Bail out if nothing changed.
 
     // Find the necessary resources
     let Some(shader_data) = shader_data else {return};
-    let Some(image) = gpu_images.get(shader_data.tiles.id()) else {return};
-
-    // Prepare data
-    let prng: u32 = rand::thread_rng().gen_range(1..65536);
-    let map_data = [
-        updates.selected_tile.x as u8,
-        updates.selected_tile.y as u8,
-        // Init xorshift16 with non-zero seed.
-        (prng / 256) as u8,
-        (prng % 256) as u8,
-    ];
-
-    // Queue the pixel write
-    queue.write_texture(
-        TexelCopyTextureInfo{
-            texture: &image.texture,
-            mip_level: 0,
-            origin: Origin3d {
-                x: (updates.hex_cell.x & 1023) as u32,
-                y: (updates.hex_cell.y & 1023) as u32,
-                z: 0,
+    let Some(image) = gpu_images.get(shader_data.start().id()) else {return};
+
+    // Queue the pixel writes
+    for (texel, data) in changes.0.drain(..) {
+        queue.write_texture(
+            TexelCopyTextureInfo{
+                texture: &image.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: texel.x,
+                    y: texel.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
             },
-            aspect: TextureAspect::All,
-        },
-        &map_data,
-        TexelCopyBufferLayout{
-            offset: 0,
-            bytes_per_row: Some(4),
-            rows_per_image: Some(1),
-        },
-        Extent3d{
-            width: 1,
-            height: 1,
-            depth_or_array_layers: 1,
-        }
-    );
+            &data,
+            TexelCopyBufferLayout{
+                offset: 0,
+                bytes_per_row: Some(4),
+                rows_per_image: Some(1),
+            },
+            Extent3d{
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            }
+        );
+    }
 }
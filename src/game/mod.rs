@@ -1,29 +1,78 @@
 use bevy::prelude::*;
 
+mod biome;
+mod deck;
+mod hex;
+mod hex_map;
+mod history;
 mod load_tiles;
 mod map;
 mod mouse;
+mod pathfinding;
+mod quests;
+mod region;
+mod save;
 mod scene;
+mod scoring;
+mod seed;
+mod simulation;
+mod streaming;
+mod tile_packs;
 mod tileset;
+mod tileset_cache;
+mod wfc;
+
+pub use biome::{generate as generate_biomes, BiomeSettings};
+pub use deck::game_over;
+pub use hex_map::{HexMap, MapBoundary, MapShape, MAP_SIZES};
+pub use load_tiles::TileCatalogue;
+pub use save::{LoadGame, SaveGame};
+pub use scoring::Score;
+pub use seed::{CommandLineSeed, WorldRng, WorldSeed};
+pub use tile_packs::register_source as register_tile_pack_source;
+pub use tileset_cache::bake_from_args as bake_tileset_from_args;
+pub use wfc::Wfc;
 
 #[allow(unused_imports)]
 mod prelude {
-    pub use super::load_tiles::TILE_COUNT;
+    pub use super::hex::Hex;
+    pub use super::hex_map::{HexMap, MapBoundary, MapShape};
+    pub use super::load_tiles::{Edge, EdgeSet, TileCatalogue};
     pub use super::map::TileMap;
     pub use super::mouse::MousePos;
+    pub use super::pathfinding::{Path, Pathfinder};
     pub use super::scene::MainCamera;
-    pub use super::tileset::{Tileset, Tile};
+    pub use super::seed::{WorldRng, WorldSeed};
+    pub use super::tileset::{Tileset, TilesetMips, Tile};
 
     pub const TILE_SIZE: u32 = 128;
-    pub const MAP_SIZE: u32 = 1024;
+    /// Pixels around each tile in the tileset, so that its smaller mip levels don't bleed into each other.
+    /// They are empty in the tileset, and repeat the edge of the tile in its mips.
+    pub const TILE_PADDING: u32 = 8;
 }
 
 pub(super) fn plugin(app: &mut App) {
     app.add_plugins((
+        seed::plugin,
         map::MapPlugin,
         mouse::plugin,
         scene::plugin,
         tileset::plugin,
         load_tiles::plugin,
+        hex_map::plugin,
+        save::plugin,
+        wfc::plugin,
+        biome::plugin,
+    ));
+    app.add_plugins((
+        history::plugin,
+        scoring::plugin,
+        deck::plugin,
+        quests::plugin,
+        pathfinding::plugin,
+        simulation::plugin,
+        streaming::plugin,
+        tile_packs::plugin,
+        tileset_cache::plugin,
     ));
 }
@@ -4,26 +4,26 @@ use bevy::{
         PickSet
     },
     prelude::*,
-    render::extract_resource::{ExtractResource, ExtractResourcePlugin},
     window::PrimaryWindow,
 };
 
 use super::prelude::*;
 
-#[derive(Resource, Default, Reflect, ExtractResource, Clone)]
+#[derive(Resource, Default, Reflect, Clone)]
 #[reflect(Resource)]
 pub struct MousePos {
-    pub hex_cell: IVec3,
+    pub hex_cell: Hex,
     pub on_screen: bool,
     pub click_started: Option<Vec2>,
     pub click: bool,
     pub selected_tile: UVec2,
+    /// Whether the selected tile fits the neighbours of the hovered cell.
+    pub placeable: bool,
 }
 
 pub(super) fn plugin(app: &mut App) {
     app.init_resource::<MousePos>();
     app.register_type::<MousePos>();
-    app.add_plugins(ExtractResourcePlugin::<MousePos>::default());
     app.add_systems(PreUpdate, tracking.in_set(PickSet::Backend));
     app.add_systems(First, |mut mousepos: ResMut<MousePos>| {mousepos.click = false;});
 }
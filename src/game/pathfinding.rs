@@ -0,0 +1,365 @@
+//! Shortest paths across the map, for anything that needs to travel over it.
+//!
+//! Moving to a neighbour crosses the side between the two cells, and the sockets on that side
+//! decide whether the move is possible: water and rivers can't be crossed, and roads on both
+//! sides make the move cheap. Inside a cell, a river or a bay splits the sides into banks
+//! that are only joined by roads, such as the one over a bridge.
+
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use super::{load_tiles::TileInfo, prelude::*};
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(Update, update_travel_costs.run_if(resource_exists_and_changed::<TileCatalogue>));
+
+    #[cfg(feature = "dev")]
+    overlay::plugin(app);
+}
+
+/// Cost of following a road from one cell to the next. No move is cheaper.
+const ROAD_COST: u32 = 1;
+const GRASS_COST: u32 = 2;
+const HILL_COST: u32 = 3;
+const FOREST_COST: u32 = 4;
+
+/// The cost of entering a tile, by its tags, or `None` if it can't be entered.
+/// Tiles that are all water cost nothing special, as none of their sides can be crossed.
+fn enter_cost(tile: &TileInfo) -> Option<u32> {
+    if tile.has_tag("mountain") {
+        None
+    } else if tile.has_tag("forest") {
+        Some(FOREST_COST)
+    } else if tile.has_tag("hill") || tile.has_tag("rocky-hill") {
+        Some(HILL_COST)
+    } else {
+        Some(GRASS_COST)
+    }
+}
+
+/// Searches give up after expanding this many nodes, so unreachable goals stay cheap to ask for.
+const MAX_EXPANDED: usize = 1 << 16;
+
+/// Marks a side that can't be crossed.
+const BLOCKED: u8 = u8::MAX;
+
+/// Travel costs for every tile and rotation.
+#[derive(Resource)]
+pub struct TravelCosts {
+    /// Cost of entering each tile, or `None` if it can't be entered. Indexed by tile id.
+    enter: Vec<Option<u32>>,
+    /// The bank each side of a tile belongs to, or [`BLOCKED`]. Sides on the same bank can
+    /// reach each other inside the cell. Indexed by tile id times six plus rotation.
+    banks: Vec<[u8; 6]>,
+    /// The sides of a tile with a road, as a bitmask. Indexed like `banks`.
+    roads: Vec<u8>,
+}
+
+impl TravelCosts {
+    pub fn new(catalogue: &TileCatalogue) -> Self {
+        let enter: Vec<_> = catalogue.tiles.iter().map(enter_cost).collect();
+        let tiles = (0..catalogue.len() * 6).map(|index| uvec2(index / 6, index % 6));
+        let banks = tiles.clone().map(|tile| banks(catalogue.edges(tile), enter[tile.x as usize].is_some())).collect();
+        let roads = tiles.map(|tile| {
+            let edges = catalogue.edges(tile);
+            (0..6).filter(|&side| edges.get(side) == Edge::Path).fold(0, |mask, side| mask | 1 << side)
+        }).collect();
+        Self {enter, banks, roads}
+    }
+
+    fn banks(&self, tile: UVec2) -> [u8; 6] {
+        self.banks[(tile.x * 6 + tile.y) as usize]
+    }
+
+    fn has_road(&self, tile: UVec2, side: usize) -> bool {
+        self.roads[(tile.x * 6 + tile.y) as usize] & 1 << (side % 6) != 0
+    }
+}
+
+/// Recomputes the costs for the tiles of a newly loaded catalogue.
+fn update_travel_costs(mut commands: Commands, catalogue: Res<TileCatalogue>) {
+    commands.insert_resource(TravelCosts::new(&catalogue));
+}
+
+/// Splits the sides of a tile into banks, see [`TravelCosts::banks`].
+fn banks(edges: EdgeSet, passable: bool) -> [u8; 6] {
+    let blocked = |side: usize| !passable || matches!(edges.get(side), Edge::Water | Edge::River);
+    let Some(start) = (0..6).find(|&side| blocked(side)) else {
+        return [0; 6];
+    };
+
+    // Walk around the tile, starting a new bank after every stretch of water.
+    let mut res = [BLOCKED; 6];
+    let mut bank = 0;
+    for side in (1..=6).map(|i| (start + i) % 6) {
+        if !blocked(side) {
+            res[side] = bank;
+        } else if side != start && !blocked((side + 5) % 6) {
+            bank += 1;
+        }
+    }
+
+    // Roads join the banks they leave from.
+    let roads: Vec<usize> = (0..6).filter(|&side| edges.get(side) == Edge::Path && res[side] != BLOCKED).collect();
+    if let Some(&first) = roads.first() {
+        for &side in &roads[1..] {
+            let (joined, other) = (res[first], res[side]);
+            for bank in res.iter_mut().filter(|bank| **bank == other) {
+                *bank = joined;
+            }
+        }
+    }
+    res
+}
+
+/// A way from one cell to another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Path {
+    /// The cells along the path, including both ends. Coordinates carry on across the edges
+    /// of the map instead of wrapping around, so consecutive cells are always neighbours.
+    pub cells: Vec<Hex>,
+    /// Total cost of the moves along the path.
+    pub cost: u32,
+}
+
+/// A cell, by its storage index, together with the bank of the cell it is on.
+type Node = (usize, u8);
+
+struct Visit {
+    cost: u32,
+    /// The cell in the coordinates of the path, rather than wrapped around.
+    hex: Hex,
+    from: Option<Node>,
+}
+
+/// Finds the cheapest path from `from` to `to` with A*, taking the shortest way around the map if it wraps.
+/// The ocean beyond the edges of a bounded map can't be crossed.
+pub fn find_path(map: &HexMap, costs: &TravelCosts, from: Hex, to: Hex) -> Option<Path> {
+    if !map.contains(from) || !map.contains(to) {
+        return None;
+    }
+    let size = map.size();
+    let goal = to.index(size);
+    let estimate = |hex: Hex| map.shape().distance(hex, to) * ROAD_COST;
+
+    let mut visits = HashMap::<Node, Visit>::new();
+    let mut open = BinaryHeap::new();
+    let start_banks = costs.banks(map.get(from).tile());
+    for bank in start_banks {
+        if bank == BLOCKED || visits.contains_key(&(from.index(size), bank)) {continue}
+        visits.insert((from.index(size), bank), Visit {cost: 0, hex: from, from: None});
+        open.push(Reverse((estimate(from), 0, from, bank)));
+    }
+
+    let mut expanded = 0;
+    while let Some(Reverse((_, cost, hex, bank))) = open.pop() {
+        let node = (hex.index(size), bank);
+        if visits[&node].cost < cost {continue}
+        if node.0 == goal {
+            return Some(Path {cells: trace(&visits, node), cost});
+        }
+        expanded += 1;
+        if expanded > MAX_EXPANDED {break}
+
+        let tile = map.get(hex).tile();
+        let banks = costs.banks(tile);
+        for side in (0..6).filter(|&side| banks[side] == bank) {
+            let next = hex.neighbour(side);
+            let next_tile = map.get(next).tile();
+            let next_bank = costs.banks(next_tile)[(side + 3) % 6];
+            if next_bank == BLOCKED {continue}
+            let Some(enter) = costs.enter[next_tile.x as usize] else {continue};
+            let road = costs.has_road(tile, side) && costs.has_road(next_tile, side + 3);
+            let next_cost = cost + if road {ROAD_COST} else {enter};
+
+            let next_node = (next.index(size), next_bank);
+            if visits.get(&next_node).is_some_and(|visit| visit.cost <= next_cost) {continue}
+            visits.insert(next_node, Visit {cost: next_cost, hex: next, from: Some(node)});
+            open.push(Reverse((next_cost + estimate(next), next_cost, next, next_bank)));
+        }
+    }
+    None
+}
+
+/// Follows the visits back from `node` to the start of the search.
+fn trace(visits: &HashMap<Node, Visit>, mut node: Node) -> Vec<Hex> {
+    let mut cells = Vec::new();
+    loop {
+        let visit = &visits[&node];
+        cells.push(visit.hex);
+        match visit.from {
+            Some(from) => node = from,
+            None => break,
+        }
+    }
+    cells.reverse();
+    cells
+}
+
+/// Finds paths across the current map.
+#[derive(SystemParam)]
+pub struct Pathfinder<'w> {
+    map: Option<Res<'w, HexMap>>,
+    /// Missing until the tile catalogue has loaded.
+    costs: Option<Res<'w, TravelCosts>>,
+}
+
+impl Pathfinder<'_> {
+    /// The cheapest path between two cells, if they are connected and close enough to search.
+    pub fn find(&self, from: Hex, to: Hex) -> Option<Path> {
+        find_path(self.map.as_deref()?, self.costs.as_deref()?, from, to)
+    }
+}
+
+/// Draws the path between a pinned cell and the hovered cell.
+#[cfg(feature = "dev")]
+mod overlay {
+    use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+
+    use super::{super::prelude::*, Pathfinder};
+
+    const PATH_DEBUG_KEY: KeyCode = KeyCode::F4;
+
+    pub(super) fn plugin(app: &mut App) {
+        app.init_resource::<PathDebug>();
+        app.add_systems(Update, (
+            pin_start.run_if(input_just_pressed(PATH_DEBUG_KEY)),
+            update_path,
+            draw_path,
+        ).chain());
+    }
+
+    #[derive(Resource, Default)]
+    struct PathDebug {
+        start: Option<Hex>,
+        end: Option<Hex>,
+        cells: Vec<Hex>,
+    }
+
+    /// Pins the hovered cell as the start of the path, or clears it if one is pinned already.
+    fn pin_start(mouse: Res<MousePos>, mut debug: ResMut<PathDebug>) {
+        debug.start = match debug.start {
+            Some(_) => None,
+            None => Some(mouse.hex_cell),
+        };
+        debug.end = None;
+        debug.cells.clear();
+    }
+
+    fn update_path(mouse: Res<MousePos>, mut debug: ResMut<PathDebug>, pathfinder: Pathfinder) {
+        let Some(start) = debug.start else {return};
+        if debug.end == Some(mouse.hex_cell) {return}
+        debug.end = Some(mouse.hex_cell);
+        debug.cells = pathfinder.find(start, mouse.hex_cell).map_or_else(Vec::new, |path| path.cells);
+    }
+
+    fn draw_path(debug: Res<PathDebug>, mut gizmos: Gizmos) {
+        gizmos.linestrip(
+            debug.cells.iter().map(|hex| hex.to_world() + 0.2 * Vec3::Y),
+            Color::srgb(1.0, 0.2, 0.8),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::hex_map::Cell, *};
+
+    /// A map filled with a single tile, with the catalogue and travel costs for it.
+    fn filled_map(id: &str, size: u32, boundary: MapBoundary) -> (TileCatalogue, HexMap, TravelCosts) {
+        let catalogue = TileCatalogue::standard();
+        let tile = uvec2(catalogue.find(id).unwrap(), 0);
+        let map = HexMap::new(MapShape::new(size, boundary), tile, &catalogue, &mut *WorldRng::new(WorldSeed(1)));
+        let costs = TravelCosts::new(&catalogue);
+        (catalogue, map, costs)
+    }
+
+    fn place(map: &mut HexMap, catalogue: &TileCatalogue, hex: Hex, id: &str, rotation: u32) {
+        map.set(hex, Cell::new(uvec2(catalogue.find(id).unwrap(), rotation), 1));
+    }
+
+    #[test]
+    fn roads_are_cheapest_and_forests_dearest() {
+        let (catalogue, mut map, costs) = filled_map("grass", 16, MapBoundary::Bounded);
+        let (from, to) = (Hex::new(-4, 0), Hex::new(4, 0));
+        let path = find_path(&map, &costs, from, to).unwrap();
+        assert_eq!(path.cost, 8 * GRASS_COST);
+
+        // A road that runs the same way, but one row over, is worth the detour.
+        for x in -4..=4 {
+            place(&mut map, &catalogue, Hex::new(x, 1), "grass-path-straight", 0);
+        }
+        let path = find_path(&map, &costs, from, to).unwrap();
+        assert!(path.cells[1..path.cells.len() - 1].iter().all(|hex| hex.y == 1), "{:?}", path.cells);
+        assert_eq!(path.cost, 2 * GRASS_COST + 7 * ROAD_COST);
+
+        let (_, forest, costs) = filled_map("grass-forest", 16, MapBoundary::Bounded);
+        let path = find_path(&forest, &costs, from, to).unwrap();
+        assert_eq!(path.cost, 8 * FOREST_COST);
+    }
+
+    #[test]
+    fn water_rivers_and_mountains_block_unless_bridged() {
+        let (from, to) = (Hex::new(-3, 0), Hex::new(3, 0));
+        // River tiles turned so that the river runs from side 2 to side 5, across the map.
+        for (id, rotation) in [("water", 0), ("river-straight", 2), ("stone-mountain", 0)] {
+            let (catalogue, mut map, costs) = filled_map("grass", 16, MapBoundary::Bounded);
+            for y in -8..8 {
+                place(&mut map, &catalogue, Hex::new(0, y), id, rotation);
+            }
+            assert_eq!(find_path(&map, &costs, from, to), None, "{id}");
+
+            if id == "stone-mountain" {continue}
+            // The road over the bridge runs from side 3 to side 0, crossing the river between them.
+            place(&mut map, &catalogue, Hex::new(0, 0), "bridge-path-a", 2);
+            let path = find_path(&map, &costs, from, to).unwrap_or_else(|| panic!("{id} with a bridge"));
+            assert!(path.cells.contains(&Hex::new(0, 0)), "{id}: {:?}", path.cells);
+        }
+
+        // Both bridges join the banks on either side of their river, in every rotation.
+        let catalogue = TileCatalogue::standard();
+        for id in ["bridge-path-a", "bridge-path-b"] {
+            for rotation in 0..6 {
+                let tile = uvec2(catalogue.find(id).unwrap(), rotation);
+                let banks = banks(catalogue.edges(tile), true);
+                assert_eq!(banks.iter().filter(|&&bank| bank == BLOCKED).count(), 2, "{id} {rotation}");
+                assert!(banks.iter().filter(|&&bank| bank != BLOCKED).all(|&bank| bank == banks[(rotation as usize + 1) % 6]), "{id} {rotation}: {banks:?}");
+            }
+        }
+        let river = banks(catalogue.edges(uvec2(catalogue.find("river-straight").unwrap(), 0)), true);
+        assert_eq!(river, [BLOCKED, 0, 0, BLOCKED, 1, 1]);
+    }
+
+    #[test]
+    fn paths_cross_the_seam_of_wrapping_maps() {
+        let (_, map, costs) = filled_map("grass", 16, MapBoundary::Wrapping);
+        for (from, to) in [(Hex::new(0, 0), Hex::new(15, 0)), (Hex::new(1, 3), Hex::new(14, 12)), (Hex::new(2, 15), Hex::new(3, 1))] {
+            let path = find_path(&map, &costs, from, to).unwrap();
+            let distance = from.wrapped_distance(to, 16);
+            assert!(distance < from.distance(to), "{from:?} to {to:?} doesn't cross the seam");
+            assert_eq!(path.cost, distance * GRASS_COST);
+            assert_eq!(path.cells.len() as u32, distance + 1);
+            assert_eq!(path.cells[0], from);
+            assert_eq!(path.cells.last().unwrap().index(16), to.index(16));
+            for pair in path.cells.windows(2) {
+                assert_eq!(pair[0].distance(pair[1]), 1, "{:?}", path.cells);
+            }
+        }
+    }
+
+    #[test]
+    fn unreachable_goals_give_up() {
+        // More cells than a search may expand, with the goal walled in by mountains.
+        let (catalogue, mut map, costs) = filled_map("grass", 512, MapBoundary::Wrapping);
+        let goal = Hex::new(200, 200);
+        for hex in goal.ring(1) {
+            place(&mut map, &catalogue, hex, "stone-mountain", 0);
+        }
+        assert!((map.size() * map.size()) as usize > MAX_EXPANDED);
+        assert_eq!(find_path(&map, &costs, Hex::ZERO, goal), None);
+    }
+}
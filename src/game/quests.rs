@@ -0,0 +1,317 @@
+//! Placement goals attached to cells of the map, such as growing a forest or connecting a river to the sea.
+//!
+//! A few quests are active at any time. Quests are posted near the most recently placed tile and
+//! are checked after every placement. Completing one earns points or extra tiles in the deck,
+//! while a quest whose cell no longer has the right terrain is dropped. Quests are not saved.
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use crate::{screens::Screen, theme::prelude::*};
+
+use super::{
+    deck::{DeckAssets, DeckConfig, GameMode, TileDeck},
+    prelude::*,
+    region::Feature,
+    scoring::{Score, TilePlaced},
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<Quests>();
+    app.init_resource::<Quests>();
+    app.add_observer(update_quests);
+    app.add_systems(OnEnter(Screen::Gameplay), spawn_quest_list);
+    app.add_systems(PostUpdate, post_first_quests);
+    app.add_systems(Update, update_quest_label.run_if(resource_changed::<Quests>));
+}
+
+/// Number of quests that are active at the same time. Also the number of markers on the map.
+pub const MAX_QUESTS: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub enum QuestKind {
+    /// Grow the forest containing the quest's cell to `target` cells.
+    GrowForest { target: u32 },
+    /// Connect the river through the quest's cell to a lake or the sea.
+    ConnectRiver,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect)]
+pub enum Reward {
+    Points(u32),
+    /// Extra tiles at the bottom of the deck.
+    Tiles(u32),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Progress {
+    /// The quest's cell no longer has the terrain the quest is about.
+    Failed,
+    /// How far along the quest is, between 0 and 1.
+    Ongoing(f32),
+    Done,
+}
+
+#[derive(Clone, Debug, Reflect)]
+pub struct Quest {
+    /// The cell the quest is attached to.
+    pub hex: Hex,
+    pub kind: QuestKind,
+    pub reward: Reward,
+}
+
+impl Quest {
+    pub fn progress(&self, map: &HexMap) -> Progress {
+        match self.kind {
+            QuestKind::GrowForest { target } => match map.region(self.hex, Feature::Forest) {
+                None => Progress::Failed,
+                Some((_, region)) if region.size >= target => Progress::Done,
+                Some((_, region)) => Progress::Ongoing(region.size as f32 / target as f32),
+            },
+            QuestKind::ConnectRiver => match map.region(self.hex, Feature::River) {
+                None => Progress::Failed,
+                Some((_, region)) if region.touches(Feature::Lake) => Progress::Done,
+                Some(_) => Progress::Ongoing(0.0),
+            },
+        }
+    }
+
+    pub fn description(&self, map: &HexMap) -> String {
+        let goal = match self.kind {
+            QuestKind::GrowForest { target } => {
+                let size = map.region(self.hex, Feature::Forest).map_or(0, |(_, region)| region.size);
+                format!("Grow the forest to {target} tiles ({size}/{target})")
+            }
+            QuestKind::ConnectRiver => "Connect the river to the sea".to_string(),
+        };
+        match self.reward {
+            Reward::Points(points) => format!("{goal}: {points} points"),
+            Reward::Tiles(tiles) => format!("{goal}: {tiles} tiles"),
+        }
+    }
+
+    /// Tries to come up with a quest for one of the cells around `center`
+    /// that isn't already completed.
+    pub fn random(map: &HexMap, center: Hex, tiles_as_reward: bool, rng: &mut impl Rng) -> Option<Quest> {
+        for _ in 0..64 {
+            let offset = Hex::new(rng.gen_range(-12..=12), rng.gen_range(-12..=12));
+            if !(3..=12).contains(&offset.length()) {continue}
+            let hex = center + offset;
+
+            let kind = if let Some((_, forest)) = map.region(hex, Feature::Forest) {
+                QuestKind::GrowForest { target: (forest.size + rng.gen_range(10..=25)).next_multiple_of(5) }
+            } else if map.region(hex, Feature::River).is_some() {
+                QuestKind::ConnectRiver
+            } else {
+                continue;
+            };
+            let reward = if tiles_as_reward {
+                Reward::Tiles(rng.gen_range(3..=6))
+            } else {
+                Reward::Points(rng.gen_range(4..=10) * 5)
+            };
+            let quest = Quest {hex, kind, reward};
+            if matches!(quest.progress(map), Progress::Ongoing(_)) {
+                return Some(quest);
+            }
+        }
+        None
+    }
+}
+
+/// The quests the player is working on.
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+pub struct Quests {
+    pub active: Vec<Quest>,
+    /// Number of quests completed in this game.
+    pub completed: u32,
+}
+
+impl Quests {
+    /// Posts new quests around `center` until there are [`MAX_QUESTS`].
+    pub fn fill(&mut self, map: &HexMap, center: Hex, tiles_as_reward: bool, rng: &mut impl Rng) {
+        for _ in 0..MAX_QUESTS * 4 {
+            if self.active.len() >= MAX_QUESTS {break}
+            let Some(quest) = Quest::random(map, center, tiles_as_reward, rng) else {continue};
+            if self.active.iter().all(|other| map.texel(other.hex) != map.texel(quest.hex)) {
+                self.active.push(quest);
+            }
+        }
+    }
+
+    /// Drops the quests that failed, and takes out and returns the ones that were completed.
+    pub fn check(&mut self, map: &HexMap) -> Vec<Quest> {
+        let mut done = Vec::new();
+        self.active.retain(|quest| match quest.progress(map) {
+            Progress::Failed => false,
+            Progress::Ongoing(_) => true,
+            Progress::Done => {
+                done.push(quest.clone());
+                false
+            }
+        });
+        self.completed += done.len() as u32;
+        done
+    }
+
+    /// Map markers for the active quests, in the layout expected by `tilemap.wgsl`:
+    /// the cell's cube coordinates, and the kind of quest plus one (zero for no quest).
+    pub fn markers(&self) -> [Vec4; MAX_QUESTS] {
+        let mut res = [Vec4::ZERO; MAX_QUESTS];
+        for (marker, quest) in res.iter_mut().zip(&self.active) {
+            let kind = match quest.kind {
+                QuestKind::GrowForest { .. } => 1.0,
+                QuestKind::ConnectRiver => 2.0,
+            };
+            *marker = quest.hex.cube().as_vec3().extend(kind);
+        }
+        res
+    }
+}
+
+/// Marks the text listing the active quests.
+#[derive(Component)]
+struct QuestLabel;
+
+fn spawn_quest_list(mut commands: Commands) {
+    commands.spawn((
+        Name::new("Quest list"),
+        Node {
+            position_type: PositionType::Absolute,
+            left: Val::Px(8.0),
+            top: Val::Px(8.0),
+            ..default()
+        },
+        Pickable::IGNORE,
+        StateScoped(Screen::Gameplay),
+        children![(
+            widget::label(""),
+            QuestLabel,
+            Pickable::IGNORE,
+        )],
+    ));
+}
+
+/// Posts quests around the origin whenever a new map appears.
+fn post_first_quests(
+    map: Option<Res<HexMap>>,
+    mode: Res<GameMode>,
+    mut quests: ResMut<Quests>,
+    mut rng: ResMut<WorldRng>,
+) {
+    let Some(map) = map else {return};
+    if !map.is_added() {return}
+    *quests = Quests::default();
+    quests.fill(&map, Hex::ZERO, !mode.free_choice(), &mut **rng);
+}
+
+fn update_quests(
+    trigger: Trigger<TilePlaced>,
+    map: Option<Res<HexMap>>,
+    mode: Res<GameMode>,
+    mut quests: ResMut<Quests>,
+    mut score: ResMut<Score>,
+    mut deck: ResMut<TileDeck>,
+    deck_assets: Option<Res<DeckAssets>>,
+    deck_configs: Res<Assets<DeckConfig>>,
+    catalogue: Res<TileCatalogue>,
+    mut rng: ResMut<WorldRng>,
+) {
+    let Some(map) = map else {return};
+    for quest in quests.check(&map) {
+        info!("Completed quest: {}", quest.description(&map));
+        match quest.reward {
+            // Rewards are kept when the placement is undone, like the quest's completion.
+            Reward::Points(points) => score.total += points,
+            Reward::Tiles(count) => {
+                let config = deck_assets.as_ref().and_then(|assets| deck_configs.get(&assets.config));
+                if let Some(config) = config {
+                    deck.add_random(config, &catalogue, count, &mut **rng);
+                }
+            }
+        }
+    }
+    quests.fill(&map, trigger.hex, !mode.free_choice(), &mut **rng);
+}
+
+fn update_quest_label(
+    map: Option<Res<HexMap>>,
+    quests: Res<Quests>,
+    mut label: Query<&mut Text, With<QuestLabel>>,
+) {
+    let Some(map) = map else {return};
+    let text = quests.active.iter()
+        .map(|quest| quest.description(&map))
+        .collect::<Vec<_>>()
+        .join("\n");
+    for mut label in label.iter_mut() {
+        label.0.clone_from(&text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::hex_map::Cell, *};
+
+    fn grassland(catalogue: &TileCatalogue) -> HexMap {
+        let grass = uvec2(catalogue.roles.grassland, 0);
+        HexMap::new(MapShape::new(8, MapBoundary::Bounded), grass, catalogue, &mut *WorldRng::new(WorldSeed(1)))
+    }
+
+    fn tile(catalogue: &TileCatalogue, id: &str, rotation: u32) -> Cell {
+        Cell::new(uvec2(catalogue.find(id).unwrap(), rotation), 1)
+    }
+
+    #[test]
+    fn forests_grow_to_their_target() {
+        let catalogue = TileCatalogue::standard();
+        let mut map = grassland(&catalogue);
+        let hex = Hex::ZERO;
+        let quest = Quest {hex, kind: QuestKind::GrowForest {target: 4}, reward: Reward::Points(10)};
+        assert_eq!(quest.progress(&map), Progress::Failed);
+
+        map.set(hex, tile(&catalogue, "grass-forest", 0));
+        assert_eq!(quest.progress(&map), Progress::Ongoing(0.25));
+        for neighbour in &hex.neighbours()[..3] {
+            map.set(*neighbour, tile(&catalogue, "grass-forest", 0));
+        }
+        assert_eq!(quest.progress(&map), Progress::Done);
+    }
+
+    #[test]
+    fn rivers_connect_to_the_sea() {
+        let catalogue = TileCatalogue::standard();
+        let mut map = grassland(&catalogue);
+        let hex = Hex::ZERO;
+        let quest = Quest {hex, kind: QuestKind::ConnectRiver, reward: Reward::Tiles(3)};
+        map.set(hex, tile(&catalogue, "river-straight", 0));
+        assert_eq!(quest.progress(&map), Progress::Ongoing(0.0));
+
+        // The estuary's river side faces back toward the quest's cell.
+        map.set(hex.neighbours()[0], tile(&catalogue, "water-river", 3));
+        assert_eq!(quest.progress(&map), Progress::Done);
+    }
+
+    #[test]
+    fn checking_takes_out_completed_and_failed_quests() {
+        let catalogue = TileCatalogue::standard();
+        let mut map = grassland(&catalogue);
+        let [east, west, ..] = Hex::ZERO.neighbours();
+        map.set(Hex::ZERO, tile(&catalogue, "grass-forest", 0));
+        map.set(west, tile(&catalogue, "river-straight", 0));
+        let forest = Quest {hex: Hex::ZERO, kind: QuestKind::GrowForest {target: 2}, reward: Reward::Points(10)};
+        let river = Quest {hex: west, kind: QuestKind::ConnectRiver, reward: Reward::Tiles(3)};
+        let mut quests = Quests {active: vec![forest, river], completed: 0};
+        assert!(quests.check(&map).is_empty());
+        assert_eq!(quests.active.len(), 2);
+
+        map.set(east, tile(&catalogue, "grass-forest", 0));
+        map.set(west, tile(&catalogue, "grass", 0));
+        let done = quests.check(&map);
+        assert_eq!(done.len(), 1);
+        assert_eq!(done[0].kind, QuestKind::GrowForest {target: 2});
+        assert!(quests.active.is_empty());
+        assert_eq!(quests.completed, 1);
+    }
+}
@@ -0,0 +1,606 @@
+//! Connected regions of terrain, such as a forest, a lake, a river network or a road.
+//!
+//! Two neighbouring cells belong to the same region when the terrain crosses the side
+//! they share, taking the rotation of both tiles into account. The regions are labelled
+//! once when a map is created, after which [`HexMap::set`] keeps them up to date by only
+//! visiting the regions around the changed cell. When the window of an infinite map moves,
+//! only the regions that reach into the chunks that were swapped are labelled again.
+
+use std::collections::{HashMap, VecDeque};
+
+use bevy::{
+    prelude::*,
+    tasks::{ComputeTaskPool, TaskPool},
+};
+
+use super::{hex_map::Cell, prelude::*};
+
+/// Terrain that forms regions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect)]
+pub enum Feature {
+    Forest,
+    Lake,
+    River,
+    Road,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 4] = [Feature::Forest, Feature::Lake, Feature::River, Feature::Road];
+
+    pub fn index(self) -> usize {
+        self as usize
+    }
+
+    /// The sides of a tile crossed by this feature, as a bitmask.
+    fn sides(self, catalogue: &TileCatalogue, tile: UVec2) -> u8 {
+        let edges = catalogue.edges(tile);
+        let forest = catalogue.tiles[tile.x as usize].has_tag("forest");
+        (0..6).filter(|&side| match self {
+            Feature::Forest => forest,
+            Feature::Lake => matches!(edges.get(side), Edge::Water | Edge::CoastNext | Edge::CoastPrev),
+            Feature::River => edges.get(side) == Edge::River,
+            Feature::Road => edges.get(side) == Edge::Path,
+        }).fold(0, |mask, side| mask | 1 << side)
+    }
+}
+
+/// Identifies a region of a single feature. Ids are reused once a region disappears.
+pub type RegionId = u32;
+
+const NONE: RegionId = RegionId::MAX;
+
+/// Summary of a region.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RegionInfo {
+    /// Number of cells.
+    pub size: u32,
+    /// Number of sides where the feature runs into a neighbour that doesn't continue it.
+    /// For forests, these are the sides that border open grassland, which the forest could still spread over.
+    pub loose_ends: u32,
+    /// Number of sides where the feature runs off the window of an infinite map,
+    /// beyond which it isn't known how the region goes on.
+    pub beyond_window: u32,
+    /// Number of cells that also contain each feature, indexed by [`Feature::index`].
+    pub overlap: [u32; 4],
+}
+
+impl RegionInfo {
+    /// Whether the region has no loose ends, and doesn't reach past the window of an infinite map.
+    pub fn closed(&self) -> bool {
+        self.loose_ends == 0 && self.beyond_window == 0
+    }
+
+    /// Whether any cell of the region also contains `feature`.
+    pub fn touches(&self, feature: Feature) -> bool {
+        self.overlap[feature.index()] > 0
+    }
+
+    fn add(&mut self, other: &RegionInfo) {
+        self.size += other.size;
+        self.loose_ends += other.loose_ends;
+        self.beyond_window += other.beyond_window;
+        for (a, b) in self.overlap.iter_mut().zip(other.overlap) {
+            *a += b;
+        }
+    }
+
+    fn sub(&mut self, other: &RegionInfo) {
+        self.size -= other.size;
+        self.loose_ends -= other.loose_ends;
+        self.beyond_window -= other.beyond_window;
+        for (a, b) in self.overlap.iter_mut().zip(other.overlap) {
+            *a -= b;
+        }
+    }
+}
+
+/// The region labels of a single feature.
+#[derive(Clone, Default)]
+struct Layer {
+    labels: Vec<RegionId>,
+    regions: Vec<RegionInfo>,
+    free: Vec<RegionId>,
+}
+
+impl Layer {
+    fn alloc(&mut self) -> RegionId {
+        match self.free.pop() {
+            Some(id) => id,
+            None => {
+                self.regions.push(RegionInfo::default());
+                self.regions.len() as RegionId - 1
+            }
+        }
+    }
+
+    fn release(&mut self, id: RegionId) {
+        debug_assert_eq!(self.regions[id as usize], RegionInfo::default(), "released region still has cells");
+        self.free.push(id);
+    }
+}
+
+/// Region labels for every feature, indexed like the cells of a [`HexMap`].
+#[derive(Clone)]
+pub struct Regions {
+    shape: MapShape,
+    /// Sides crossed by each feature for every tile and rotation, indexed by `tile * 6 + rotation`.
+    tile_sides: Vec<[u8; 4]>,
+    /// Whether each tile is open grassland, indexed by tile id.
+    tile_open: Vec<bool>,
+    /// Sides crossed by each feature for every cell.
+    sides: Vec<[u8; 4]>,
+    /// Whether each cell is open grassland, which leaves the forests around it open.
+    open: Vec<bool>,
+    layers: [Layer; 4],
+}
+
+impl Regions {
+    /// Labels all regions of the given cells, in row-major texel order.
+    pub fn new(shape: MapShape, cells: &[Cell], catalogue: &TileCatalogue) -> Self {
+        let tile_sides = (0..catalogue.len() * 6)
+            .map(|index| Feature::ALL.map(|feature| feature.sides(catalogue, uvec2(index / 6, index % 6))))
+            .collect();
+        let tile_open = catalogue.tiles.iter().map(|tile| tile.has_tag("grassland")).collect();
+        Self::label(shape, cells, tile_sides, tile_open)
+    }
+
+    /// Labels the regions again after the cells at `replaced` and the shape of the map changed, as they do
+    /// when the window of an infinite map moves. Only the regions that reach into those cells or their
+    /// neighbours are taken apart and labelled again, the others can't have changed.
+    pub fn replace(&mut self, shape: MapShape, cells: &[Cell], replaced: &[usize]) {
+        let mut affected = vec![false; self.sides.len()];
+        for &index in replaced {
+            affected[index] = true;
+            for side in 0..6 {
+                affected[self.neighbour(index, side)] = true;
+            }
+        }
+        let affected: Vec<usize> = (0..affected.len()).filter(|&index| affected[index]).collect();
+
+        // Take apart the regions around the replaced cells while the old cells can still be followed.
+        let cleared = Feature::ALL.map(|feature| self.clear(feature, &affected));
+        self.shape = shape;
+        for &index in replaced {
+            let cell = cells[index];
+            self.sides[index] = self.tile_sides[cell.tile as usize * 6 + cell.rotation as usize];
+            self.open[index] = self.tile_open[cell.tile as usize];
+        }
+        for (feature, cleared) in Feature::ALL.into_iter().zip(cleared) {
+            for &start in cleared.iter().chain(replaced) {
+                self.fill(feature, start);
+            }
+        }
+    }
+
+    fn label(shape: MapShape, cells: &[Cell], tile_sides: Vec<[u8; 4]>, tile_open: Vec<bool>) -> Self {
+        let sides = cells.iter().map(|cell| tile_sides[cell.tile as usize * 6 + cell.rotation as usize]).collect();
+        let open = cells.iter().map(|cell| tile_open[cell.tile as usize]).collect();
+        let mut res = Self {
+            shape,
+            tile_sides,
+            tile_open,
+            sides,
+            open,
+            layers: default(),
+        };
+
+        let pool = ComputeTaskPool::get_or_init(TaskPool::default);
+        let this = &res;
+        let layers = pool.scope(|scope| {
+            for feature in Feature::ALL {
+                scope.spawn(async move { this.label_all(feature) });
+            }
+        });
+        for (layer, labelled) in res.layers.iter_mut().zip(layers) {
+            *layer = labelled;
+        }
+        res
+    }
+
+    /// The region of `feature` containing the cell at `index`, if the cell has that feature.
+    pub fn get(&self, feature: Feature, index: usize) -> Option<(RegionId, RegionInfo)> {
+        let layer = &self.layers[feature.index()];
+        let id = layer.labels[index];
+        (id != NONE).then(|| (id, layer.regions[id as usize]))
+    }
+
+    /// Updates the regions after the cell at `index` changed to `cell`.
+    pub fn update(&mut self, index: usize, cell: Cell) {
+        let old = self.sides[index];
+        let new = self.tile_sides[cell.tile as usize * 6 + cell.rotation as usize];
+        let open = self.tile_open[cell.tile as usize];
+        if old == new && self.open[index] == open {return}
+
+        // The statistics of the changed cell and the neighbouring sides depend on the cell's contents.
+        // They are taken out before changing anything and added back afterwards, and are not
+        // moved along when cells change regions in between.
+        let mut affected = vec![index];
+        for side in 0..6 {
+            let neighbour = self.neighbour(index, side);
+            if !affected.contains(&neighbour) {
+                affected.push(neighbour);
+            }
+        }
+        for feature in Feature::ALL {
+            for &cell in &affected {
+                self.account(feature, cell, false);
+            }
+        }
+        for feature in Feature::ALL {
+            if old[feature.index()] != new[feature.index()] {
+                self.detach(feature, index, &affected);
+            }
+        }
+        self.sides[index] = new;
+        self.open[index] = open;
+        for feature in Feature::ALL {
+            if old[feature.index()] != new[feature.index()] && new[feature.index()] != 0 {
+                self.attach(feature, index, &affected);
+            }
+        }
+        for feature in Feature::ALL {
+            for &cell in &affected {
+                self.account(feature, cell, true);
+            }
+        }
+    }
+
+    /// The neighbour on `side`, wrapping around even on a bounded map. Features never cross its edges,
+    /// see [`Regions::connected`].
+    fn neighbour(&self, index: usize, side: usize) -> usize {
+        Hex::from_index(index, self.shape.size).neighbour(side).index(self.shape.size)
+    }
+
+    /// Whether `feature` crosses from the cell at `index` into its neighbour on `side`.
+    fn connected(&self, feature: Feature, index: usize, side: usize) -> bool {
+        let f = feature.index();
+        self.sides[index][f] & 1 << side != 0
+            && self.shape.neighbour(index, side).is_some_and(|neighbour| self.sides[neighbour][f] & 1 << ((side + 3) % 6) != 0)
+    }
+
+    /// What a single cell contributes to the summary of its region.
+    fn stats(&self, feature: Feature, index: usize) -> RegionInfo {
+        let f = feature.index();
+        let crossed = |side: usize| self.sides[index][f] & 1 << side != 0;
+        // The edge of the window is where the known part of an infinite map ends, not where the map does.
+        let beyond_window = if self.shape.boundary != MapBoundary::Infinite {0} else {
+            (0..6).filter(|&side| crossed(side) && self.shape.neighbour(index, side).is_none()).count() as u32
+        };
+        let loose_ends = if feature == Feature::Forest {
+            (0..6).filter(|&side| self.shape.neighbour(index, side).is_some_and(|neighbour| self.open[neighbour])).count() as u32
+        } else {
+            (0..6).filter(|&side| crossed(side) && !self.connected(feature, index, side)).count() as u32 - beyond_window
+        };
+        RegionInfo {
+            size: 1,
+            loose_ends,
+            beyond_window,
+            overlap: self.sides[index].map(|sides| (sides != 0) as u32),
+        }
+    }
+
+    /// Adds or removes the statistics of a cell to or from its region.
+    fn account(&mut self, feature: Feature, index: usize, add: bool) {
+        let id = self.layers[feature.index()].labels[index];
+        if id == NONE {return}
+        let stats = self.stats(feature, index);
+        let info = &mut self.layers[feature.index()].regions[id as usize];
+        if add {info.add(&stats)} else {info.sub(&stats)}
+    }
+
+    /// Moves a cell to another region, carrying its statistics along unless they are taken out.
+    fn relabel(&mut self, feature: Feature, index: usize, to: RegionId, unaccounted: &[usize]) {
+        let layer = &self.layers[feature.index()];
+        let from = layer.labels[index];
+        if !unaccounted.contains(&index) {
+            let stats = self.stats(feature, index);
+            let layer = &mut self.layers[feature.index()];
+            layer.regions[from as usize].sub(&stats);
+            layer.regions[to as usize].add(&stats);
+        }
+        self.layers[feature.index()].labels[index] = to;
+    }
+
+    /// Removes the regions of `feature` that contain any of the cells in `affected`, returning their cells.
+    fn clear(&mut self, feature: Feature, affected: &[usize]) -> Vec<usize> {
+        let f = feature.index();
+        let mut cleared = Vec::new();
+        for &start in affected {
+            let id = self.layers[f].labels[start];
+            if id == NONE {continue}
+            self.layers[f].labels[start] = NONE;
+            let mut stack = vec![start];
+            while let Some(index) = stack.pop() {
+                cleared.push(index);
+                for side in 0..6 {
+                    if !self.connected(feature, index, side) {continue}
+                    let neighbour = self.neighbour(index, side);
+                    if self.layers[f].labels[neighbour] == id {
+                        self.layers[f].labels[neighbour] = NONE;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+            self.layers[f].regions[id as usize] = RegionInfo::default();
+            self.layers[f].release(id);
+        }
+        cleared
+    }
+
+    /// Labels the region of `feature` containing the cell at `start`, unless it is already labelled.
+    /// None of the cells connected to it may be labelled yet.
+    fn fill(&mut self, feature: Feature, start: usize) {
+        let f = feature.index();
+        if self.sides[start][f] == 0 || self.layers[f].labels[start] != NONE {return}
+        let id = self.layers[f].alloc();
+        self.layers[f].labels[start] = id;
+        let mut stack = vec![start];
+        while let Some(index) = stack.pop() {
+            let stats = self.stats(feature, index);
+            self.layers[f].regions[id as usize].add(&stats);
+            for side in 0..6 {
+                if !self.connected(feature, index, side) {continue}
+                let neighbour = self.neighbour(index, side);
+                if self.layers[f].labels[neighbour] == NONE {
+                    self.layers[f].labels[neighbour] = id;
+                    stack.push(neighbour);
+                }
+            }
+        }
+    }
+
+    /// Labels every region of `feature` from scratch.
+    fn label_all(&self, feature: Feature) -> Layer {
+        let f = feature.index();
+        let mut layer = Layer {
+            labels: vec![NONE; self.sides.len()],
+            ..default()
+        };
+        let mut stack = Vec::new();
+        for start in 0..self.sides.len() {
+            if self.sides[start][f] == 0 || layer.labels[start] != NONE {continue}
+            let id = layer.alloc();
+            layer.labels[start] = id;
+            stack.push(start);
+            while let Some(index) = stack.pop() {
+                layer.regions[id as usize].add(&self.stats(feature, index));
+                for side in 0..6 {
+                    if !self.connected(feature, index, side) {continue}
+                    let neighbour = self.neighbour(index, side);
+                    if layer.labels[neighbour] == NONE {
+                        layer.labels[neighbour] = id;
+                        stack.push(neighbour);
+                    }
+                }
+            }
+        }
+        layer
+    }
+
+    /// Takes the cell at `index` out of its region, splitting the region if the cell held it together.
+    fn detach(&mut self, feature: Feature, index: usize, unaccounted: &[usize]) {
+        let f = feature.index();
+        let id = self.layers[f].labels[index];
+        if id == NONE {return}
+        self.layers[f].labels[index] = NONE;
+
+        let mut entries = Vec::new();
+        for side in 0..6 {
+            let neighbour = self.neighbour(index, side);
+            if self.connected(feature, index, side) && neighbour != index && !entries.contains(&neighbour) {
+                entries.push(neighbour);
+            }
+        }
+        if entries.is_empty() {
+            // The cell was the whole region.
+            self.layers[f].regions[id as usize] = RegionInfo::default();
+            self.layers[f].release(id);
+            return;
+        }
+        if entries.len() == 1 {return}
+
+        // Search from every entry at the same pace, merging searches that meet. Once all but one
+        // search ran out of cells, those searches found the parts that were split off, and the
+        // remaining one may keep the region's id. This only visits the smaller parts of the region,
+        // apart from a few cells, which keeps cutting off a bay from the ocean cheap.
+        let mut parent: Vec<usize> = (0..entries.len()).collect();
+        fn find(parent: &mut [usize], mut group: usize) -> usize {
+            while parent[group] != group {
+                parent[group] = parent[parent[group]];
+                group = parent[group];
+            }
+            group
+        }
+        let mut owner: HashMap<usize, usize> = entries.iter().enumerate().map(|(group, &cell)| (cell, group)).collect();
+        let mut queues: Vec<VecDeque<usize>> = entries.iter().map(|&cell| VecDeque::from([cell])).collect();
+        loop {
+            let roots: Vec<usize> = (0..entries.len()).filter(|&group| parent[group] == group).collect();
+            if roots.len() == 1 {return}
+            let active: Vec<usize> = roots.iter().copied().filter(|&group| !queues[group].is_empty()).collect();
+            if active.len() <= 1 {
+                let mut parts: HashMap<usize, Vec<usize>> = HashMap::new();
+                for (&cell, &group) in &owner {
+                    parts.entry(find(&mut parent, group)).or_default().push(cell);
+                }
+                // Keep the id for the part that is still being searched, or for the largest if all are done.
+                let keep = active.first().copied().unwrap_or_else(|| {
+                    *parts.iter().max_by_key(|(_, cells)| cells.len()).unwrap().0
+                });
+                for (root, cells) in parts {
+                    if root == keep {continue}
+                    let new_id = self.layers[f].alloc();
+                    for cell in cells {
+                        self.relabel(feature, cell, new_id, unaccounted);
+                    }
+                }
+                return;
+            }
+            for root in active {
+                let root = find(&mut parent, root);
+                let Some(cell) = queues[root].pop_front() else {continue};
+                for side in 0..6 {
+                    if !self.connected(feature, cell, side) {continue}
+                    let neighbour = self.neighbour(cell, side);
+                    if self.layers[f].labels[neighbour] != id {continue}
+                    match owner.get(&neighbour) {
+                        None => {
+                            owner.insert(neighbour, root);
+                            queues[root].push_back(neighbour);
+                        }
+                        Some(&group) => {
+                            let other = find(&mut parent, group);
+                            if other != root {
+                                parent[other] = root;
+                                let rest = std::mem::take(&mut queues[other]);
+                                queues[root].extend(rest);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds the cell at `index` to the regions around it, merging them if the cell connects several.
+    fn attach(&mut self, feature: Feature, index: usize, unaccounted: &[usize]) {
+        let f = feature.index();
+        let mut ids: Vec<(RegionId, usize)> = Vec::new();
+        for side in 0..6 {
+            if !self.connected(feature, index, side) {continue}
+            let neighbour = self.neighbour(index, side);
+            let id = self.layers[f].labels[neighbour];
+            if id != NONE && !ids.iter().any(|&(other, _)| other == id) {
+                ids.push((id, neighbour));
+            }
+        }
+        let Some(&(target, _)) = ids.iter().max_by_key(|(id, _)| self.layers[f].regions[*id as usize].size) else {
+            let id = self.layers[f].alloc();
+            self.layers[f].labels[index] = id;
+            return;
+        };
+
+        // Merge the smaller regions into the largest one.
+        for &(id, start) in &ids {
+            if id == target {continue}
+            let mut stack = vec![start];
+            self.relabel(feature, start, target, unaccounted);
+            while let Some(cell) = stack.pop() {
+                for side in 0..6 {
+                    if !self.connected(feature, cell, side) {continue}
+                    let neighbour = self.neighbour(cell, side);
+                    if self.layers[f].labels[neighbour] == id {
+                        self.relabel(feature, neighbour, target, unaccounted);
+                        stack.push(neighbour);
+                    }
+                }
+            }
+            self.layers[f].release(id);
+        }
+        self.layers[f].labels[index] = target;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use rand::Rng;
+
+    use super::{super::simulation::CHUNK_SIZE, *};
+
+    /// Checks that two labellings divide the cells into the same regions with the same summaries.
+    fn assert_same(a: &Regions, b: &Regions) {
+        for feature in Feature::ALL {
+            let mut ids = HashMap::new();
+            for index in 0..a.sides.len() {
+                let (x, y) = (a.get(feature, index), b.get(feature, index));
+                assert_eq!(x.map(|(_, info)| info), y.map(|(_, info)| info), "{feature:?} at {index}");
+                if let (Some((x, _)), Some((y, _))) = (x, y) {
+                    assert_eq!(*ids.entry(x).or_insert(y), y, "{feature:?} at {index}");
+                }
+            }
+        }
+    }
+
+    /// Number of regions of `feature`.
+    fn count(regions: &Regions, feature: Feature) -> usize {
+        let ids: HashSet<RegionId> = (0..regions.sides.len())
+            .filter_map(|index| regions.get(feature, index).map(|(id, _)| id))
+            .collect();
+        ids.len()
+    }
+
+    #[test]
+    fn updates_match_labelling_from_scratch() {
+        let catalogue = TileCatalogue::standard();
+        // Mostly water and forest, so that single cells often split or join large regions.
+        let palette: Vec<u32> = ["water", "water", "water", "grass-forest", "grass-forest", "grass", "water-straight",
+            "river-intersection", "grass-path-intersection", "bridge-path-a"]
+            .into_iter().map(|id| catalogue.find(id).unwrap()).collect();
+        for (seed, boundary) in [(1, MapBoundary::Wrapping), (2, MapBoundary::Bounded)] {
+            let mut rng = WorldRng::new(WorldSeed(seed));
+            let random_cell = |rng: &mut WorldRng| Cell::new(uvec2(palette[rng.gen_range(0..palette.len())], rng.gen_range(0..6)), 1);
+            let shape = MapShape::new(12, boundary);
+            let mut cells: Vec<Cell> = (0..shape.size * shape.size).map(|_| random_cell(&mut rng)).collect();
+            let mut regions = Regions::new(shape, &cells, &catalogue);
+
+            let (mut splits, mut joins) = (0, 0);
+            for _ in 0..400 {
+                let index = rng.gen_range(0..cells.len());
+                let cell = random_cell(&mut rng);
+                let before = Feature::ALL.map(|feature| (regions.get(feature, index).is_some(), count(&regions, feature)));
+                cells[index] = cell;
+                regions.update(index, cell);
+                assert_same(&regions, &Regions::new(shape, &cells, &catalogue));
+                // A cell that had the feature and leaves more regions split one, one that has it and leaves fewer joined some.
+                for (feature, (had, count_before)) in Feature::ALL.into_iter().zip(before) {
+                    let (has, count_after) = (regions.get(feature, index).is_some(), count(&regions, feature));
+                    if had && count_after > count_before {splits += 1}
+                    if has && count_after < count_before {joins += 1}
+                }
+            }
+            assert!(splits > 0 && joins > 0, "{boundary:?}: {splits} splits, {joins} joins");
+        }
+    }
+
+    #[test]
+    fn moving_the_window_matches_labelling_from_scratch() {
+        let catalogue = TileCatalogue::standard();
+        let mut rng = WorldRng::new(WorldSeed(7));
+        let mut random_cell = || Cell::new(uvec2(rng.gen_range(0..catalogue.len()), rng.gen_range(0..6)), 1);
+
+        let shape = MapShape::new(4 * CHUNK_SIZE, MapBoundary::Infinite);
+        let mut cells: Vec<Cell> = (0..shape.size * shape.size).map(|_| random_cell()).collect();
+        let mut regions = Regions::new(shape, &cells, &catalogue);
+
+        // Move one chunk along x, which swaps out the column of chunks that is now behind the window.
+        let moved = shape.with_origin(shape.origin() + IVec2::new(CHUNK_SIZE as i32, 0));
+        let replaced: Vec<usize> = (0..cells.len())
+            .filter(|&index| shape.hex(index) != moved.hex(index))
+            .collect();
+        assert_eq!(replaced.len() as u32, shape.size * CHUNK_SIZE);
+        for &index in &replaced {
+            cells[index] = random_cell();
+        }
+        regions.replace(moved, &cells, &replaced);
+        assert_same(&regions, &Regions::new(moved, &cells, &catalogue));
+    }
+
+    #[test]
+    fn regions_reaching_past_the_window_are_open() {
+        let catalogue = TileCatalogue::standard();
+        let ocean = Cell::new(uvec2(catalogue.roles.ocean, 0), 1);
+        let cells = vec![ocean; (CHUNK_SIZE * CHUNK_SIZE) as usize];
+
+        let bounded = Regions::new(MapShape::new(CHUNK_SIZE, MapBoundary::Bounded), &cells, &catalogue);
+        let (_, info) = bounded.get(Feature::Lake, 0).unwrap();
+        assert!(!info.closed());
+        assert_eq!(info.beyond_window, 0);
+
+        let infinite = Regions::new(MapShape::new(CHUNK_SIZE, MapBoundary::Infinite), &cells, &catalogue);
+        let (_, info) = infinite.get(Feature::Lake, 0).unwrap();
+        assert!(!info.closed());
+        assert_eq!(info.loose_ends, 0);
+        assert!(info.beyond_window > 0);
+    }
+}
@@ -0,0 +1,550 @@
+//! Saving and loading of the map to a versioned, compressed file.
+//!
+//! The file starts with a 4 byte magic and a little endian `u16` format version,
+//! followed by a zlib-compressed body containing the map, camera, selected tile, score and deck.
+//! Version 1 files have no score, files before version 3 have no deck, and maps in files
+//! before version 4 always wrap around. Version 5 adds infinite maps, which also store
+//! the edited chunks outside of their window.
+//!
+//! From version 6 the body starts with the names of all tiles, so that tile ids in the file can
+//! be mapped to those of the running game, which depend on the installed tile packs.
+//! Older files only use the game's own tiles, whose ids haven't changed.
+//! Version 7 adds the state of the [`WorldRng`], so that a loaded game deals the same tiles
+//! as it would have without saving.
+//!
+//! Map sizes must be a multiple of [`CHUNK_SIZE`], in every version, and of twice that for bounded maps.
+//! The window of an infinite map starts on the corner of a chunk.
+
+use std::{
+    fmt,
+    fs,
+    io::{self, Read, Write},
+};
+
+use bevy::prelude::*;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use super::{
+    biome::BiomeSettings,
+    deck::TileDeck,
+    hex_map::Cell,
+    prelude::*,
+    scoring::Score,
+    simulation::CHUNK_SIZE,
+    streaming::{ChunkSource, ChunkStore},
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_observer(save_game);
+    app.add_observer(load_game);
+}
+
+const SAVE_PATH: &str = "sprawl.save";
+const MAGIC: [u8; 4] = *b"SPRL";
+const VERSION: u16 = 7;
+
+/// Trigger this to write the current game to the save file.
+#[derive(Event)]
+pub struct SaveGame;
+
+/// Trigger this to replace the current game with the contents of the save file.
+#[derive(Event)]
+pub struct LoadGame;
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    NotASave,
+    UnsupportedVersion(u16),
+    Corrupt,
+    /// The map uses a tile that isn't in the catalogue, such as one from a pack that was removed.
+    MissingTile(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "{err}"),
+            SaveError::NotASave => write!(f, "not a save file"),
+            SaveError::UnsupportedVersion(version) => write!(f, "unsupported save version {version}"),
+            SaveError::Corrupt => write!(f, "save file is corrupt"),
+            SaveError::MissingTile(name) => write!(f, "the map uses tile {name:?}, which isn't installed"),
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err: io::Error) -> Self {
+        SaveError::Io(err)
+    }
+}
+
+/// Everything that is stored in a save file.
+pub struct SaveData {
+    pub map: HexMap,
+    pub camera: Transform,
+    pub selected_tile: UVec2,
+    pub score: u32,
+    /// `None` when loaded from a file that predates decks.
+    pub deck: Option<TileDeck>,
+    /// Where the chunks of an infinite map come from: the seed, and whether they have biomes.
+    pub chunk_source: (WorldSeed, bool),
+    /// Chunks in the window of an infinite map that were edited, see [`HexMap::edited_chunks`].
+    pub edited: Vec<IVec2>,
+    /// Edited chunks outside of the window of an infinite map, with their cells.
+    pub stored: Vec<(IVec2, Vec<Cell>)>,
+    /// `None` when loaded from a file that predates storing it.
+    pub rng: Option<WorldRng>,
+}
+
+impl SaveData {
+    pub fn encode(&self, catalogue: &TileCatalogue) -> Vec<u8> {
+        self.encode_version(catalogue, VERSION)
+    }
+
+    /// Writes the file in the layout of an older version, leaving out whatever that version can't hold,
+    /// so that tests can cover every version that [`SaveData::decode`] reads.
+    fn encode_version(&self, catalogue: &TileCatalogue, version: u16) -> Vec<u8> {
+        let mut body = Vec::new();
+        if version >= 6 {
+            body.extend(catalogue.len().to_le_bytes());
+            for tile in &catalogue.tiles {
+                let name = tile.id();
+                body.push(u8::try_from(name.len()).expect("tile ids are checked when their catalogue loads"));
+                body.extend(name.as_bytes());
+            }
+        }
+        body.extend(self.map.size().to_le_bytes());
+        if version >= 4 {
+            body.push(match self.map.shape().boundary {
+                MapBoundary::Wrapping => 0,
+                MapBoundary::Bounded => 1,
+                MapBoundary::Infinite => 2,
+            });
+        }
+        if version >= 5 {
+            body.extend(self.map.shape().origin().x.to_le_bytes());
+            body.extend(self.map.shape().origin().y.to_le_bytes());
+        }
+        body.extend(self.map.texture_data());
+        let camera = self.camera.translation.to_array().into_iter()
+            .chain(self.camera.rotation.to_array())
+            .chain(self.camera.scale.to_array());
+        for value in camera {
+            body.extend(value.to_le_bytes());
+        }
+        body.extend(self.selected_tile.x.to_le_bytes());
+        body.extend(self.selected_tile.y.to_le_bytes());
+        if version >= 2 {
+            body.extend(self.score.to_le_bytes());
+        }
+        if version >= 3 {
+            let deck = self.deck.as_ref().map_or(&[][..], |deck| deck.tiles());
+            body.extend((deck.len() as u32).to_le_bytes());
+            // Tile ids fit in a byte, as they do in the map texture.
+            body.extend(deck.iter().map(|&tile| u8::try_from(tile).expect("deck holds a tile that isn't in the catalogue")));
+        }
+        if version >= 5 {
+            body.extend(self.chunk_source.0.0.to_le_bytes());
+            body.push(self.chunk_source.1 as u8);
+            body.extend((self.edited.len() as u32).to_le_bytes());
+            for chunk in &self.edited {
+                body.extend(chunk.x.to_le_bytes());
+                body.extend(chunk.y.to_le_bytes());
+            }
+            body.extend((self.stored.len() as u32).to_le_bytes());
+            for (chunk, cells) in &self.stored {
+                body.extend(chunk.x.to_le_bytes());
+                body.extend(chunk.y.to_le_bytes());
+                body.extend(cells.iter().flat_map(|cell| cell.to_texel()));
+            }
+        }
+        if version >= 7 {
+            body.push(self.rng.is_some() as u8);
+            if let Some((seed, stream, word_pos)) = self.rng.as_ref().map(WorldRng::state) {
+                body.extend(seed);
+                body.extend(stream.to_le_bytes());
+                body.extend(word_pos.to_le_bytes());
+            }
+        }
+
+        let mut res = Vec::from(MAGIC);
+        res.extend(version.to_le_bytes());
+        let mut encoder = ZlibEncoder::new(res, Compression::default());
+        encoder.write_all(&body).expect("writing to a Vec can't fail");
+        encoder.finish().expect("writing to a Vec can't fail")
+    }
+
+    pub fn decode(data: &[u8], catalogue: &TileCatalogue) -> Result<Self, SaveError> {
+        if data.len() < 6 || data[0..4] != MAGIC {
+            return Err(SaveError::NotASave);
+        }
+        let version = u16::from_le_bytes([data[4], data[5]]);
+        if version == 0 || version > VERSION {
+            return Err(SaveError::UnsupportedVersion(version));
+        }
+        // The body is inflated as it is read, so that a corrupt file can't make it take more memory
+        // than the sizes in its header call for.
+        let mut reader = Reader(ZlibDecoder::new(&data[6..]));
+
+        // The id in the running game of each tile id in the file, or the name of the tile if it isn't installed.
+        let ids: Vec<Result<u8, String>> = if version >= 6 {
+            (0..reader.u32()?).map(|_| {
+                let len = reader.bytes(1)?[0];
+                let name = String::from_utf8(reader.bytes(len as usize)?).map_err(|_| SaveError::Corrupt)?;
+                Ok(catalogue.find(&name).map(|id| id as u8).ok_or(name))
+            }).collect::<Result<_, SaveError>>()?
+        } else {
+            (0..catalogue.len()).map(|tile| Ok(tile as u8)).collect()
+        };
+        let remap = |tile: u8| match ids.get(tile as usize) {
+            Some(Ok(id)) => Ok(*id),
+            Some(Err(name)) => Err(SaveError::MissingTile(name.clone())),
+            None => Err(SaveError::Corrupt),
+        };
+
+        let size = reader.u32()?;
+        // Maps are simulated and streamed in whole chunks.
+        if size == 0 || size % CHUNK_SIZE != 0 {
+            return Err(SaveError::Corrupt);
+        }
+        let boundary = if version >= 4 {
+            match reader.bytes(1)?[0] {
+                0 => MapBoundary::Wrapping,
+                1 => MapBoundary::Bounded,
+                2 if version >= 5 => MapBoundary::Infinite,
+                _ => return Err(SaveError::Corrupt),
+            }
+        } else {
+            MapBoundary::Wrapping
+        };
+        // The edges of a bounded map must run along the edges of chunks, see [`MapShape::size`].
+        if boundary == MapBoundary::Bounded && size % (2 * CHUNK_SIZE) != 0 {
+            return Err(SaveError::Corrupt);
+        }
+        let mut shape = MapShape::new(size, boundary);
+        if version >= 5 {
+            shape = shape.with_origin(reader.ivec2()?);
+        }
+        // The window of an infinite map moves by whole chunks, see [`HexMap::move_window`].
+        if boundary == MapBoundary::Infinite && shape.origin() % CHUNK_SIZE as i32 != IVec2::ZERO {
+            return Err(SaveError::Corrupt);
+        }
+        let len = (size as usize).checked_mul(size as usize).and_then(|cells| cells.checked_mul(4)).ok_or(SaveError::Corrupt)?;
+        let mut cells = reader.bytes(len)?;
+        for texel in cells.chunks_exact_mut(4) {
+            texel[0] = remap(texel[0])?;
+            check_rotation(texel[1])?;
+        }
+        let map = HexMap::from_texture_data(shape, &cells, catalogue).ok_or(SaveError::Corrupt)?;
+        let mut camera = [0.0; 10];
+        for value in camera.iter_mut() {
+            *value = f32::from_bits(reader.u32()?);
+        }
+        let selected_tile = uvec2(reader.u32()?, reader.u32()?);
+        if selected_tile.x > u8::MAX as u32 || selected_tile.y >= 6 {
+            return Err(SaveError::Corrupt);
+        }
+        let selected_tile = uvec2(remap(selected_tile.x as u8)? as u32, selected_tile.y);
+        let score = if version >= 2 {reader.u32()?} else {0};
+        let deck = if version >= 3 {
+            let len = reader.u32()?;
+            let tiles = reader.bytes(len as usize)?.iter().map(|&tile| Ok(remap(tile)? as u32)).collect::<Result<_, SaveError>>()?;
+            Some(TileDeck::from_tiles(tiles))
+        } else {
+            None
+        };
+        let (chunk_source, edited, stored) = if version >= 5 {
+            let seed = WorldSeed(reader.u64()?);
+            let biomes = reader.bytes(1)?[0] != 0;
+            let edited = (0..reader.u32()?).map(|_| reader.ivec2()).collect::<Result<_, _>>()?;
+            let stored = (0..reader.u32()?).map(|_| {
+                let chunk = reader.ivec2()?;
+                let cells = reader.bytes((CHUNK_SIZE * CHUNK_SIZE * 4) as usize)?;
+                let cells = cells.chunks_exact(4)
+                    .map(|texel| Ok(Cell::from_texel([remap(texel[0])?, check_rotation(texel[1])?, texel[2], texel[3]])))
+                    .collect::<Result<_, SaveError>>()?;
+                Ok((chunk, cells))
+            }).collect::<Result<_, SaveError>>()?;
+            ((seed, biomes), edited, stored)
+        } else {
+            ((WorldSeed(0), false), Vec::new(), Vec::new())
+        };
+        let rng = if version >= 7 && reader.bytes(1)?[0] != 0 {
+            let seed = reader.bytes(32)?.try_into().expect("read 32 bytes");
+            let stream = reader.u64()?;
+            let word_pos = u128::from_le_bytes(reader.bytes(16)?.try_into().expect("read 16 bytes"));
+            Some(WorldRng::from_state((seed, stream, word_pos)))
+        } else {
+            None
+        };
+        if !reader.at_end()? {
+            return Err(SaveError::Corrupt);
+        }
+
+        Ok(SaveData {
+            map,
+            camera: Transform {
+                translation: Vec3::from_slice(&camera[0..3]),
+                rotation: Quat::from_slice(&camera[3..7]).normalize(),
+                scale: Vec3::from_slice(&camera[7..10]),
+            },
+            selected_tile,
+            score,
+            deck,
+            chunk_source,
+            edited,
+            stored,
+            rng,
+        })
+    }
+}
+
+/// Passes on the rotation of a cell, which is in sixths of a turn.
+fn check_rotation(rotation: u8) -> Result<u8, SaveError> {
+    if rotation < 6 {Ok(rotation)} else {Err(SaveError::Corrupt)}
+}
+
+/// Reads values from a stream of bytes.
+struct Reader<R>(R);
+
+impl<R: Read> Reader<R> {
+    /// Reads exactly `len` bytes. Memory is only taken as the bytes arrive, not up front.
+    fn bytes(&mut self, len: usize) -> Result<Vec<u8>, SaveError> {
+        let mut res = Vec::new();
+        (&mut self.0).take(len as u64).read_to_end(&mut res).map_err(|_| SaveError::Corrupt)?;
+        if res.len() != len {
+            return Err(SaveError::Corrupt);
+        }
+        Ok(res)
+    }
+
+    /// Whether all bytes have been read.
+    fn at_end(&mut self) -> Result<bool, SaveError> {
+        Ok(self.0.read(&mut [0]).map_err(|_| SaveError::Corrupt)? == 0)
+    }
+
+    fn u32(&mut self) -> Result<u32, SaveError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, SaveError> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+
+    fn ivec2(&mut self) -> Result<IVec2, SaveError> {
+        Ok(ivec2(self.u32()? as i32, self.u32()? as i32))
+    }
+}
+
+fn save_game(
+    _: Trigger<SaveGame>,
+    map: Option<Res<HexMap>>,
+    camera: Query<&Transform, With<MainCamera>>,
+    mouse_pos: Res<MousePos>,
+    score: Res<Score>,
+    deck: Res<TileDeck>,
+    chunk_source: Res<ChunkSource>,
+    chunk_store: Res<ChunkStore>,
+    rng: Res<WorldRng>,
+    catalogue: Res<TileCatalogue>,
+) {
+    let Some(map) = map else {return};
+    let Ok(camera) = camera.single() else {return};
+    let data = SaveData {
+        map: map.clone(),
+        camera: *camera,
+        selected_tile: mouse_pos.selected_tile,
+        score: score.total,
+        deck: Some(deck.clone()),
+        chunk_source: (chunk_source.seed(), chunk_source.has_biomes()),
+        edited: map.edited_chunks().collect(),
+        stored: chunk_store.chunks().filter_map(|chunk| Some((chunk, chunk_store.get(chunk)?))).collect(),
+        rng: Some(rng.clone()),
+    };
+    match fs::write(SAVE_PATH, data.encode(&catalogue)) {
+        Ok(()) => info!("Saved game to {SAVE_PATH}"),
+        Err(err) => error!("Failed to save game to {SAVE_PATH}: {err}"),
+    }
+}
+
+fn load_game(
+    _: Trigger<LoadGame>,
+    mut commands: Commands,
+    mut camera: Query<&mut Transform, With<MainCamera>>,
+    mut mouse_pos: ResMut<MousePos>,
+    mut score: ResMut<Score>,
+    mut deck: ResMut<TileDeck>,
+    biome_settings: Res<BiomeSettings>,
+    mut chunk_source: ResMut<ChunkSource>,
+    mut chunk_store: ResMut<ChunkStore>,
+    mut rng: ResMut<WorldRng>,
+    catalogue: Res<TileCatalogue>,
+) {
+    let mut data = match fs::read(SAVE_PATH).map_err(SaveError::from).and_then(|data| SaveData::decode(&data, &catalogue)) {
+        Ok(data) => data,
+        Err(err) => {
+            error!("Failed to load game from {SAVE_PATH}: {err}");
+            return;
+        }
+    };
+    let (seed, biomes) = data.chunk_source;
+    *chunk_source = ChunkSource::new(seed, biomes.then_some(&*biome_settings), &catalogue);
+    chunk_store.clear();
+    for (chunk, cells) in &data.stored {
+        chunk_store.put(*chunk, cells);
+    }
+    data.map.mark_edited(data.edited);
+    commands.insert_resource(data.map);
+    if let Ok(mut camera) = camera.single_mut() {
+        *camera = data.camera;
+    }
+    mouse_pos.selected_tile = data.selected_tile;
+    *score = Score {total: data.score, last: 0};
+    if let Some(loaded) = data.deck {
+        *deck = loaded;
+    }
+    if let Some(loaded) = data.rng {
+        *rng = loaded;
+    }
+    info!("Loaded game from {SAVE_PATH}");
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::{super::hex_map::random_prng, *};
+
+    /// A map of random cells.
+    fn random_map(shape: MapShape, catalogue: &TileCatalogue, rng: &mut WorldRng) -> HexMap {
+        let cells = (0..shape.size * shape.size)
+            .map(|_| Cell::new(uvec2(rng.gen_range(0..catalogue.len()), rng.gen_range(0..6)), random_prng(&mut **rng)))
+            .collect();
+        HexMap::from_cells(shape, cells, catalogue)
+    }
+
+    /// A game that uses everything that the given version can hold.
+    fn game(catalogue: &TileCatalogue, version: u16) -> SaveData {
+        let mut rng = WorldRng::new(WorldSeed(3));
+        let shape = match version {
+            1..=3 => MapShape::new(2 * CHUNK_SIZE, MapBoundary::Wrapping),
+            4 => MapShape::new(2 * CHUNK_SIZE, MapBoundary::Bounded),
+            _ => MapShape::new(2 * CHUNK_SIZE, MapBoundary::Infinite).with_origin(ivec2(-3, 1) * CHUNK_SIZE as i32),
+        };
+        let map = random_map(shape, catalogue, &mut rng);
+        let stored = random_map(MapShape::new(CHUNK_SIZE, MapBoundary::Infinite), catalogue, &mut rng);
+        let infinite = version >= 5;
+        SaveData {
+            map,
+            // Rotations are normalized on load, so this one has to come out the same.
+            camera: Transform::from_xyz(1.0, 2.5, -3.0).with_rotation(Quat::from_xyzw(0.0, 1.0, 0.0, 0.0)),
+            selected_tile: uvec2(5, 2),
+            score: if version >= 2 {42} else {0},
+            deck: (version >= 3).then(|| TileDeck::from_tiles(vec![1, 2, 3, 2])),
+            chunk_source: if infinite {(WorldSeed(9), true)} else {(WorldSeed(0), false)},
+            edited: if infinite {vec![ivec2(-3, 1), ivec2(-2, 2)]} else {Vec::new()},
+            stored: if infinite {
+                let cells = (0..stored.size() * stored.size()).map(|index| stored.get(stored.shape().hex(index as usize))).collect();
+                vec![(ivec2(7, -4), cells)]
+            } else {
+                Vec::new()
+            },
+            rng: (version >= 7).then_some(rng),
+        }
+    }
+
+    #[test]
+    fn every_version_round_trips() {
+        let catalogue = TileCatalogue::standard();
+        for version in 1..=VERSION {
+            let data = game(&catalogue, version);
+            let encoded = data.encode_version(&catalogue, version);
+            let decoded = SaveData::decode(&encoded, &catalogue).unwrap_or_else(|err| panic!("version {version}: {err}"));
+            assert_eq!(decoded.encode_version(&catalogue, version), encoded, "version {version}");
+
+            assert_eq!(decoded.map.shape(), data.map.shape(), "version {version}");
+            assert_eq!(decoded.map.texture_data(), data.map.texture_data(), "version {version}");
+            assert_eq!(decoded.camera, data.camera, "version {version}");
+            assert_eq!(decoded.score, data.score, "version {version}");
+            assert_eq!(decoded.deck.map(|deck| deck.tiles().to_vec()), data.deck.map(|deck| deck.tiles().to_vec()), "version {version}");
+            assert_eq!(decoded.edited, data.edited, "version {version}");
+            assert_eq!(decoded.stored, data.stored, "version {version}");
+            assert_eq!(decoded.rng.map(|rng| rng.state()), data.rng.map(|rng| rng.state()), "version {version}");
+        }
+    }
+
+    #[test]
+    fn loaded_games_continue_with_the_same_numbers() {
+        let catalogue = TileCatalogue::standard();
+        let mut data = game(&catalogue, VERSION);
+        let mut decoded = SaveData::decode(&data.encode(&catalogue), &catalogue).unwrap();
+        let (original, loaded) = (data.rng.as_mut().unwrap(), decoded.rng.as_mut().unwrap());
+        for _ in 0..10 {
+            assert_eq!(original.gen_range(0..1000), loaded.gen_range(0..1000));
+        }
+    }
+
+    #[test]
+    fn sizes_must_be_whole_chunks() {
+        let catalogue = TileCatalogue::standard();
+        let mut rng = WorldRng::new(WorldSeed(5));
+        for size in [0, CHUNK_SIZE / 2, CHUNK_SIZE + 8] {
+            let mut data = game(&catalogue, VERSION);
+            data.map = random_map(MapShape::new(size, MapBoundary::Wrapping), &catalogue, &mut rng);
+            let result = SaveData::decode(&data.encode(&catalogue), &catalogue);
+            assert!(matches!(result, Err(SaveError::Corrupt)), "size {size}");
+        }
+
+        // A bounded map whose edge would run through the middle of a chunk.
+        let mut data = game(&catalogue, VERSION);
+        data.map = random_map(MapShape::new(3 * CHUNK_SIZE, MapBoundary::Bounded), &catalogue, &mut rng);
+        assert!(matches!(SaveData::decode(&data.encode(&catalogue), &catalogue), Err(SaveError::Corrupt)), "bounded size");
+
+        // An infinite map whose window doesn't start on the corner of a chunk.
+        let mut data = game(&catalogue, VERSION);
+        let shape = MapShape::new(2 * CHUNK_SIZE, MapBoundary::Infinite).with_origin(ivec2(-3, 1) * CHUNK_SIZE as i32 + ivec2(0, 1));
+        data.map = random_map(shape, &catalogue, &mut rng);
+        assert!(matches!(SaveData::decode(&data.encode(&catalogue), &catalogue), Err(SaveError::Corrupt)), "origin");
+
+        // A cell turned further than a full turn.
+        let mut data = game(&catalogue, VERSION);
+        let hex = data.map.shape().hex(0);
+        data.map.set(hex, Cell {rotation: 6, ..data.map.get(hex)});
+        assert!(matches!(SaveData::decode(&data.encode(&catalogue), &catalogue), Err(SaveError::Corrupt)), "rotation");
+    }
+
+    #[test]
+    fn huge_sizes_are_rejected() {
+        let catalogue = TileCatalogue::standard();
+        // No tile names, and a wrapping map whose cells would take more bytes than fit in a `usize`,
+        // or a terabyte map with no cells at all.
+        for size in [u32::MAX - CHUNK_SIZE + 1, CHUNK_SIZE << 14] {
+            let mut body = Vec::from(0u32.to_le_bytes());
+            body.extend(size.to_le_bytes());
+            body.push(0);
+            body.extend([0; 8]);
+            let mut data = Vec::from(MAGIC);
+            data.extend(VERSION.to_le_bytes());
+            let mut encoder = ZlibEncoder::new(data, Compression::default());
+            encoder.write_all(&body).unwrap();
+            let result = SaveData::decode(&encoder.finish().unwrap(), &catalogue);
+            assert!(matches!(result, Err(SaveError::Corrupt)), "size {size}");
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_are_rejected() {
+        let catalogue = TileCatalogue::standard();
+        let data = game(&catalogue, VERSION);
+        let mut body = Vec::new();
+        ZlibDecoder::new(&data.encode(&catalogue)[6..]).read_to_end(&mut body).unwrap();
+        body.push(0);
+        let mut file = Vec::from(MAGIC);
+        file.extend(VERSION.to_le_bytes());
+        let mut encoder = ZlibEncoder::new(file, Compression::default());
+        encoder.write_all(&body).unwrap();
+        assert!(matches!(SaveData::decode(&encoder.finish().unwrap(), &catalogue), Err(SaveError::Corrupt)));
+    }
+}
@@ -31,6 +31,13 @@ pub(super) fn plugin(app: &mut App) {
 #[reflect(Component)]
 pub struct MainCamera;
 
+/// The point on the ground at the center of the camera's view.
+pub fn ground_point(camera: &GlobalTransform) -> Vec3 {
+    let forward = camera.forward();
+    let distance = if forward.y < -1e-3 {-camera.translation().y / forward.y} else {0.0};
+    camera.translation() + distance * forward
+}
+
 fn spawn_camera(mut commands: Commands) {
     commands.spawn((
         Name::new("Main Camera"),
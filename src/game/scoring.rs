@@ -0,0 +1,243 @@
+//! Points for placing tiles.
+//!
+//! Every side of a placed tile that continues the terrain of its neighbour is worth a point,
+//! with a bonus when all six do on a tile with more than one kind of terrain. Tiles that close
+//! off a region, such as a lake, a river or a road with no loose ends, or a forest cut off
+//! from open grassland, earn points for every cell of that region. Regions that were already
+//! closed before the placement don't earn them again.
+
+use bevy::prelude::*;
+
+use crate::theme::prelude::*;
+
+use super::{
+    history::EditHistory,
+    prelude::*,
+    region::Feature,
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<Score>();
+    app.init_resource::<Score>();
+    app.add_observer(score_placement);
+    app.add_systems(Update, update_score_label.run_if(resource_changed::<Score>));
+}
+
+/// Points for each side that continues the neighbouring terrain.
+const EDGE_POINTS: u32 = 1;
+/// Bonus for a tile whose six sides all continue the neighbouring terrain, when they aren't all alike.
+const PERFECT_BONUS: u32 = 5;
+/// Points for each cell of a region that was closed off.
+const REGION_POINTS: u32 = 2;
+/// Regions larger than this are considered open, like the sea.
+const MAX_REGION: usize = 256;
+
+/// The player's points in the current game.
+#[derive(Resource, Reflect, Default, Debug)]
+#[reflect(Resource)]
+pub struct Score {
+    pub total: u32,
+    /// Points earned by the most recent placement.
+    pub last: u32,
+}
+
+/// Trigger this after the player placed a tile, to award points for it.
+#[derive(Event)]
+pub struct TilePlaced {
+    pub hex: Hex,
+    /// The regions around the cell from before the tile was placed.
+    pub before: RegionsAround,
+}
+
+/// Whether the cell and each of its neighbours belonged to a closed region of each feature,
+/// indexed by [`Feature::index`] and then with the cell itself first.
+#[derive(Clone, Copy, Debug)]
+pub struct RegionsAround([[Option<bool>; 7]; 4]);
+
+impl RegionsAround {
+    pub fn new(map: &HexMap, hex: Hex) -> Self {
+        Self(Feature::ALL.map(|feature| {
+            around(hex).map(|cell| map.region(cell, feature).map(|(_, region)| region.closed()))
+        }))
+    }
+}
+
+fn around(hex: Hex) -> [Hex; 7] {
+    let [a, b, c, d, e, f] = hex.neighbours();
+    [hex, a, b, c, d, e, f]
+}
+
+/// Marks the text showing the score.
+#[derive(Component)]
+struct ScoreLabel;
+
+/// The text showing the score, to be placed in the HUD.
+pub fn score_label() -> impl Bundle {
+    (
+        widget::label("Score 0"),
+        ScoreLabel,
+        Pickable::IGNORE,
+    )
+}
+
+/// Whether two sides lie against each other with the same kind of terrain.
+fn continues(edge: Edge, other: Edge) -> bool {
+    edge.fits(other) && !matches!((edge, other), (Edge::Grass, Edge::Stone) | (Edge::Stone, Edge::Grass))
+}
+
+/// The points earned by the tile that was just placed at `hex`, given the regions around it from before.
+pub fn placement_points(map: &HexMap, catalogue: &TileCatalogue, hex: Hex, before: &RegionsAround) -> u32 {
+    let edges = catalogue.edges(map.get(hex).tile());
+    let matched = hex.neighbours().into_iter().enumerate().filter(|(side, neighbour)| {
+        let neighbour = catalogue.edges(map.get(*neighbour).tile());
+        continues(edges.get(*side), neighbour.get(side + 3))
+    }).count() as u32;
+
+    let mut points = matched * EDGE_POINTS;
+    // A tile of a single kind of terrain continues its surroundings all too easily.
+    if matched == 6 && (1..6).any(|side| edges.get(side) != edges.get(0)) {
+        points += PERFECT_BONUS;
+    }
+    for feature in Feature::ALL {
+        let before = before.0[feature.index()];
+        let mut scored = Vec::new();
+        for cell in around(hex) {
+            let Some((id, region)) = map.region(cell, feature) else {continue};
+            if scored.contains(&id) || !region.closed() || region.size as usize > MAX_REGION {continue}
+            scored.push(id);
+            // Whether the region of each cell around the tile that is now part of this one was closed before.
+            let was: Vec<Option<bool>> = around(hex).into_iter().zip(before)
+                .filter(|(cell, _)| map.region(*cell, feature).is_some_and(|(other, _)| other == id))
+                .map(|(_, closed)| closed)
+                .collect();
+            // Only count regions that this placement closed, because part of them was open or they are new.
+            if was.contains(&Some(false)) || was.iter().all(Option::is_none) {
+                points += region.size * REGION_POINTS;
+            }
+        }
+    }
+    points
+}
+
+fn score_placement(
+    trigger: Trigger<TilePlaced>,
+    map: Option<Res<HexMap>>,
+    catalogue: Res<TileCatalogue>,
+    mut score: ResMut<Score>,
+    mut history: ResMut<EditHistory>,
+) {
+    let Some(map) = map else {return};
+    let points = placement_points(&map, &catalogue, trigger.hex, &trigger.before);
+    score.total += points;
+    score.last = points;
+    history.award(points);
+}
+
+fn update_score_label(score: Res<Score>, mut label: Query<&mut Text, With<ScoreLabel>>) {
+    for mut text in label.iter_mut() {
+        text.0 = if score.last > 0 {
+            format!("Score {} (+{})", score.total, score.last)
+        } else {
+            format!("Score {}", score.total)
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::hex_map::Cell, *};
+
+    /// Places `tile` at `hex` and returns the points for it.
+    fn place(map: &mut HexMap, catalogue: &TileCatalogue, hex: Hex, tile: UVec2) -> u32 {
+        let before = RegionsAround::new(map, hex);
+        map.set(hex, Cell::new(tile, 1));
+        placement_points(map, catalogue, hex, &before)
+    }
+
+    #[test]
+    fn regions_score_once_when_closed() {
+        let catalogue = TileCatalogue::standard();
+        let roles = &catalogue.roles;
+        let rocks = uvec2(roles.rocks, 0);
+        let mut map = HexMap::new(MapShape::new(8, MapBoundary::Bounded), rocks, &catalogue, &mut *WorldRng::new(WorldSeed(1)));
+        let forest = Hex::from_axial(IVec2::ZERO);
+        let [east, west, ..] = forest.neighbours();
+        map.set(forest, Cell::new(uvec2(roles.forest, 0), 1));
+        map.set(east, Cell::new(uvec2(roles.forest, 0), 1));
+        map.set(west, Cell::new(uvec2(roles.grassland, 0), 1));
+        assert!(!map.region(forest, Feature::Forest).unwrap().1.closed());
+
+        // Every side continues plain land, but the rocks are all alike so there is no bonus.
+        let edge_points = 6 * EDGE_POINTS;
+        assert_eq!(place(&mut map, &catalogue, west, rocks), edge_points + 2 * REGION_POINTS);
+        assert!(map.region(forest, Feature::Forest).unwrap().1.closed());
+
+        // Turning a tile of the closed forest, or placing more rocks next to it, doesn't close it again.
+        assert_eq!(place(&mut map, &catalogue, forest, uvec2(roles.forest, 1)), edge_points);
+        assert_eq!(place(&mut map, &catalogue, west, uvec2(roles.rocks, 2)), edge_points);
+    }
+
+    /// A bounded map of rocks, which continue every kind of plain land around them.
+    fn rocky_map(catalogue: &TileCatalogue) -> HexMap {
+        HexMap::new(MapShape::new(8, MapBoundary::Bounded), uvec2(catalogue.roles.rocks, 0), catalogue, &mut *WorldRng::new(WorldSeed(1)))
+    }
+
+    #[test]
+    fn mixed_tiles_that_fit_all_around_earn_a_bonus() {
+        let catalogue = TileCatalogue::standard();
+        let path = catalogue.find("grass-path-straight").unwrap();
+        let mut map = rocky_map(&catalogue);
+        let hex = Hex::from_axial(IVec2::ZERO);
+        let neighbours = hex.neighbours();
+        // A path running through the cell from side 0 to side 3, which stays open at both ends.
+        map.set(neighbours[0], Cell::new(uvec2(path, 0), 1));
+        map.set(neighbours[3], Cell::new(uvec2(path, 0), 1));
+        assert_eq!(place(&mut map, &catalogue, hex, uvec2(path, 0)), 6 * EDGE_POINTS + PERFECT_BONUS);
+        assert!(!map.region(hex, Feature::Road).unwrap().1.closed());
+
+        // Turned, the path runs into rocks on two sides and is cut off on the other two.
+        assert_eq!(place(&mut map, &catalogue, hex, uvec2(path, 1)), 2 * EDGE_POINTS);
+    }
+
+    #[test]
+    fn grass_and_stone_fit_without_continuing() {
+        let catalogue = TileCatalogue::standard();
+        let mountain = uvec2(catalogue.roles.mountain, 0);
+        let mut map = rocky_map(&catalogue);
+        let hex = Hex::from_axial(IVec2::ZERO);
+        assert!(Edge::Stone.fits(Edge::Grass));
+        assert_eq!(place(&mut map, &catalogue, hex, mountain), 0);
+        // Only the side against the other mountain continues it.
+        assert_eq!(place(&mut map, &catalogue, hex.neighbour(0), mountain), EDGE_POINTS);
+    }
+
+    #[test]
+    fn closing_a_road_earns_its_cells() {
+        let catalogue = TileCatalogue::standard();
+        let end = catalogue.find("grass-path-start").unwrap();
+        let mut map = rocky_map(&catalogue);
+        let hex = Hex::from_axial(IVec2::ZERO);
+        // A path end pointing at side 3, and then another one pointing back at it.
+        assert_eq!(place(&mut map, &catalogue, hex, uvec2(end, 0)), 5 * EDGE_POINTS);
+        assert!(!map.region(hex, Feature::Road).unwrap().1.closed());
+        assert_eq!(place(&mut map, &catalogue, hex.neighbour(3), uvec2(end, 3)), 6 * EDGE_POINTS + PERFECT_BONUS + 2 * REGION_POINTS);
+        assert!(map.region(hex, Feature::Road).unwrap().1.closed());
+    }
+
+    #[test]
+    fn closing_a_lake_earns_its_cells_unless_it_is_the_sea() {
+        let catalogue = TileCatalogue::standard();
+        let (ocean, rocks) = (uvec2(catalogue.roles.ocean, 0), uvec2(catalogue.roles.rocks, 0));
+        // A wrapping map of water with a single rock in it, which keeps the water open until it is flooded.
+        for (size, region_points) in [(8, 64 * REGION_POINTS), (32, 0)] {
+            let mut map = HexMap::new(MapShape::new(size, MapBoundary::Wrapping), ocean, &catalogue, &mut *WorldRng::new(WorldSeed(1)));
+            let hex = Hex::from_axial(IVec2::ZERO);
+            map.set(hex, Cell::new(rocks, 1));
+            assert!(!map.region(hex.neighbour(0), Feature::Lake).unwrap().1.closed());
+            assert_eq!(place(&mut map, &catalogue, hex, ocean), 6 * EDGE_POINTS + region_points, "size {size}");
+            let (_, lake) = map.region(hex, Feature::Lake).unwrap();
+            assert!(lake.closed());
+            assert_eq!(lake.size as usize > MAX_REGION, region_points == 0);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+//! The world seed, from which all randomness in the game is derived.
+
+use bevy::prelude::*;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+pub(super) fn plugin(app: &mut App) {
+    let from_args = seed_from_args(std::env::args());
+    let seed = match &from_args {
+        Some(Ok(seed)) => *seed,
+        Some(Err(value)) => {
+            warn!("Ignoring invalid seed {value:?}");
+            rand::random()
+        }
+        None => rand::random(),
+    };
+    info!("World seed: {seed}");
+
+    app.register_type::<WorldSeed>();
+    app.insert_resource(WorldSeed(seed));
+    app.insert_resource(WorldRng::new(WorldSeed(seed)));
+    app.insert_resource(CommandLineSeed(from_args.and_then(Result::ok)));
+}
+
+/// The seed used to generate the world. Can be set with `--seed <number>` on the command line.
+#[derive(Resource, Reflect, Clone, Copy, Debug, PartialEq, Eq)]
+#[reflect(Resource)]
+pub struct WorldSeed(pub u64);
+
+/// The seed given with `--seed`, until a new game has been started from the menu with it,
+/// so that the world it reproduces isn't lost when the player starts over.
+#[derive(Resource, Default)]
+pub struct CommandLineSeed(pub Option<u64>);
+
+/// The random number generator that should be used for everything in the world.
+///
+/// It is reset from the [`WorldSeed`] whenever a new world is created.
+#[derive(Resource, Deref, DerefMut, Clone)]
+pub struct WorldRng(ChaCha8Rng);
+
+impl WorldRng {
+    pub fn new(seed: WorldSeed) -> Self {
+        Self(ChaCha8Rng::seed_from_u64(seed.0))
+    }
+
+    /// The full state of the generator, as the key, the stream and the position in the stream.
+    pub fn state(&self) -> ([u8; 32], u64, u128) {
+        (self.0.get_seed(), self.0.get_stream(), self.0.get_word_pos())
+    }
+
+    /// A generator that continues from a [`WorldRng::state`].
+    pub fn from_state((seed, stream, word_pos): ([u8; 32], u64, u128)) -> Self {
+        let mut rng = ChaCha8Rng::from_seed(seed);
+        rng.set_stream(stream);
+        rng.set_word_pos(word_pos);
+        Self(rng)
+    }
+}
+
+/// Looks for `--seed <number>` or `--seed=<number>`. Returns the offending value if it isn't a number.
+fn seed_from_args(mut args: impl Iterator<Item = String>) -> Option<Result<u64, String>> {
+    while let Some(arg) = args.next() {
+        let value = if arg == "--seed" {
+            args.next().unwrap_or_default()
+        } else if let Some(value) = arg.strip_prefix("--seed=") {
+            value.to_string()
+        } else {
+            continue;
+        };
+        return Some(value.parse().map_err(|_| value));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Option<Result<u64, String>> {
+        seed_from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn seeds_are_read_from_args() {
+        assert_eq!(parse(&["sprawl"]), None);
+        assert_eq!(parse(&["sprawl", "--bake-tileset"]), None);
+        assert_eq!(parse(&["sprawl", "--seed", "42"]), Some(Ok(42)));
+        assert_eq!(parse(&["sprawl", "--seed=18446744073709551615"]), Some(Ok(u64::MAX)));
+        assert_eq!(parse(&["sprawl", "--seed"]), Some(Err(String::new())));
+        assert_eq!(parse(&["sprawl", "--seed", "forest"]), Some(Err("forest".to_string())));
+        assert_eq!(parse(&["sprawl", "--seed=-1"]), Some(Err("-1".to_string())));
+    }
+}
@@ -0,0 +1,520 @@
+//! The world keeps growing between placements: forests spread into grassland, villages grow
+//! along roads and rivers wear down the hills next to them.
+//!
+//! The rules run on the GPU in `shaders/simulate.wgsl`, which reads one map texture and writes
+//! the next state of every cell to another, so that each cell sees its neighbours as they were
+//! before the tick. Ticks happen at a fixed rate while the game isn't paused.
+//! Each cell that a rule could change rolls its own xorshift16 generator, stored next to its
+//! tile in the map texture.
+//!
+//! [`step`] is the same tick on the CPU, over the same texel layout, with identical results.
+//! It is used instead of the kernel where compute shaders can't use storage textures, such as on WebGL2.
+//! Growth on the GPU is read back from the map texture and applied to the [`HexMap`] a few frames later.
+//!
+//! The kernel works on chunks of [`CHUNK_SIZE`] by [`CHUNK_SIZE`] cells. By default it covers
+//! the whole map, but [`SimulationSettings::chunked`] limits it to the chunks near the camera
+//! and those with recent changes, which saves GPU time on large maps.
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::{ShaderDefVal, TextureFormat, TextureUsages},
+        renderer::RenderAdapter,
+        storage::ShaderStorageBuffer,
+    },
+};
+
+use crate::{AppSystems, PausableSystems};
+
+use super::{
+    hex_map::{self, Cell, MapChanges},
+    prelude::*,
+    scene::ground_point,
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<(SimulationClock, SimulationSettings)>();
+    app.init_resource::<SimulationClock>();
+    app.init_resource::<SimulationSettings>();
+    app.init_resource::<ChunkActivity>();
+    app.init_resource::<SimulationChunks>();
+    app.init_resource::<SimulationTicks>();
+    app.init_resource::<SimulationRules>();
+    app.init_resource::<SimulationBackend>();
+    app.add_plugins((
+        ExtractResourcePlugin::<SimulationTicks>::default(),
+        ExtractResourcePlugin::<SimulationChunks>::default(),
+    ));
+    app.add_systems(Startup, choose_backend);
+    app.add_systems(First, clear_ticks);
+    app.add_systems(Update, update_rules.run_if(resource_exists_and_changed::<TileCatalogue>));
+    app.add_systems(Update, (
+        advance_clock.in_set(AppSystems::TickTimers),
+        simulate_on_cpu.run_if(resource_equals(SimulationBackend::Cpu)),
+    ).chain().in_set(PausableSystems));
+    app.add_systems(PostUpdate, select_chunks.after(hex_map::collect_changes));
+}
+
+pub const TICKS_PER_SECOND: f32 = 4.0;
+/// Ticks beyond this many in a single frame are dropped, so a slow frame doesn't snowball.
+const MAX_TICKS_PER_FRAME: u32 = 4;
+
+/// Width and height of the chunks that the kernel is dispatched over, in cells.
+/// Must be a multiple of the kernel's workgroup size of 8.
+pub const CHUNK_SIZE: u32 = 64;
+
+/// Chance out of 65536, per forest neighbour, that grass turns into forest in a tick.
+const FOREST_CHANCE: u32 = 400;
+/// Chance out of 65536, per settlement neighbour, that grass next to a road turns into a house.
+const VILLAGE_CHANCE: u32 = 200;
+/// Chance out of 65536, per river neighbour, that a hill erodes in a tick.
+const EROSION_CHANCE: u32 = 100;
+
+/// Bits of a tile's entry in [`tile_flags`], which `simulate.wgsl` mirrors.
+const GRASS: u32 = 1 << 0;
+const FOREST: u32 = 1 << 1;
+const ROAD: u32 = 1 << 2;
+const SETTLEMENT: u32 = 1 << 3;
+const RIVER: u32 = 1 << 4;
+/// The tile a hill erodes into, plus one, is stored from this bit on.
+const ERODES_SHIFT: u32 = 8;
+
+/// Tiles that villages are made of have this tag in the catalogue.
+const SETTLEMENT_TAG: &str = "settlement";
+
+/// How each tile takes part in the simulation, indexed by tile id.
+/// Tiles play the part of the [`TileRoles`](super::load_tiles::TileRoles) whose tags they have.
+fn tile_flags(catalogue: &TileCatalogue) -> Vec<u32> {
+    let roles = &catalogue.roles;
+    catalogue.tiles.iter().map(|tile| {
+        let mut flags = 0;
+        if tile.has_tag("grassland") {flags |= GRASS}
+        if tile.has_tag("forest") {flags |= FOREST}
+        if tile.edges.0.contains(&Edge::Path) {flags |= ROAD}
+        if tile.has_tag(SETTLEMENT_TAG) {flags |= SETTLEMENT}
+        if tile.edges.0.contains(&Edge::River) {flags |= RIVER}
+        // Rocky hills wear down into hills, and hills into grassland.
+        if tile.has_tag("rocky-hill") {
+            flags |= (roles.hill + 1) << ERODES_SHIFT;
+        } else if tile.has_tag("hill") {
+            flags |= (roles.grassland + 1) << ERODES_SHIFT;
+        }
+        flags
+    }).collect()
+}
+
+/// Constants of the rules, for compiling `simulate.wgsl`.
+pub fn shader_defs() -> Vec<ShaderDefVal> {
+    vec![
+        ShaderDefVal::UInt("CHUNK_SIZE".into(), CHUNK_SIZE),
+        ShaderDefVal::UInt("FOREST_CHANCE".into(), FOREST_CHANCE),
+        ShaderDefVal::UInt("VILLAGE_CHANCE".into(), VILLAGE_CHANCE),
+        ShaderDefVal::UInt("EROSION_CHANCE".into(), EROSION_CHANCE),
+    ]
+}
+
+/// The rules that depend on the loaded tiles, for both the CPU and the GPU.
+/// They are filled in by [`update_rules`] once the [`TileCatalogue`] is loaded.
+#[derive(Resource)]
+pub struct SimulationRules {
+    /// The entries of [`tile_flags`].
+    pub flags: Vec<u32>,
+    pub forest_tile: u8,
+    pub house_tile: u8,
+    /// The rules for the compute kernel: the forest tile, the house tile and then
+    /// [`SimulationRules::flags`], as laid out by `Rules` in `simulate.wgsl`.
+    pub buffer: Handle<ShaderStorageBuffer>,
+}
+
+impl FromWorld for SimulationRules {
+    fn from_world(world: &mut World) -> Self {
+        let mut buffers = world.resource_mut::<Assets<ShaderStorageBuffer>>();
+        Self {
+            buffer: buffers.add(ShaderStorageBuffer::from(vec![0u32; 2])),
+            flags: Vec::new(),
+            forest_tile: 0,
+            house_tile: 0,
+        }
+    }
+}
+
+/// Derives the rules from the tile catalogue, again whenever it is reloaded.
+/// The kernel keeps its buffer, so it picks up the new rules without rebuilding any bind groups.
+fn update_rules(
+    catalogue: Res<TileCatalogue>,
+    mut rules: ResMut<SimulationRules>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+) {
+    rules.flags = tile_flags(&catalogue);
+    rules.forest_tile = catalogue.roles.forest as u8;
+    rules.house_tile = catalogue.roles.house as u8;
+    let data: Vec<u32> = [rules.forest_tile as u32, rules.house_tile as u32].into_iter().chain(rules.flags.iter().copied()).collect();
+    if let Some(buffer) = buffers.get_mut(&rules.buffer) {
+        *buffer = ShaderStorageBuffer::from(data);
+    }
+}
+
+/// Advances a cell's xorshift16 generator.
+pub fn xorshift16(state: u16) -> u16 {
+    let mut x = state;
+    x ^= x << 7;
+    x ^= x >> 9;
+    x ^= x << 8;
+    x
+}
+
+/// Runs one tick over a map in the `Rgba8Uint` layout of the map texture, see [`HexMap::texture_data`].
+///
+/// Every cell sees its neighbours as they were before the tick. Beyond the edges of a bounded map there are none.
+/// Beyond the window of an infinite map they aren't known, so cells along its edges stand still.
+pub fn step(rules: &SimulationRules, shape: MapShape, texels: &[u8]) -> Vec<u8> {
+    let mut res = texels.to_vec();
+    for (index, texel) in res.chunks_exact_mut(4).enumerate() {
+        let cell = Cell::from_texel([texel[0], texel[1], texel[2], texel[3]]);
+        let flags = rules.flags[cell.tile as usize];
+        let neighbours = (0..6).map(|side| shape.neighbour(index, side));
+        if shape.boundary == MapBoundary::Infinite && neighbours.clone().any(|neighbour| neighbour.is_none()) {continue}
+
+        let (mut forests, mut settlements, mut roads, mut rivers) = (0, 0, 0, 0);
+        for neighbour in neighbours.flatten() {
+            let other = rules.flags[texels[neighbour * 4] as usize];
+            forests += (other & FOREST != 0) as u32;
+            settlements += (other & SETTLEMENT != 0) as u32;
+            roads += (other & ROAD != 0) as u32;
+            rivers += (other & RIVER != 0) as u32;
+        }
+
+        let erodes = flags >> ERODES_SHIFT;
+        // Villages need a road to grow along.
+        let village = if roads > 0 {settlements * VILLAGE_CHANCE} else {0};
+        let forest = forests * FOREST_CHANCE;
+        let grows = flags & GRASS != 0 && village + forest > 0;
+        let wears = erodes != 0 && rivers > 0;
+        if !grows && !wears {continue}
+
+        let roll = xorshift16(cell.prng);
+        let mut tile = cell.tile;
+        if grows {
+            if (roll as u32) < village {
+                tile = rules.house_tile;
+            } else if (roll as u32) < village + forest {
+                tile = rules.forest_tile;
+            }
+        }
+        if wears && (roll as u32) < rivers * EROSION_CHANCE {
+            tile = (erodes - 1) as u8;
+        }
+        texel.copy_from_slice(&Cell {tile, rotation: cell.rotation, prng: roll}.to_texel());
+    }
+    res
+}
+
+/// Where the simulation runs.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SimulationBackend {
+    /// In the compute kernel, on the map texture.
+    #[default]
+    Gpu,
+    /// With [`step`], on the [`HexMap`].
+    Cpu,
+}
+
+/// Where the simulation can run on the given adapter. The kernel needs the map texture to be a storage texture.
+pub fn supported_backend(adapter: &RenderAdapter) -> SimulationBackend {
+    let features = adapter.get_texture_format_features(TextureFormat::Rgba8Uint);
+    if features.allowed_usages.contains(TextureUsages::STORAGE_BINDING) {
+        SimulationBackend::Gpu
+    } else {
+        SimulationBackend::Cpu
+    }
+}
+
+/// Falls back to the CPU when the map texture can't be a storage texture.
+fn choose_backend(adapter: Option<Res<RenderAdapter>>, mut backend: ResMut<SimulationBackend>) {
+    let Some(adapter) = adapter else {return};
+    *backend = supported_backend(&adapter);
+    if *backend == SimulationBackend::Cpu {
+        info!("Storage textures aren't supported, simulating on the CPU");
+    }
+}
+
+/// Counts simulation ticks.
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct SimulationClock {
+    pub timer: Timer,
+    /// Number of ticks since the game started.
+    pub tick: u64,
+    /// Number of ticks that are due this frame.
+    pub due: u32,
+}
+
+impl Default for SimulationClock {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(1.0 / TICKS_PER_SECOND, TimerMode::Repeating),
+            tick: 0,
+            due: 0,
+        }
+    }
+}
+
+/// Number of ticks the compute kernel runs this frame.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Eq, ExtractResource)]
+pub struct SimulationTicks(pub u32);
+
+fn clear_ticks(mut clock: ResMut<SimulationClock>, mut ticks: ResMut<SimulationTicks>) {
+    clock.due = 0;
+    ticks.set_if_neq(SimulationTicks(0));
+}
+
+fn advance_clock(
+    time: Res<Time>,
+    map: Option<Res<HexMap>>,
+    backend: Res<SimulationBackend>,
+    mut clock: ResMut<SimulationClock>,
+    mut ticks: ResMut<SimulationTicks>,
+) {
+    if map.is_none() {return}
+    clock.timer.tick(time.delta());
+    clock.due = clock.timer.times_finished_this_tick().min(MAX_TICKS_PER_FRAME);
+    clock.tick += clock.due as u64;
+    if *backend == SimulationBackend::Gpu {
+        ticks.set_if_neq(SimulationTicks(clock.due));
+    }
+}
+
+fn simulate_on_cpu(map: Option<ResMut<HexMap>>, rules: Res<SimulationRules>, clock: Res<SimulationClock>) {
+    let Some(mut map) = map else {return};
+    for _ in 0..clock.due {
+        let shape = map.shape();
+        let before = map.texture_data();
+        let after = step(&rules, shape, &before);
+        for (index, (old, new)) in before.chunks_exact(4).zip(after.chunks_exact(4)).enumerate() {
+            if old != new {
+                map.set(shape.hex(index), Cell::from_texel([new[0], new[1], new[2], new[3]]));
+            }
+        }
+    }
+}
+
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct SimulationSettings {
+    /// Only simulate the chunks near the camera and those with recent changes.
+    /// Everything else stands still until the camera comes close.
+    pub chunked: bool,
+    /// Chunks up to this many chunks away from the one under the camera are simulated.
+    pub camera_radius: u32,
+    /// Chunks are simulated for this many ticks after one of their cells was edited.
+    pub change_ticks: u32,
+}
+
+impl Default for SimulationSettings {
+    fn default() -> Self {
+        Self {
+            chunked: false,
+            camera_radius: 4,
+            change_ticks: 64,
+        }
+    }
+}
+
+/// The chunks the kernel runs on this frame, as chunk coordinates in `xy`.
+/// Chunks with zero in `z` are only copied to the other map texture.
+/// The bits of `w` mark the sides of the chunk that lie on the edge of a bounded or infinite map,
+/// in the order -x, +x, -y, +y. On infinite maps the fifth bit is set as well, as the map goes on beyond
+/// those sides, which leaves the cells along them to stand still like [`step`] does.
+#[derive(Resource, Default, Clone, ExtractResource)]
+pub struct SimulationChunks(pub Vec<UVec4>);
+
+/// What the kernel did with each chunk, in row-major order.
+#[derive(Resource, Default)]
+struct ChunkActivity {
+    /// Chunks along each side of the map.
+    size: u32,
+    /// The tick at which a cell of the chunk last changed.
+    changed: Vec<Option<u64>>,
+    /// Whether the chunk was simulated during the last ticks.
+    active: Vec<bool>,
+}
+
+/// Picks the chunks to simulate on frames with ticks.
+///
+/// A chunk that stops being simulated is copied for one more frame, so that both map textures agree on it.
+/// From then on it doesn't change, apart from edits, which wake it up again.
+fn select_chunks(
+    map: Option<Res<HexMap>>,
+    changes: Res<MapChanges>,
+    clock: Res<SimulationClock>,
+    ticks: Res<SimulationTicks>,
+    settings: Res<SimulationSettings>,
+    camera: Query<&GlobalTransform, With<MainCamera>>,
+    mut activity: ResMut<ChunkActivity>,
+    mut chunks: ResMut<SimulationChunks>,
+) {
+    let Some(map) = map else {return};
+    let size = map.size().div_ceil(CHUNK_SIZE);
+    if map.is_added() || activity.size != size {
+        *activity = ChunkActivity {
+            size,
+            changed: vec![None; (size * size) as usize],
+            active: vec![false; (size * size) as usize],
+        };
+    }
+    let chunk_index = |texel: UVec2| ((texel.y / CHUNK_SIZE) * size + texel.x / CHUNK_SIZE) as usize;
+    if changes.is_changed() {
+        for (texel, _) in &changes.0 {
+            activity.changed[chunk_index(*texel)] = Some(clock.tick);
+        }
+    }
+    if ticks.0 == 0 {return}
+
+    // The chunk under the center of the screen.
+    let center = camera.single().ok().map(|camera| {
+        let texel = Hex::from_world(ground_point(camera)).texel(map.size());
+        (texel / CHUNK_SIZE).as_ivec2()
+    });
+    let near = |chunk: IVec2| center.is_some_and(|center| {
+        let offset = (chunk - center).rem_euclid(IVec2::splat(size as i32));
+        let offset = offset.min(IVec2::splat(size as i32) - offset);
+        offset.max_element() as u32 <= settings.camera_radius
+    });
+
+    let shape = map.shape();
+    let edges = |chunk: UVec2| {
+        if shape.wraps() {return 0}
+        let first = shape.hex(((chunk.y * map.size() + chunk.x) * CHUNK_SIZE) as usize);
+        let position = (first.axial() - shape.origin()) / CHUNK_SIZE as i32;
+        let last = size as i32 - 1;
+        (position.x == 0) as u32
+            | ((position.x == last) as u32) << 1
+            | ((position.y == 0) as u32) << 2
+            | ((position.y == last) as u32) << 3
+            | ((shape.boundary == MapBoundary::Infinite) as u32) << 4
+    };
+    let activity = &mut *activity;
+    chunks.0.clear();
+    for index in 0..(size * size) as usize {
+        let chunk = uvec2(index as u32 % size, index as u32 / size);
+        let active = !settings.chunked
+            || near(chunk.as_ivec2())
+            || activity.changed[index].is_some_and(|tick| tick + settings.change_ticks as u64 >= clock.tick);
+        if active || activity.active[index] {
+            chunks.0.push(chunk.extend(active as u32).extend(edges(chunk)));
+        }
+        activity.active[index] = active;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRASS_TILE: u8 = 0;
+    const FOREST_TILE: u8 = 1;
+    const HOUSE_TILE: u8 = 2;
+    const VILLAGE_TILE: u8 = 3;
+    const RIVER_TILE: u8 = 4;
+    const HILL_TILE: u8 = 5;
+
+    fn rules() -> SimulationRules {
+        SimulationRules {
+            flags: vec![
+                GRASS,
+                FOREST,
+                SETTLEMENT | ROAD,
+                SETTLEMENT,
+                RIVER,
+                (GRASS_TILE as u32 + 1) << ERODES_SHIFT,
+            ],
+            forest_tile: FOREST_TILE,
+            house_tile: HOUSE_TILE,
+            buffer: Handle::default(),
+        }
+    }
+
+    /// A generator state whose next roll is below `chance`, or not if `below` is false.
+    fn seed_rolling(chance: u32, below: bool) -> u16 {
+        (1..=u16::MAX).find(|&seed| ((xorshift16(seed) as u32) < chance) == below).unwrap()
+    }
+
+    /// Runs a tick on a small bounded map of grass, with `target` in the middle and `neighbour` on one side of it.
+    /// Returns the target after the tick.
+    fn tick(target: Cell, neighbour: u8) -> Cell {
+        let shape = MapShape::new(8, MapBoundary::Bounded);
+        let mut cells = vec![Cell {tile: GRASS_TILE, rotation: 0, prng: 1}; 64];
+        let index = Hex::ZERO.index(shape.size);
+        cells[index] = target;
+        cells[shape.neighbour(index, 0).unwrap()].tile = neighbour;
+        let texels: Vec<u8> = cells.iter().flat_map(|cell| cell.to_texel()).collect();
+        let after = step(&rules(), shape, &texels);
+        let texel = &after[index * 4..index * 4 + 4];
+        Cell::from_texel([texel[0], texel[1], texel[2], texel[3]])
+    }
+
+    #[test]
+    fn xorshift16_sequence() {
+        let sequence: Vec<u16> = std::iter::successors(Some(1), |&state| Some(xorshift16(state))).skip(1).take(5).collect();
+        assert_eq!(sequence, [33153, 24609, 59801, 11787, 46494]);
+        // Zero is the one state the generator never leaves, which is why cells are never seeded with it.
+        assert_eq!(xorshift16(0), 0);
+    }
+
+    #[test]
+    fn forest_spreads_into_grass() {
+        let seed = seed_rolling(FOREST_CHANCE, true);
+        let cell = tick(Cell {tile: GRASS_TILE, rotation: 2, prng: seed}, FOREST_TILE);
+        assert_eq!(cell, Cell {tile: FOREST_TILE, rotation: 2, prng: xorshift16(seed)});
+
+        let seed = seed_rolling(FOREST_CHANCE, false);
+        let cell = tick(Cell {tile: GRASS_TILE, rotation: 2, prng: seed}, FOREST_TILE);
+        assert_eq!(cell, Cell {tile: GRASS_TILE, rotation: 2, prng: xorshift16(seed)});
+    }
+
+    #[test]
+    fn villages_grow_along_roads() {
+        let seed = seed_rolling(VILLAGE_CHANCE, true);
+        let cell = tick(Cell {tile: GRASS_TILE, rotation: 0, prng: seed}, HOUSE_TILE);
+        assert_eq!(cell.tile, HOUSE_TILE);
+
+        // Without a road next to it, the cell doesn't even roll.
+        let cell = tick(Cell {tile: GRASS_TILE, rotation: 0, prng: seed}, VILLAGE_TILE);
+        assert_eq!(cell, Cell {tile: GRASS_TILE, rotation: 0, prng: seed});
+    }
+
+    #[test]
+    fn cells_at_the_window_edge_stand_still() {
+        let seed = seed_rolling(FOREST_CHANCE, true);
+        let grass = Cell {tile: GRASS_TILE, rotation: 0, prng: seed};
+        for (boundary, expected) in [
+            (MapBoundary::Bounded, Cell {tile: FOREST_TILE, rotation: 0, prng: xorshift16(seed)}),
+            (MapBoundary::Infinite, grass),
+        ] {
+            let shape = MapShape::new(8, boundary);
+            let mut cells = vec![Cell {tile: FOREST_TILE, rotation: 0, prng: 1}; 64];
+            let corner = Hex::from_axial(shape.origin()).index(shape.size);
+            cells[corner] = grass;
+            let texels: Vec<u8> = cells.iter().flat_map(|cell| cell.to_texel()).collect();
+            let after = step(&rules(), shape, &texels);
+            let texel = &after[corner * 4..corner * 4 + 4];
+            assert_eq!(Cell::from_texel([texel[0], texel[1], texel[2], texel[3]]), expected, "{boundary:?}");
+        }
+    }
+
+    #[test]
+    fn rivers_erode_hills() {
+        let seed = seed_rolling(EROSION_CHANCE, true);
+        let cell = tick(Cell {tile: HILL_TILE, rotation: 0, prng: seed}, RIVER_TILE);
+        assert_eq!(cell.tile, GRASS_TILE);
+
+        let seed = seed_rolling(EROSION_CHANCE, false);
+        let cell = tick(Cell {tile: HILL_TILE, rotation: 0, prng: seed}, RIVER_TILE);
+        assert_eq!(cell, Cell {tile: HILL_TILE, rotation: 0, prng: xorshift16(seed)});
+
+        // Hills away from rivers stand still.
+        let cell = tick(Cell {tile: HILL_TILE, rotation: 0, prng: seed}, GRASS_TILE);
+        assert_eq!(cell, Cell {tile: HILL_TILE, rotation: 0, prng: seed});
+    }
+}
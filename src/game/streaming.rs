@@ -0,0 +1,222 @@
+//! Infinite worlds, streamed in chunks around the camera.
+//!
+//! An infinite map only keeps a window of [`MapShape::size`] by [`MapShape::size`] cells, stored
+//! in the map texture like a bounded map. As the camera pans, the window follows it in steps of
+//! [`CHUNK_SIZE`] cells. Chunks that leave the window are put in the [`ChunkStore`] if they were
+//! edited, and chunks that enter it are taken back from the store, or generated again from the
+//! world seed if they never changed.
+//!
+//! Cells are stored at their coordinates modulo the size of the texture, which turns the texture
+//! into a ring buffer of chunks. The origin of the window is all the tilemap shader needs to tell
+//! which chunk a texel holds, so it serves as the page table.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Read, Write},
+};
+
+use bevy::prelude::*;
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use super::{
+    biome::{BiomeSettings, ChunkGenerator},
+    hex_map::{chunk_of, random_prng, Cell},
+    prelude::*,
+    scene::ground_point,
+    simulation::CHUNK_SIZE,
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<ChunkSource>();
+    app.init_resource::<ChunkStore>();
+    app.add_systems(Update, follow_camera);
+}
+
+/// Edited chunks are written to files in this directory, on platforms with a file system.
+#[cfg(not(target_family = "wasm"))]
+const CHUNK_DIR: &str = "sprawl-chunks";
+
+/// Generates the chunks of an infinite map that were never edited.
+#[derive(Resource, Clone, Default)]
+pub struct ChunkSource {
+    seed: u64,
+    /// The tile id of the ocean.
+    ocean: u32,
+    /// `None` for maps that are ocean apart from what was generated up front, such as islands.
+    biomes: Option<ChunkGenerator>,
+}
+
+impl ChunkSource {
+    pub fn new(seed: WorldSeed, biomes: Option<&BiomeSettings>, catalogue: &TileCatalogue) -> Self {
+        Self {
+            seed: seed.0,
+            ocean: catalogue.roles.ocean,
+            biomes: biomes.map(|settings| ChunkGenerator::new(settings, catalogue, &mut ChaCha8Rng::seed_from_u64(seed.0))),
+        }
+    }
+
+    pub fn seed(&self) -> WorldSeed {
+        WorldSeed(self.seed)
+    }
+
+    /// Whether chunks have biomes, rather than being all ocean.
+    pub fn has_biomes(&self) -> bool {
+        self.biomes.is_some()
+    }
+
+    /// The cells of a chunk in row-major order. The same chunk always gets the same cells.
+    pub fn generate(&self, chunk: IVec2) -> Vec<Cell> {
+        let key = (chunk.x as u32 as u64) << 32 | chunk.y as u32 as u64;
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed ^ key.wrapping_mul(0x9e3779b97f4a7c15));
+        match &self.biomes {
+            Some(generator) => generator.generate(chunk * CHUNK_SIZE as i32, CHUNK_SIZE, &mut rng),
+            None => {
+                let water = uvec2(self.ocean, 0);
+                (0..CHUNK_SIZE * CHUNK_SIZE).map(|_| Cell::new(water, random_prng(&mut rng))).collect()
+            }
+        }
+    }
+
+    /// Generates the window of a new infinite map.
+    pub fn generate_map(&self, shape: MapShape, catalogue: &TileCatalogue) -> HexMap {
+        let size = shape.size;
+        let mut cells = vec![Cell::default(); (size * size) as usize];
+        let chunks = size / CHUNK_SIZE;
+        for y in 0..chunks as i32 {
+            for x in 0..chunks as i32 {
+                let chunk = shape.origin() / CHUNK_SIZE as i32 + ivec2(x, y);
+                for (cell, data) in self.generate(chunk).into_iter().enumerate() {
+                    let offset = ivec2((cell as u32 % CHUNK_SIZE) as i32, (cell as u32 / CHUNK_SIZE) as i32);
+                    cells[Hex::from_axial(chunk * CHUNK_SIZE as i32 + offset).index(size)] = data;
+                }
+            }
+        }
+        HexMap::from_cells(shape, cells, catalogue)
+    }
+}
+
+/// Edited chunks of an infinite map that are outside of its window.
+///
+/// They are written to [`CHUNK_DIR`] where there is a file system, and kept in memory otherwise
+/// or when writing fails.
+#[derive(Resource, Default)]
+pub struct ChunkStore {
+    /// Chunks that are stored in files.
+    files: HashSet<IVec2>,
+    memory: HashMap<IVec2, Vec<u8>>,
+}
+
+impl ChunkStore {
+    /// Forgets all chunks, for example because a new map was started.
+    pub fn clear(&mut self) {
+        #[cfg(not(target_family = "wasm"))]
+        for &chunk in &self.files {
+            let _ = std::fs::remove_file(chunk_path(chunk));
+        }
+        self.files.clear();
+        self.memory.clear();
+    }
+
+    /// The chunks in the store.
+    pub fn chunks(&self) -> impl Iterator<Item = IVec2> + '_ {
+        self.files.iter().chain(self.memory.keys()).copied()
+    }
+
+    pub fn put(&mut self, chunk: IVec2, cells: &[Cell]) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+        for cell in cells {
+            encoder.write_all(&cell.to_texel()).expect("writing to a Vec can't fail");
+        }
+        let data = encoder.finish().expect("writing to a Vec can't fail");
+
+        #[cfg(not(target_family = "wasm"))]
+        match std::fs::create_dir_all(CHUNK_DIR).and_then(|()| std::fs::write(chunk_path(chunk), &data)) {
+            Ok(()) => {
+                self.files.insert(chunk);
+                return;
+            }
+            Err(err) => warn!("Keeping chunk {chunk} in memory, as it couldn't be written: {err}"),
+        }
+        self.memory.insert(chunk, data);
+    }
+
+    /// Reads a chunk's cells, leaving it in the store.
+    pub fn get(&self, chunk: IVec2) -> Option<Vec<Cell>> {
+        let data = match self.memory.get(&chunk) {
+            Some(data) => data.clone(),
+            #[cfg(not(target_family = "wasm"))]
+            None if self.files.contains(&chunk) => match std::fs::read(chunk_path(chunk)) {
+                Ok(data) => data,
+                Err(err) => {
+                    error!("Failed to read chunk {chunk}: {err}");
+                    return None;
+                }
+            },
+            None => return None,
+        };
+        // Reading one byte more than a chunk holds tells a chunk from a corrupt file that inflates without end.
+        let len = (CHUNK_SIZE * CHUNK_SIZE * 4) as usize;
+        let mut texels = Vec::with_capacity(len);
+        if ZlibDecoder::new(&data[..]).take(len as u64 + 1).read_to_end(&mut texels).is_err() || texels.len() != len {
+            error!("Chunk {chunk} is corrupt");
+            return None;
+        }
+        Some(texels.chunks_exact(4).map(|texel| Cell::from_texel([texel[0], texel[1], texel[2], texel[3]])).collect())
+    }
+
+    /// Takes a chunk's cells out of the store.
+    pub fn take(&mut self, chunk: IVec2) -> Option<Vec<Cell>> {
+        let cells = self.get(chunk);
+        #[cfg(not(target_family = "wasm"))]
+        if self.files.remove(&chunk) {
+            let _ = std::fs::remove_file(chunk_path(chunk));
+        }
+        self.memory.remove(&chunk);
+        cells
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn chunk_path(chunk: IVec2) -> String {
+    format!("{CHUNK_DIR}/{}_{}.chunk", chunk.x, chunk.y)
+}
+
+/// Moves the window of an infinite map once the camera gets close to its edges.
+fn follow_camera(
+    map: Option<ResMut<HexMap>>,
+    source: Res<ChunkSource>,
+    mut store: ResMut<ChunkStore>,
+    camera: Query<&GlobalTransform, With<MainCamera>>,
+) {
+    let Some(mut map) = map else {return};
+    if map.shape().boundary != MapBoundary::Infinite {return}
+    let Ok(camera) = camera.single() else {return};
+
+    let chunks = (map.size() / CHUNK_SIZE) as i32;
+    let center = chunk_of(Hex::from_world(ground_point(camera)));
+    let current = map.shape().origin() / CHUNK_SIZE as i32 + IVec2::splat(chunks / 2);
+    // Leave some slack, so that panning back and forth over the edge of a chunk doesn't move the window every time.
+    if (center - current).abs().max_element() <= 1 {return}
+
+    let origin = (center - IVec2::splat(chunks / 2)) * CHUNK_SIZE as i32;
+    let mut restored = Vec::new();
+    let mut unloaded = Vec::new();
+    map.move_window(
+        origin,
+        |chunk| match store.take(chunk) {
+            Some(cells) => {
+                restored.push(chunk);
+                cells
+            }
+            None => source.generate(chunk),
+        },
+        |chunk, cells| unloaded.push((chunk, cells)),
+    );
+    // Restored chunks were edited before, and must be stored again when they leave.
+    map.mark_edited(restored);
+    for (chunk, cells) in unloaded {
+        store.put(chunk, &cells);
+    }
+}
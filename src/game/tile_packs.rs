@@ -0,0 +1,83 @@
+//! Packs of extra tiles, dropped into a `mods` folder next to the game's `assets` folder.
+//!
+//! Each pack is a folder holding a catalogue in the same format as `assets/standard.tiles.ron`,
+//! the models it lists and optionally a colour map for them:
+//!
+//! ```text
+//! mods/
+//!     castles/
+//!         castles.tiles.ron
+//!         colormap.png
+//!         models/
+//!             keep.glb
+//! ```
+//!
+//! Paths in a pack's catalogue are relative to the catalogue, and the ids of its tiles have the
+//! name of the folder in front, as in `"castles/keep"`. Saves store these names, so a saved map
+//! that uses tiles from a pack loads as long as the pack is installed.
+//! Packs are only read on platforms with a file system.
+
+#[cfg(not(target_family = "wasm"))]
+use bevy::asset::io::{file::FileAssetReader, AssetSourceBuilder};
+use bevy::prelude::*;
+
+use super::prelude::*;
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<TilePacks>();
+}
+
+/// The asset source that packs are read from.
+#[cfg(not(target_family = "wasm"))]
+const SOURCE: &str = "mods";
+
+/// Adds the asset source for the `mods` folder, if there is one.
+/// This has to happen before the `AssetPlugin` is added.
+#[cfg(not(target_family = "wasm"))]
+pub fn register_source(app: &mut App) {
+    if FileAssetReader::get_base_path().join(SOURCE).is_dir() {
+        app.register_asset_source(SOURCE, AssetSourceBuilder::platform_default(SOURCE, None));
+    }
+}
+
+#[cfg(target_family = "wasm")]
+pub fn register_source(_app: &mut App) {}
+
+/// The catalogues of the installed packs, in the order their tiles are added to the game's own.
+#[derive(Resource)]
+pub struct TilePacks {
+    pub catalogues: Vec<Handle<TileCatalogue>>,
+}
+
+impl FromWorld for TilePacks {
+    fn from_world(world: &mut World) -> Self {
+        let assets = world.resource::<AssetServer>();
+        Self {
+            catalogues: find_catalogues().into_iter().map(|path| assets.load(path)).collect(),
+        }
+    }
+}
+
+/// The asset paths of the `.tiles.ron` files in the folders under `mods`.
+#[cfg(not(target_family = "wasm"))]
+fn find_catalogues() -> Vec<String> {
+    let Ok(packs) = std::fs::read_dir(FileAssetReader::get_base_path().join(SOURCE)) else {return Vec::new()};
+    let mut res = Vec::new();
+    for pack in packs.flatten().filter(|entry| entry.path().is_dir()) {
+        let Ok(files) = std::fs::read_dir(pack.path()) else {continue};
+        for file in files.flatten() {
+            let name = file.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".tiles.ron") {
+                res.push(format!("{SOURCE}://{}/{name}", pack.file_name().to_string_lossy()));
+            }
+        }
+    }
+    // The order decides the ids of the packs' tiles, so it mustn't depend on the file system.
+    res.sort();
+    res
+}
+
+#[cfg(target_family = "wasm")]
+fn find_catalogues() -> Vec<String> {
+    Vec::new()
+}
@@ -2,6 +2,7 @@ use std::f32::consts::PI;
 
 use bevy::{
     asset::RenderAssetUsages,
+    image::{ImageFilterMode, ImageSampler, ImageSamplerDescriptor},
     prelude::*,
     render::{
         camera::ScalingMode,
@@ -12,11 +13,52 @@ use bevy::{
 
 use crate::theme::prelude::*;
 
-use super::prelude::*;
+use super::{
+    deck::{deck_label, DeckPreview, GameMode, PREVIEW_COUNT},
+    prelude::*,
+    scoring::score_label,
+};
 
 #[derive(Resource)]
 pub struct Tileset(pub Handle<Image>);
 
+/// The [`Tileset`] with a chain of [`MIP_LEVELS`] mip levels, for drawing the map from afar.
+///
+/// The tileset camera can't render to more than one level, so the mips are made from the tileset
+/// on the compute task pool once it has been read back or loaded from the cache. Until then, they
+/// show an older view.
+#[derive(Resource)]
+pub struct TilesetMips {
+    /// The mips of the current view. Every view has an image of its own, which this switches between.
+    pub image: Handle<Image>,
+    /// Whether there are mips for the catalogue, though they may show an older view than the tileset.
+    pub ready: bool,
+}
+
+/// Steps of yaw and pitch, in degrees, that the view of the tileset snaps to.
+const ORBIT_STEP: Vec2 = vec2(15.0, 5.0);
+
+/// The view that the tileset is drawn from, as yaw and pitch in whole degrees: the rotation of the
+/// main camera, snapped to the nearest [`ORBIT_STEP`]. Turning the camera only redraws the tileset
+/// when it crosses into another step, and the tileset of each step can be kept.
+pub(super) fn orbit_step(camera: Quat) -> IVec2 {
+    let (yaw, pitch, _) = camera.to_euler(EulerRot::YXZ);
+    let step = ((vec2(yaw, pitch).map(f32::to_degrees) / ORBIT_STEP).round() * ORBIT_STEP).as_ivec2();
+    ivec2(step.x.rem_euclid(360), step.y)
+}
+
+fn orbit_rotation(step: IVec2) -> Quat {
+    let angles = step.as_vec2().map(f32::to_radians);
+    Quat::from_euler(EulerRot::YXZ, angles.x, angles.y, 0.0)
+}
+
+/// Levels of the [`TilesetMips`]. The smallest has tiles of 8 pixels, with half a pixel of padding.
+const MIP_LEVELS: u32 = 5;
+
+/// The layout of the [`Tileset`], one column per tile and one row per rotation.
+#[derive(Resource)]
+struct TilesetLayout(Handle<TextureAtlasLayout>);
+
 #[derive(Component)]
 pub struct TilesCamera;
 
@@ -40,7 +82,8 @@ pub(super) fn plugin(app: &mut App) {
     ));
     app.add_systems(Update, (
         copy_transform,
-        (keyboard_input, update_selected_tile).chain(),
+        resize_tileset.run_if(resource_exists_and_changed::<TileCatalogue>),
+        (keyboard_input, update_selected_tile).chain().run_if(resource_exists::<TileCatalogue>),
     ));
 }
 
@@ -49,11 +92,8 @@ fn setup(
     mut images: ResMut<Assets<Image>>,
     mut atlasses: ResMut<Assets<TextureAtlasLayout>>,
 ) {
-    let size = Extent3d {
-        width: TILE_SIZE * TILE_COUNT,
-        height: TILE_SIZE * 6,
-        ..default()
-    };
+    // The tileset gets its width once the tile catalogue has loaded, see `resize_tileset`.
+    let size = tileset_size(1);
 
     // This is the texture that will be rendered to.
     let mut image = Image::new_fill(
@@ -63,14 +103,19 @@ fn setup(
         TextureFormat::Bgra8UnormSrgb,
         RenderAssetUsages::default(),
     );
-    // You need to set these texture usage flags in order to use the image as a render target
+    // You need to set these texture usage flags in order to use the image as a render target,
+    // and to read it back for the tileset cache.
     image.texture_descriptor.usage =
-        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::RENDER_ATTACHMENT;
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT;
 
     let image_handle = images.add(image);
 
     // Save tileset handle in a resource
     commands.insert_resource(Tileset(image_handle.clone()));
+    commands.insert_resource(TilesetMips {
+        image: images.add(Image::default()),
+        ready: false,
+    });
 
     commands.spawn((
         Name::new("Tilesheet Camera"),
@@ -91,22 +136,51 @@ fn setup(
         RenderLayers::layer(1),
     ));
 
-    let atlas = TextureAtlasLayout::from_grid(uvec2(TILE_SIZE, TILE_SIZE), TILE_COUNT, 6, None, None);
-    let layout = atlasses.add(atlas);
+    let layout = atlasses.add(tileset_layout(1));
+    commands.insert_resource(TilesetLayout(layout.clone()));
 
     commands.spawn((
         Name::new("GUI Container"),
         Node {
             width: Val::Percent(100.0),
             height: Val::Percent(100.0),
-            flex_direction: FlexDirection::Column,
+            flex_direction: FlexDirection::Row,
             justify_content: JustifyContent::FlexEnd,
             align_items: AlignItems::FlexEnd,
+            column_gap: Val::Px(12.0),
             padding: UiRect::all(Val::Px(4.0)),
             ..default()
         },
         Pickable::IGNORE,
     )).with_children(|parent| {
+        parent.spawn(score_label());
+        parent.spawn(deck_label());
+        parent.spawn((
+            widget::label(""),
+            TileNameLabel,
+            Pickable::IGNORE,
+        ));
+        // The upcoming draws, with the next one closest to the preview.
+        for index in (0..PREVIEW_COUNT).rev() {
+            parent.spawn((
+                Name::new("Deck preview"),
+                ImageNode::from_atlas_image(
+                    image_handle.clone(),
+                    TextureAtlas {
+                        layout: layout.clone(),
+                        index: 0
+                    }
+                ),
+                Node {
+                    width: Val::Px(TILE_SIZE as f32 / 2.0),
+                    height: Val::Px(TILE_SIZE as f32 / 2.0),
+                    display: Display::None,
+                    ..default()
+                },
+                DeckPreview(index),
+                Pickable::IGNORE,
+            ));
+        }
         parent.spawn((
             Name::new("Tileset preview"),
             ImageNode::from_atlas_image(
@@ -125,7 +199,7 @@ fn setup(
             Button,
             ui_palette::BUTTON_INTERACTION_PALETTE,
             BorderRadius::all(Val::Px(30.0)),
-        )).observe(|trigger: Trigger<Pointer<Click>>, mut mouse_pos: ResMut<MousePos>| {
+        )).observe(|trigger: Trigger<Pointer<Click>>, mut mouse_pos: ResMut<MousePos>, mode: Res<GameMode>, catalogue: Res<TileCatalogue>| {
             let dir = Vec2::ONE - 2.0 * trigger.hit.position.unwrap().xy();
             if dir.x < -dir.y.abs() {
                 mouse_pos.selected_tile.y += 1;
@@ -133,48 +207,185 @@ fn setup(
             if dir.x >  dir.y.abs() {
                 mouse_pos.selected_tile.y += 5;
             }
-            if dir.y < -dir.x.abs() {
-                mouse_pos.selected_tile.x += TILE_COUNT - 1;
+            if dir.y < -dir.x.abs() && mode.free_choice() {
+                mouse_pos.selected_tile.x += catalogue.len() - 1;
             }
-            if dir.y >  dir.x.abs() {
+            if dir.y >  dir.x.abs() && mode.free_choice() {
                 mouse_pos.selected_tile.x += 1;
             }
-            mouse_pos.selected_tile %= uvec2(TILE_COUNT, 6);
-        }).observe(|trigger: Trigger<Pointer<Scroll>>, mut mouse_pos: ResMut<MousePos>| {
+            mouse_pos.selected_tile %= uvec2(catalogue.len(), 6);
+        }).observe(|trigger: Trigger<Pointer<Scroll>>, mut mouse_pos: ResMut<MousePos>, mode: Res<GameMode>, catalogue: Res<TileCatalogue>| {
             if trigger.x < 0.0 {
                 mouse_pos.selected_tile.y += 1;
             }
             if trigger.x > 0.0 {
                 mouse_pos.selected_tile.y += 5;
             }
-            if trigger.y < 0.0 {
-                mouse_pos.selected_tile.x += TILE_COUNT - 1;
+            if trigger.y < 0.0 && mode.free_choice() {
+                mouse_pos.selected_tile.x += catalogue.len() - 1;
             }
-            if trigger.y > 0.0 {
+            if trigger.y > 0.0 && mode.free_choice() {
                 mouse_pos.selected_tile.x += 1;
             }
-            mouse_pos.selected_tile %= uvec2(TILE_COUNT, 6);
+            mouse_pos.selected_tile %= uvec2(catalogue.len(), 6);
         });
     });
 }
 
-fn copy_transform(main: Query<&Transform, With<MainCamera>>, mut tiles: Query<(&mut Transform, &Tile), Without<MainCamera>>) {
+/// Pixels from one tile in the tileset to the next.
+pub(super) const TILE_STRIDE: u32 = TILE_SIZE + 2 * TILE_PADDING;
+
+pub(super) fn tileset_size(tile_count: u32) -> Extent3d {
+    Extent3d {
+        width: TILE_STRIDE * tile_count,
+        height: TILE_STRIDE * 6,
+        ..default()
+    }
+}
+
+fn tileset_layout(tile_count: u32) -> TextureAtlasLayout {
+    TextureAtlasLayout::from_grid(
+        uvec2(TILE_SIZE, TILE_SIZE),
+        tile_count,
+        6,
+        Some(UVec2::splat(2 * TILE_PADDING)),
+        Some(UVec2::splat(TILE_PADDING)),
+    )
+}
+
+/// Makes the [`TilesetMips`] from the pixels of the tileset, with the padding around the tiles filled in.
+pub(super) fn tileset_mips(data: &[u8], size: Extent3d) -> Image {
+    let mut level = data.to_vec();
+    pad_tiles(&mut level, size);
+    let mut image = Image::new(size, TextureDimension::D2, level.clone(), TextureFormat::Bgra8UnormSrgb, RenderAssetUsages::RENDER_WORLD);
+    let (mut width, mut height) = (size.width as usize, size.height as usize);
+    let mut chain = level.clone();
+    for _ in 1..MIP_LEVELS {
+        level = downsample(&level, width, height);
+        width /= 2;
+        height /= 2;
+        chain.extend_from_slice(&level);
+    }
+    image.data = Some(chain);
+    image.texture_descriptor.mip_level_count = MIP_LEVELS;
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        mipmap_filter: ImageFilterMode::Linear,
+        ..ImageSamplerDescriptor::linear()
+    });
+    image
+}
+
+/// Fills the padding around every tile with the nearest pixel of the tile, so that filtering near the
+/// edge of a tile doesn't mix in empty pixels, even at the smallest mip level, where the padding is
+/// only half a pixel wide.
+fn pad_tiles(data: &mut [u8], size: Extent3d) {
+    let nearest = |texel: u32| {
+        let offset = texel % TILE_STRIDE;
+        texel - offset + offset.clamp(TILE_PADDING, TILE_PADDING + TILE_SIZE - 1)
+    };
+    let width = size.width as usize;
+    for y in 0..size.height {
+        for x in 0..size.width {
+            let (from_x, from_y) = (nearest(x), nearest(y));
+            if (from_x, from_y) == (x, y) {continue}
+            let from = (from_y as usize * width + from_x as usize) * 4;
+            data.copy_within(from..from + 4, (y as usize * width + x as usize) * 4);
+        }
+    }
+}
+
+/// Halves an image in the sRGB format of the tileset. The pixels are averaged in linear colour
+/// and weighted by their alpha, so that transparent pixels don't darken the edges of the models.
+fn downsample(data: &[u8], width: usize, height: usize) -> Vec<u8> {
+    // Tables for converting between sRGB and linear colour, as this runs on millions of pixels.
+    let linear: [f32; 256] = std::array::from_fn(|value| Srgba::gamma_function(value as f32 / 255.0));
+    let srgb: [u8; 4096] = std::array::from_fn(|value| (Srgba::gamma_function_inverse(value as f32 / 4095.0) * 255.0).round() as u8);
+    let mut res = Vec::with_capacity(data.len() / 4);
+    for y in 0..height / 2 {
+        for x in 0..width / 2 {
+            let mut sum = [0.0; 4];
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let pixel = &data[((2 * y + dy) * width + 2 * x + dx) * 4..][..4];
+                let alpha = pixel[3] as f32 / 255.0;
+                for channel in 0..3 {
+                    sum[channel] += linear[pixel[channel] as usize] * alpha;
+                }
+                sum[3] += alpha;
+            }
+            for channel in 0..3 {
+                let color = if sum[3] > 0.0 {sum[channel] / sum[3]} else {0.0};
+                res.push(srgb[(color * 4095.0).round() as usize]);
+            }
+            res.push((sum[3] / 4.0 * 255.0).round() as u8);
+        }
+    }
+    res
+}
+
+/// Fits the tileset to the number of tiles in the catalogue.
+fn resize_tileset(
+    catalogue: Res<TileCatalogue>,
+    tileset: Res<Tileset>,
+    layout: Res<TilesetLayout>,
+    mut images: ResMut<Assets<Image>>,
+    mut atlasses: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    let count = catalogue.len().max(1);
+    if let Some(image) = images.get_mut(&tileset.0) {
+        image.resize(tileset_size(count));
+    }
+    if let Some(atlas) = atlasses.get_mut(&layout.0) {
+        *atlas = tileset_layout(count);
+    }
+}
+
+/// Turns the tiles to the [`orbit_step`] of the main camera. Only new tiles are touched while the step
+/// stays the same, so that the tileset camera sees nothing change.
+fn copy_transform(
+    main: Query<&Transform, With<MainCamera>>,
+    mut tiles: Query<(&mut Transform, Ref<Tile>), Without<MainCamera>>,
+    mut last: Local<Option<IVec2>>,
+) {
     let Ok(main) = main.single() else {return};
-    let base = main.rotation.inverse();
+    let step = orbit_step(main.rotation);
+    let changed = *last != Some(step);
+    *last = Some(step);
+    let base = orbit_rotation(step).inverse();
     for (mut transform, tile) in tiles.iter_mut() {
-        transform.rotation = base * tile.rotation;
+        if changed || tile.is_added() {
+            transform.rotation = base * tile.rotation;
+        }
     }
 }
 
-fn update_selected_tile(mouse_pos: Res<MousePos>, mut image: Query<&mut ImageNode, With<Button>>) {
-    let Ok(mut image) = image.single_mut() else {return};
+/// Shows the name of the selected tile next to the tileset preview.
+#[derive(Component)]
+struct TileNameLabel;
+
+fn update_selected_tile(
+    mouse_pos: Res<MousePos>,
+    catalogue: Res<TileCatalogue>,
+    mut image: Query<&mut ImageNode, With<Button>>,
+    mut label: Query<&mut Text, With<TileNameLabel>>,
+) {
     let index = mouse_pos.selected_tile;
-    image.texture_atlas.as_mut().unwrap().index = (index.x + index.y * TILE_COUNT) as usize;
+    if let Ok(mut image) = image.single_mut() {
+        image.texture_atlas.as_mut().unwrap().index = (index.x + index.y * catalogue.len()) as usize;
+    }
+    let Some(tile) = catalogue.tiles.get(index.x as usize) else {return};
+    for mut text in label.iter_mut() {
+        let name = format!("{} ({})", tile.name, tile.category.label());
+        if text.0 != name {
+            text.0 = name;
+        }
+    }
 }
 
 fn keyboard_input(
     keys: Res<ButtonInput<KeyCode>>,
     mut mouse_pos: ResMut<MousePos>,
+    mode: Res<GameMode>,
+    catalogue: Res<TileCatalogue>,
 ) {
     if keys.just_pressed(KeyCode::ArrowLeft) {
         mouse_pos.selected_tile.y += 1;
@@ -182,11 +393,96 @@ fn keyboard_input(
     if keys.just_released(KeyCode::ArrowRight) {
         mouse_pos.selected_tile.y += 5;
     }
-    if keys.just_pressed(KeyCode::ArrowUp) {
-        mouse_pos.selected_tile.x += TILE_COUNT - 1;
+    if keys.just_pressed(KeyCode::ArrowUp) && mode.free_choice() {
+        mouse_pos.selected_tile.x += catalogue.len() - 1;
     }
-    if keys.just_pressed(KeyCode::ArrowDown) {
+    if keys.just_pressed(KeyCode::ArrowDown) && mode.free_choice() {
         mouse_pos.selected_tile.x += 1;
     }
-    mouse_pos.selected_tile %= uvec2(TILE_COUNT, 6);
+    mouse_pos.selected_tile %= uvec2(catalogue.len(), 6);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera(yaw: f32, pitch: f32) -> Quat {
+        Quat::from_euler(EulerRot::YXZ, yaw.to_radians(), pitch.to_radians(), 0.0)
+    }
+
+    #[test]
+    fn views_snap_to_orbit_steps() {
+        assert_eq!(orbit_step(camera(31.0, -58.0)), ivec2(30, -60));
+        assert_eq!(orbit_step(camera(37.0, -57.0)), ivec2(30, -55));
+        // Turning all the way around ends up at the same step.
+        assert_eq!(orbit_step(camera(-179.0, -45.0)), orbit_step(camera(179.0, -45.0)));
+        assert_eq!(orbit_step(camera(-10.0, -45.0)), ivec2(345, -45));
+        for step in [ivec2(0, -45), ivec2(165, -60), ivec2(300, -30)] {
+            assert_eq!(orbit_step(orbit_rotation(step)), step);
+        }
+    }
+
+    /// A tileset of two tiles, where `pixel` gives the colour of every pixel inside a tile
+    /// from its column, row and position in the tile. The padding is empty.
+    fn tileset(pixel: impl Fn(u32, u32, UVec2) -> [u8; 4]) -> (Vec<u8>, Extent3d) {
+        let size = tileset_size(2);
+        let mut data = vec![0; (size.width * size.height * 4) as usize];
+        for y in 0..size.height {
+            for x in 0..size.width {
+                let offset = uvec2(x % TILE_STRIDE, y % TILE_STRIDE);
+                if offset.cmplt(UVec2::splat(TILE_PADDING)).any() || offset.cmpge(UVec2::splat(TILE_PADDING + TILE_SIZE)).any() {continue}
+                let index = ((y * size.width + x) * 4) as usize;
+                data[index..index + 4].copy_from_slice(&pixel(x / TILE_STRIDE, y / TILE_STRIDE, offset - TILE_PADDING));
+            }
+        }
+        (data, size)
+    }
+
+    #[test]
+    fn padding_repeats_the_edges_of_tiles() {
+        let (mut data, size) = tileset(|column, row, offset| [offset.x as u8, offset.y as u8, (column * 6 + row) as u8, 255]);
+        pad_tiles(&mut data, size);
+        let texel = |x: u32, y: u32| {
+            let index = ((y * size.width + x) * 4) as usize;
+            <[u8; 4]>::try_from(&data[index..index + 4]).unwrap()
+        };
+        let last = TILE_SIZE as u8 - 1;
+        for (column, row) in [(0, 0), (1, 0), (1, 5)] {
+            let (x, y) = (column * TILE_STRIDE, row * TILE_STRIDE);
+            let id = (column * 6 + row) as u8;
+            // Corners take the corner of the tile, and sides the texel straight across.
+            assert_eq!(texel(x, y), [0, 0, id, 255]);
+            assert_eq!(texel(x + TILE_STRIDE - 1, y + TILE_STRIDE - 1), [last, last, id, 255]);
+            assert_eq!(texel(x + TILE_PADDING + 5, y), [5, 0, id, 255]);
+            assert_eq!(texel(x + TILE_STRIDE - 1, y + TILE_PADDING + 7), [last, 7, id, 255]);
+            assert_eq!(texel(x + 2, y + TILE_PADDING + 9), [0, 9, id, 255]);
+            // The tile itself is left alone.
+            assert_eq!(texel(x + TILE_PADDING + 3, y + TILE_PADDING + 4), [3, 4, id, 255]);
+        }
+    }
+
+    #[test]
+    fn each_mip_level_halves_the_tileset() {
+        // Colours that survive the round trip through linear colour exactly.
+        let colour = |column: u32, row: u32| [64 + 40 * column as u8, 100 + 30 * row as u8, 200, 255];
+        let (data, size) = tileset(|column, row, _| colour(column, row));
+        let image = tileset_mips(&data, size);
+        assert_eq!(image.texture_descriptor.mip_level_count, MIP_LEVELS);
+        let chain = image.data.unwrap();
+
+        let mut offset = 0;
+        for level in 0..MIP_LEVELS {
+            let (width, height, stride) = (size.width >> level, size.height >> level, TILE_STRIDE >> level);
+            assert_eq!(stride << level, TILE_STRIDE, "level {level} splits tiles");
+            // With the padding filled in, not even the smallest level fades out toward the edges of the tiles.
+            for y in 0..height {
+                for x in 0..width {
+                    let index = offset + ((y * width + x) * 4) as usize;
+                    assert_eq!(chain[index..index + 4], colour(x / stride, y / stride), "level {level} at {x},{y}");
+                }
+            }
+            offset += (width * height * 4) as usize;
+        }
+        assert_eq!(offset, chain.len());
+    }
 }
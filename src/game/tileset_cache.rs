@@ -0,0 +1,469 @@
+//! A cache of rendered tilesets on disk, so the tile models don't have to be rendered on every run.
+//!
+//! The tileset depends on the tile catalogue, the [`orbit_step`] of the main camera and [`TILE_SIZE`],
+//! which together make up the name of the cached image. The catalogue is identified by the
+//! contents of its model and colour map files, which are hashed in the background whenever
+//! the catalogue changes, so that edited models don't show a stale tileset.
+//!
+//! The tileset camera only renders when the view crosses into another step, and keeps rendering
+//! until every tile has been drawn once, as the first frames can miss models whose pipelines are
+//! still compiling. When there is no cached image for the current view, the tileset is read back
+//! once the view has been still for a few frames and written to [`CACHE_DIR`] in the background,
+//! which keeps the [`MAX_CACHED`] most recent views. The [`MAX_IN_MEMORY`] most recently shown
+//! views are also kept in memory, so that turning back and forth doesn't touch the disk at all.
+//! Their mips are made in the background, and the mips of the last view stay in place meanwhile.
+//!
+//! Running the game with `--bake-tileset` bakes the tileset for the starting view without
+//! opening a window, and exits once it has been written.
+
+use std::hash::Hasher;
+
+#[cfg(not(target_family = "wasm"))]
+use bevy::{
+    asset::{io::{file::FileAssetReader, AssetSourceId}, AssetPath, RenderAssetUsages},
+    image::{CompressedImageFormats, ImageSampler, ImageType},
+    render::render_resource::{TextureDimension, TextureFormat},
+};
+use bevy::{
+    prelude::*,
+    render::{
+        gpu_readback::{Readback, ReadbackComplete},
+        render_resource::Extent3d,
+    },
+    tasks::{futures::check_ready, AsyncComputeTaskPool, IoTaskPool, Task},
+};
+
+use super::{
+    prelude::*,
+    tileset::{orbit_step, tileset_mips, tileset_size, TilesCamera, TILE_STRIDE},
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<TilesetCache>();
+    app.insert_resource(BakeOnly(bake_from_args(std::env::args())));
+    app.add_systems(Update, update_tileset.run_if(resource_exists::<TileCatalogue>));
+}
+
+/// Rendered tilesets are written to this directory, on platforms with a file system.
+#[cfg(not(target_family = "wasm"))]
+const CACHE_DIR: &str = "sprawl-tileset";
+
+/// Frames that the view has to be still for before its tileset is baked.
+const SETTLE_FRAMES: u32 = 3;
+
+/// Tilesets of this many views are kept in memory, see [`TilesetCache::atlases`].
+const MAX_IN_MEMORY: usize = 8;
+
+/// Tilesets beyond this many are removed from the cache, oldest first.
+#[cfg(not(target_family = "wasm"))]
+const MAX_CACHED: usize = 32;
+
+/// Whether the game was started with `--bake-tileset`, see [`bake_from_args`].
+#[derive(Resource, Clone, Copy)]
+struct BakeOnly(bool);
+
+/// Looks for `--bake-tileset`, which makes the game bake the tileset and exit.
+pub fn bake_from_args(mut args: impl Iterator<Item = String>) -> bool {
+    args.any(|arg| arg == "--bake-tileset")
+}
+
+/// 64 bit FNV-1a. Unlike [`std::hash::DefaultHasher`], it gives the same hash in every build,
+/// so the names of cached tilesets stay valid across runs and versions of the game.
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Hashes the models and colour maps of the tiles, in the order of the catalogue.
+fn hash_catalogue(files: Vec<(String, Option<String>)>) -> u64 {
+    let mut hasher = StableHasher::default();
+    for (model, colormap) in &files {
+        hash_file(model, &mut hasher);
+        if let Some(colormap) = colormap {
+            hash_file(colormap, &mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+/// Hashes a length-prefixed byte string. The `Hash` impls of `str` and `[u8]` aren't used,
+/// as their framing may differ between platforms and versions of Rust.
+fn hash_bytes(bytes: &[u8], hasher: &mut StableHasher) {
+    hasher.write(&(bytes.len() as u64).to_le_bytes());
+    hasher.write(bytes);
+}
+
+/// Hashes the path and contents of an asset. Files that can't be read only contribute their path.
+#[cfg(not(target_family = "wasm"))]
+fn hash_file(path: &str, hasher: &mut StableHasher) {
+    hash_bytes(path.as_bytes(), hasher);
+    let path = AssetPath::parse(path);
+    let root = match path.source() {
+        AssetSourceId::Default => "assets",
+        AssetSourceId::Name(name) => name,
+    };
+    if let Ok(bytes) = std::fs::read(FileAssetReader::get_base_path().join(root).join(path.path())) {
+        hash_bytes(&bytes, hasher);
+    }
+}
+
+/// There is no cache to invalidate on the web, so the path is enough.
+#[cfg(target_family = "wasm")]
+fn hash_file(path: &str, hasher: &mut StableHasher) {
+    hash_bytes(path.as_bytes(), hasher);
+}
+
+/// Everything the rendered tileset depends on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TilesetKey {
+    /// Hash of the models and colour maps of the tiles, see [`hash_catalogue`].
+    catalogue: u64,
+    /// Yaw and pitch of the view, in whole degrees, see [`orbit_step`].
+    yaw: i32,
+    pitch: i32,
+}
+
+impl TilesetKey {
+    fn new(catalogue: u64, camera: Quat) -> Self {
+        let step = orbit_step(camera);
+        Self {
+            catalogue,
+            yaw: step.x,
+            pitch: step.y,
+        }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    fn path(&self) -> String {
+        format!("{CACHE_DIR}/{:016x}_{}_{}_{TILE_SIZE}.png", self.catalogue, self.yaw, self.pitch)
+    }
+}
+
+#[derive(Resource, Default)]
+struct TilesetCache {
+    /// Hashes the files of the catalogue after it changed.
+    hashing: Option<Task<u64>>,
+    /// The hash of the current catalogue, once it is known.
+    hash: Option<u64>,
+    /// The catalogue that the rest of the state is for, see [`TilesetKey::catalogue`].
+    catalogue: Option<u64>,
+    /// The view that the tileset is being made for.
+    current: Option<TilesetKey>,
+    /// The view that the tileset was last rendered or loaded for.
+    shown: Option<TilesetKey>,
+    /// Whether the tileset camera has drawn every tile before. Until then their pipelines may
+    /// still be compiling, and a single frame isn't enough to render the tileset.
+    warm: bool,
+    /// Frames that the view has been still for, since the models have loaded.
+    still_frames: u32,
+    /// The view that the shown mips were made for. They stay in place while the mips of
+    /// another view are made, as they are closer to it than the bare tileset.
+    baked: Option<TilesetKey>,
+    /// The mips of the views that were baked or loaded most recently, last in the list,
+    /// so that turning back to one of them doesn't touch the disk.
+    atlases: Vec<(TilesetKey, Handle<Image>)>,
+    /// Reads the tileset of a view from the cache directory in the background.
+    loading: Option<(TilesetKey, Task<Option<Vec<u8>>>)>,
+    /// Makes the mips of a view in the background, see [`build_mips`], and whether its tileset was read back.
+    building: Option<(TilesetKey, bool, Task<Option<(Vec<u8>, Image)>>)>,
+    /// Whether the tileset is being read back.
+    reading: bool,
+}
+
+impl TilesetCache {
+    /// Shows the mips of a view if they are in memory.
+    fn show(&mut self, key: TilesetKey, mips: &mut TilesetMips) -> bool {
+        let Some(index) = self.atlases.iter().position(|(other, _)| *other == key) else {return false};
+        let atlas = self.atlases.remove(index);
+        mips.image = atlas.1.clone();
+        self.atlases.push(atlas);
+        self.baked = Some(key);
+        true
+    }
+
+    /// Keeps the mips of a view in memory, forgetting the least recently shown view if there are too many.
+    fn add(&mut self, key: TilesetKey, image: Handle<Image>) {
+        if self.atlases.len() >= MAX_IN_MEMORY {
+            self.atlases.remove(0);
+        }
+        self.atlases.push((key, image));
+    }
+}
+
+/// Renders the tileset when the view changes, or loads it from memory or the cache if it is there.
+/// Once the view has been still for a while, an uncached tileset is baked.
+fn update_tileset(
+    mut commands: Commands,
+    mut cache: ResMut<TilesetCache>,
+    catalogue: Res<TileCatalogue>,
+    tileset: Res<Tileset>,
+    mut mips: ResMut<TilesetMips>,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    materials: Res<Assets<StandardMaterial>>,
+    main: Single<&Transform, With<MainCamera>>,
+    mut camera: Single<&mut Camera, With<TilesCamera>>,
+    models: Query<(&Mesh3d, &MeshMaterial3d<StandardMaterial>), With<Tile>>,
+    bake_only: Res<BakeOnly>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let cache = &mut *cache;
+    let size = tileset_size(catalogue.len().max(1));
+    // Wait for the tileset to be resized to the catalogue.
+    if images.get(&tileset.0).is_none_or(|image| image.texture_descriptor.size != size) {
+        mips.ready = false;
+        return;
+    }
+
+    if catalogue.is_changed() {
+        let files = catalogue.tiles.iter().map(|tile| (tile.path.clone(), tile.colormap.clone())).collect();
+        cache.hashing = Some(IoTaskPool::get().spawn(async move { hash_catalogue(files) }));
+        cache.hash = None;
+    }
+    if let Some(hash) = cache.hashing.as_mut().and_then(check_ready) {
+        cache.hashing = None;
+        cache.hash = Some(hash);
+    }
+    let Some(hash) = cache.hash else {
+        // Show the models until it is known which tilesets of the cache are for them.
+        mips.ready = false;
+        camera.is_active = true;
+        return;
+    };
+
+    let key = TilesetKey::new(hash, main.rotation);
+    if cache.catalogue != Some(key.catalogue) {
+        *cache = TilesetCache {
+            hash: cache.hash,
+            catalogue: Some(key.catalogue),
+            reading: cache.reading,
+            ..default()
+        };
+    }
+    if cache.current != Some(key) {
+        cache.current = Some(key);
+        cache.loading = None;
+        cache.building = None;
+        if !cache.show(key, &mut mips) {
+            cache.loading = Some((key, IoTaskPool::get().spawn(async move { read_cache(&key, size) })));
+        }
+    }
+    if let Some((loading, task)) = &mut cache.loading && let Some(data) = check_ready(task) {
+        let loading = *loading;
+        cache.loading = None;
+        if let Some(data) = data {
+            cache.building = Some((loading, false, build_mips(loading, size, data, false, false)));
+        }
+    }
+    if let Some((building, read_back, task)) = &mut cache.building && let Some(built) = check_ready(task) {
+        let (building, read_back) = (*building, *read_back);
+        cache.building = None;
+        // Tilesets that were read back may have missed some tiles, and the camera keeps rendering until they don't.
+        if let Some((data, image)) = built {
+            cache.warm |= read_back;
+            cache.add(building, images.add(image));
+            cache.show(building, &mut mips);
+            // Keep the pixels with the image, so they survive it being uploaded again while the camera is off.
+            if let Some(image) = images.get_mut(&tileset.0) {
+                image.data = Some(data);
+            }
+            cache.shown = Some(building);
+        }
+    }
+    // The mips of another view are better than none while those of this one are made.
+    mips.ready = cache.baked.is_some();
+    if bake_only.0 && cache.baked == Some(key) {
+        exit.write(AppExit::Success);
+        return;
+    }
+
+    let ready = models.iter().count() == catalogue.tiles.len() * 6 && models.iter().all(|(mesh, material)| {
+        asset_server.is_loaded_with_dependencies(&mesh.0)
+            && materials.get(&material.0)
+                .and_then(|material| material.base_color_texture.as_ref())
+                .is_none_or(|texture| asset_server.is_loaded_with_dependencies(texture))
+    });
+    if !ready {
+        // Show the models as they come in, unless there is a complete tileset from the cache.
+        camera.is_active = cache.baked != Some(key);
+        cache.still_frames = 0;
+        return;
+    }
+
+    let changed = cache.shown != Some(key);
+    camera.is_active = changed || (!cache.warm && cache.baked != Some(key));
+    cache.shown = Some(key);
+    cache.still_frames = if changed {0} else {cache.still_frames + 1};
+    let busy = cache.reading || cache.loading.is_some() || cache.building.is_some();
+    if busy || cache.baked == Some(key) || cache.still_frames < SETTLE_FRAMES {return}
+
+    cache.reading = true;
+    let bake_only = bake_only.0;
+    commands.spawn(Readback::texture(tileset.0.clone())).observe(move |
+        trigger: Trigger<ReadbackComplete>,
+        mut commands: Commands,
+        mut cache: ResMut<TilesetCache>,
+    | {
+        commands.entity(trigger.target()).despawn();
+        cache.reading = false;
+        // The view changed while the tileset was read back, so it has to be rendered again.
+        if cache.current != Some(key) {return}
+        cache.building = Some((key, true, build_mips(key, size, trigger.event().0.clone(), true, bake_only)));
+    });
+}
+
+/// Makes the mips of a tileset on the [`AsyncComputeTaskPool`], returning the tileset's pixels along with them.
+///
+/// A tileset that was `read_back` still has padded rows. If it misses any tiles, because their pipelines weren't
+/// ready yet, this returns `None`. Otherwise it is also written to the cache, see [`write_cache`].
+fn build_mips(key: TilesetKey, size: Extent3d, data: Vec<u8>, read_back: bool, bake_only: bool) -> Task<Option<(Vec<u8>, Image)>> {
+    AsyncComputeTaskPool::get().spawn(async move {
+        let data = if read_back {unpadded(&data, size)} else {data};
+        if read_back {
+            if !all_tiles_drawn(&data, size) {return None}
+            write_cache(&key, size, data.clone(), bake_only);
+        }
+        let mips = tileset_mips(&data, size);
+        Some((data, mips))
+    })
+}
+
+/// Whether every cell of a tileset in the BGRA layout has some opaque pixels.
+fn all_tiles_drawn(data: &[u8], size: Extent3d) -> bool {
+    let columns = size.width / TILE_STRIDE;
+    let mut drawn = vec![false; (columns * 6) as usize];
+    for (index, pixel) in data.chunks_exact(4).enumerate() {
+        if pixel[3] != 0 {
+            let x = index as u32 % size.width / TILE_STRIDE;
+            let y = index as u32 / size.width / TILE_STRIDE;
+            drawn[(x + y * columns) as usize] = true;
+        }
+    }
+    drawn.into_iter().all(|drawn| drawn)
+}
+
+/// Removes the padding at the end of the rows of a texture that was read back.
+fn unpadded(data: &[u8], size: Extent3d) -> Vec<u8> {
+    let row = data.len() / size.height as usize;
+    data.chunks_exact(row).flat_map(|line| &line[..size.width as usize * 4]).copied().collect()
+}
+
+/// The cached tileset for a view, in the pixel format of the tileset. This blocks on the file system
+/// and decoding, so it runs on the [`IoTaskPool`].
+#[cfg(not(target_family = "wasm"))]
+fn read_cache(key: &TilesetKey, size: Extent3d) -> Option<Vec<u8>> {
+    let bytes = std::fs::read(key.path()).ok()?;
+    let image = match Image::from_buffer(&bytes, ImageType::Extension("png"), CompressedImageFormats::NONE, true, ImageSampler::Default, RenderAssetUsages::default()) {
+        Ok(image) => image,
+        Err(err) => {
+            warn!("Ignoring cached tileset {}: {err}", key.path());
+            return None;
+        }
+    };
+    if image.texture_descriptor.size != size {
+        return None;
+    }
+    // The cache is RGBA, the tileset BGRA.
+    let mut data = image.data?;
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    Some(data)
+}
+
+#[cfg(target_family = "wasm")]
+fn read_cache(_key: &TilesetKey, _size: Extent3d) -> Option<Vec<u8>> {
+    None
+}
+
+/// Writes a tileset to the cache. This happens in the background, unless the game only bakes the tileset.
+#[cfg(not(target_family = "wasm"))]
+fn write_cache(key: &TilesetKey, size: Extent3d, mut data: Vec<u8>, bake_only: bool) {
+    let path = key.path();
+    let write = move || {
+        // The tileset is BGRA, the cache RGBA.
+        for pixel in data.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+        let image = Image::new(size, TextureDimension::D2, data, TextureFormat::Rgba8UnormSrgb, RenderAssetUsages::default());
+        let result = image.try_into_dynamic().map_err(|err| err.to_string())
+            .and_then(|image| {
+                std::fs::create_dir_all(CACHE_DIR).map_err(|err| err.to_string())?;
+                image.save(&path).map_err(|err| err.to_string())
+            });
+        match result {
+            Ok(()) => info!("Baked tileset to {path}"),
+            Err(err) => warn!("Failed to write tileset to {path}: {err}"),
+        }
+        prune_cache();
+    };
+    if bake_only {
+        write();
+    } else {
+        IoTaskPool::get().spawn(async move { write() }).detach();
+    }
+}
+
+/// Removes the least recently written tilesets, so that looking around doesn't fill up the disk.
+#[cfg(not(target_family = "wasm"))]
+fn prune_cache() {
+    let Ok(entries) = std::fs::read_dir(CACHE_DIR) else {return};
+    let mut files: Vec<_> = entries.flatten()
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .collect();
+    if files.len() <= MAX_CACHED {return}
+    files.sort();
+    for (_, path) in &files[..files.len() - MAX_CACHED] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[cfg(target_family = "wasm")]
+fn write_cache(_key: &TilesetKey, _size: Extent3d, _data: Vec<u8>, _bake_only: bool) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_are_stable() {
+        // Reference values of 64 bit FNV-1a.
+        for (input, hash) in [(&b""[..], 0xcbf29ce484222325), (b"a", 0xaf63dc4c8601ec8c), (b"foobar", 0x85944171f73967e8)] {
+            let mut hasher = StableHasher::default();
+            hasher.write(input);
+            assert_eq!(hasher.finish(), hash);
+        }
+
+        // A file that doesn't exist only contributes its length-prefixed path.
+        let mut hasher = StableHasher::default();
+        hash_file("models/missing.glb", &mut hasher);
+        assert_eq!(hasher.finish(), 0x6627468b79f006f7);
+
+        // One that does exist adds its length-prefixed contents.
+        let path = "models/bridge-path-a.glb";
+        let bytes = std::fs::read(format!("assets/{path}")).unwrap();
+        let mut expected = StableHasher::default();
+        for part in [path.as_bytes(), &bytes] {
+            expected.write(&(part.len() as u64).to_le_bytes());
+            expected.write(part);
+        }
+        let mut hasher = StableHasher::default();
+        hash_file(path, &mut hasher);
+        assert_eq!(hasher.finish(), expected.finish());
+    }
+}
@@ -0,0 +1,450 @@
+//! Wave function collapse over the tile edges.
+//!
+//! Every cell starts out allowing all rotated tiles. The cell with the fewest options is
+//! repeatedly collapsed to a single tile, after which the edge constraints are propagated
+//! to its neighbours. When a cell runs out of options, the most recent choice is undone and
+//! banned, and the search continues from there.
+
+use std::{cmp::Reverse, collections::BinaryHeap, fmt};
+
+use bevy::prelude::*;
+use rand::Rng;
+
+use super::{hex_map::{random_prng, Cell}, prelude::*};
+
+pub(super) fn plugin(app: &mut App) {
+    app.register_type::<WfcSettings>();
+    app.init_resource::<WfcSettings>();
+}
+
+/// Parameters for generating terrain with wave function collapse.
+#[derive(Resource, Reflect, Clone, Debug)]
+#[reflect(Resource)]
+pub struct WfcSettings {
+    /// Size of the generated region, in cells.
+    pub size: UVec2,
+    /// Number of choices that may be undone before giving up.
+    pub max_backtracks: u32,
+}
+
+impl Default for WfcSettings {
+    fn default() -> Self {
+        Self {
+            size: uvec2(128, 128),
+            max_backtracks: 10000,
+        }
+    }
+}
+
+/// The solver failed to find tiles for the cell at `cell`, relative to the region.
+#[derive(Debug)]
+pub struct WfcError {
+    pub cell: UVec2,
+    pub backtracks: u32,
+}
+
+impl fmt::Display for WfcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "contradiction at cell {} after {} backtracks", self.cell, self.backtracks)
+    }
+}
+
+const EDGES: usize = Edge::ALL.len();
+
+/// The tile variants and their precomputed adjacency rules.
+pub struct Wfc {
+    /// Tile id and rotation of each variant.
+    variants: Vec<UVec2>,
+    weights: Vec<f32>,
+    /// Number of `u64` words in a set of variants.
+    words: usize,
+    /// Indexed by `side * EDGES + edge`: the variants that have `edge` at `side`.
+    has_edge: Vec<u64>,
+    /// Indexed by `side * EDGES + edge`: the variants that may be the neighbour at `side`
+    /// of a tile with `edge` at that side.
+    fits: Vec<u64>,
+    /// The unrotated edges of every tile, for the cells around the region.
+    tile_edges: Vec<EdgeSet>,
+}
+
+impl Wfc {
+    /// Collects every distinct rotation of every tile. Each tile's weight from the catalogue is
+    /// split evenly over its distinct rotations.
+    pub fn new(catalogue: &TileCatalogue) -> Self {
+        let mut variants = Vec::new();
+        let mut weights = Vec::new();
+        for (tile, info) in (0..).zip(&catalogue.tiles) {
+            let mut edges = Vec::new();
+            for rotation in 0..6 {
+                let rotated = info.edges.rotated(rotation);
+                if !edges.contains(&rotated) {
+                    edges.push(rotated);
+                    variants.push(uvec2(tile, rotation));
+                }
+            }
+            weights.extend(std::iter::repeat_n(info.weight / edges.len() as f32, edges.len()));
+        }
+        let tile_edges: Vec<EdgeSet> = catalogue.tiles.iter().map(|tile| tile.edges).collect();
+
+        let words = variants.len().div_ceil(64);
+        let mut has_edge = vec![0; 6 * EDGES * words];
+        let mut fits = vec![0; 6 * EDGES * words];
+        for (index, &variant) in variants.iter().enumerate() {
+            let edges = tile_edges[variant.x as usize].rotated(variant.y);
+            let bit = 1 << (index % 64);
+            for side in 0..6 {
+                has_edge[(side * EDGES + edges.get(side) as usize) * words + index / 64] |= bit;
+                for edge in Edge::ALL {
+                    if edge.fits(edges.get(side + 3)) {
+                        fits[(side * EDGES + edge as usize) * words + index / 64] |= bit;
+                    }
+                }
+            }
+        }
+
+        Self {variants, weights, words, has_edge, fits, tile_edges}
+    }
+
+    fn has_edge(&self, side: usize, edge: usize) -> &[u64] {
+        let start = (side * EDGES + edge) * self.words;
+        &self.has_edge[start..start + self.words]
+    }
+
+    fn fits(&self, side: usize, edge: usize) -> &[u64] {
+        let start = (side * EDGES + edge) * self.words;
+        &self.fits[start..start + self.words]
+    }
+
+    /// Fills a `size` region of the map starting at texel `origin`.
+    ///
+    /// Cells just outside the region constrain the tiles along its border. If the region
+    /// spans the whole of a wrapping map along an axis, it wraps around along that axis instead.
+    pub fn generate(
+        &self,
+        map: &mut HexMap,
+        origin: IVec2,
+        size: UVec2,
+        max_backtracks: u32,
+        rng: &mut impl Rng,
+    ) -> Result<u32, WfcError> {
+        let wrap = size.cmpeq(UVec2::splat(map.size())) & BVec2::splat(map.shape().wraps());
+        let (tiles, backtracks) = self.solve(size, wrap, max_backtracks, rng, |cell, side| {
+            let outside = Hex::from_axial(origin + cell).neighbour(side);
+            let tile = map.get(outside).tile();
+            self.tile_edges[tile.x as usize].rotated(tile.y).get(side + 3)
+        })?;
+        for (index, tile) in tiles.into_iter().enumerate() {
+            let cell = origin + uvec2(index as u32 % size.x, index as u32 / size.x).as_ivec2();
+            map.set(Hex::from_axial(cell), Cell::new(tile, random_prng(rng)));
+        }
+        Ok(backtracks)
+    }
+
+    /// Solves a region of `size` cells, returning the tile of every cell in row-major order
+    /// together with the number of backtracks that were needed.
+    ///
+    /// `border` returns the edge facing `side` of a cell on the border of the region,
+    /// for sides that lie outside of the region.
+    pub fn solve(
+        &self,
+        size: UVec2,
+        wrap: BVec2,
+        max_backtracks: u32,
+        rng: &mut impl Rng,
+        border: impl Fn(IVec2, usize) -> Edge,
+    ) -> Result<(Vec<UVec2>, u32), WfcError> {
+        // Without any tiles, the first cell already has no options.
+        if self.variants.is_empty() {
+            return Err(WfcError {cell: UVec2::ZERO, backtracks: 0});
+        }
+        let mut state = State::new(self, size, wrap, rng.r#gen());
+
+        // Apply constraints from outside the region.
+        for index in 0..state.domains.len() / self.words {
+            for side in 0..6 {
+                if state.neighbour(index, side).is_none() {
+                    let edge = border(state.position(index).as_ivec2(), side);
+                    state.restrict(index, self.fits((side + 3) % 6, edge as usize));
+                }
+            }
+            state.queue.push(index);
+        }
+        if let Err(cell) = state.propagate() {
+            return Err(WfcError {cell: state.position(cell), backtracks: 0});
+        }
+
+        let mut decisions = Vec::<(usize, usize, usize)>::new();
+        let mut backtracks = 0;
+        while let Some(cell) = state.next_cell() {
+            let variant = state.choose(cell, rng);
+            decisions.push((cell, variant, state.trail.len()));
+            let mut set = vec![0; self.words];
+            set[variant / 64] = 1 << (variant % 64);
+            state.restrict(cell, &set);
+            let mut result = state.propagate();
+
+            // Undo choices until the contradiction is resolved.
+            while let Err(failed) = result {
+                backtracks += 1;
+                let Some((cell, variant, mark)) = decisions.pop().filter(|_| backtracks <= max_backtracks) else {
+                    return Err(WfcError {cell: state.position(failed), backtracks});
+                };
+                state.undo(mark);
+                let mut ban = vec![!0; self.words];
+                ban[variant / 64] &= !(1 << (variant % 64));
+                state.restrict(cell, &ban);
+                result = state.propagate();
+            }
+        }
+
+        let tiles = state.domains.chunks_exact(self.words).map(|domain| {
+            let index = domain.iter().enumerate()
+                .find(|(_, word)| **word != 0)
+                .map(|(word, bits)| word * 64 + bits.trailing_zeros() as usize)
+                .expect("all cells are collapsed");
+            self.variants[index]
+        }).collect();
+        Ok((tiles, backtracks))
+    }
+}
+
+/// The state of a single run of the solver.
+struct State<'a> {
+    wfc: &'a Wfc,
+    size: UVec2,
+    wrap: BVec2,
+    /// The remaining variants of each cell, `wfc.words` words per cell.
+    domains: Vec<u64>,
+    /// Overwritten words as `(index in domains, old value)`, for backtracking.
+    trail: Vec<(usize, u64)>,
+    /// Cells whose domain shrunk and whose neighbours must be updated.
+    queue: Vec<usize>,
+    /// Candidates for collapsing, ordered by their number of options.
+    /// Entries are not removed when a domain changes, so they must be checked when popped.
+    heap: BinaryHeap<Reverse<(u32, u32, usize)>>,
+    noise: u32,
+}
+
+impl<'a> State<'a> {
+    fn new(wfc: &'a Wfc, size: UVec2, wrap: BVec2, noise: u32) -> Self {
+        let cells = (size.x * size.y) as usize;
+        let mut all = vec![!0u64; wfc.words];
+        let spare = wfc.words * 64 - wfc.variants.len();
+        if spare > 0 {
+            all[wfc.words - 1] >>= spare;
+        }
+        let mut state = Self {
+            wfc,
+            size,
+            wrap,
+            domains: all.repeat(cells),
+            trail: Vec::new(),
+            queue: Vec::new(),
+            heap: BinaryHeap::new(),
+            noise,
+        };
+        for cell in 0..cells {
+            state.push_candidate(cell);
+        }
+        state
+    }
+
+    fn position(&self, cell: usize) -> UVec2 {
+        uvec2(cell as u32 % self.size.x, cell as u32 / self.size.x)
+    }
+
+    fn neighbour(&self, cell: usize, side: usize) -> Option<usize> {
+        let size = self.size.as_ivec2();
+        let mut pos = self.position(cell).as_ivec2() + Hex::DIRECTIONS[side].axial();
+        pos = IVec2::select(self.wrap, pos.rem_euclid(size), pos);
+        if pos.cmplt(IVec2::ZERO).any() || pos.cmpge(size).any() {
+            return None;
+        }
+        Some((pos.y * size.x + pos.x) as usize)
+    }
+
+    fn domain(&self, cell: usize) -> &[u64] {
+        &self.domains[cell * self.wfc.words..(cell + 1) * self.wfc.words]
+    }
+
+    fn count(&self, cell: usize) -> u32 {
+        self.domain(cell).iter().map(|word| word.count_ones()).sum()
+    }
+
+    fn push_candidate(&mut self, cell: usize) {
+        let count = self.count(cell);
+        if count > 1 {
+            // Break ties pseudo-randomly, so that the map doesn't fill up in scanline order.
+            let noise = (cell as u32).wrapping_mul(0x9e3779b9) ^ self.noise;
+            self.heap.push(Reverse((count, noise, cell)));
+        }
+    }
+
+    /// The uncollapsed cell with the fewest options.
+    fn next_cell(&mut self) -> Option<usize> {
+        while let Some(Reverse((count, _, cell))) = self.heap.pop() {
+            if count == self.count(cell) {
+                return Some(cell);
+            }
+        }
+        None
+    }
+
+    /// Picks one of the remaining variants of a cell, by weight.
+    fn choose(&self, cell: usize, rng: &mut impl Rng) -> usize {
+        let options: Vec<usize> = (0..self.wfc.variants.len())
+            .filter(|&variant| self.domain(cell)[variant / 64] & (1 << (variant % 64)) != 0)
+            .collect();
+        let total: f32 = options.iter().map(|&variant| self.wfc.weights[variant]).sum();
+        // Only tiles with no weight are left, so none is preferred over the others.
+        if total <= 0.0 {
+            return options[rng.gen_range(0..options.len())];
+        }
+        let mut pick = rng.gen_range(0.0..total);
+        for &variant in &options {
+            pick -= self.wfc.weights[variant];
+            if pick < 0.0 {
+                return variant;
+            }
+        }
+        *options.last().unwrap()
+    }
+
+    /// Removes the variants that are not in `allowed` from a cell.
+    fn restrict(&mut self, cell: usize, allowed: &[u64]) {
+        let mut changed = false;
+        for (word, allowed) in allowed.iter().enumerate() {
+            let index = cell * self.wfc.words + word;
+            let value = self.domains[index] & allowed;
+            if value != self.domains[index] {
+                self.trail.push((index, self.domains[index]));
+                self.domains[index] = value;
+                changed = true;
+            }
+        }
+        if changed {
+            self.queue.push(cell);
+            self.push_candidate(cell);
+        }
+    }
+
+    /// Restricts the neighbours of queued cells until nothing changes.
+    /// Returns the cell that ran out of options on a contradiction.
+    fn propagate(&mut self) -> Result<(), usize> {
+        let words = self.wfc.words;
+        let mut allowed = vec![0; words];
+        while let Some(cell) = self.queue.pop() {
+            if self.count(cell) == 0 {
+                self.queue.clear();
+                return Err(cell);
+            }
+            for side in 0..6 {
+                let Some(neighbour) = self.neighbour(cell, side) else {continue};
+                allowed.fill(0);
+                for edge in 0..EDGES {
+                    let present = self.domain(cell).iter()
+                        .zip(self.wfc.has_edge(side, edge))
+                        .any(|(a, b)| a & b != 0);
+                    if present {
+                        for (res, fits) in allowed.iter_mut().zip(self.wfc.fits(side, edge)) {
+                            *res |= fits;
+                        }
+                    }
+                }
+                self.restrict(neighbour, &allowed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Restores all domains to how they were when the trail had length `mark`.
+    fn undo(&mut self, mark: usize) {
+        self.queue.clear();
+        while self.trail.len() > mark {
+            let (index, value) = self.trail.pop().unwrap();
+            self.domains[index] = value;
+            let cell = index / self.wfc.words;
+            self.push_candidate(cell);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tiles that only fit in a triangle-free grid, which hexes are not, and a weightless
+    /// tile that is never picked while the others are left.
+    fn catalogue(with_grass: bool) -> TileCatalogue {
+        let mut tiles = String::from(r#"
+            (path: "x.glb", edges: "cccccc", category: Water, weight: 1.0, name: "X"),
+            (path: "y.glb", edges: "CCCCCC", category: Water, weight: 1.0, name: "Y"),
+        "#);
+        if with_grass {
+            tiles += r#"(path: "g.glb", edges: "gggggg", category: Grass, weight: 0.0, name: "G"),"#;
+        }
+        ron::de::from_str(&format!("(tiles: [{tiles}])")).unwrap()
+    }
+
+    fn solve(catalogue: &TileCatalogue, max_backtracks: u32) -> Result<(Vec<UVec2>, u32), WfcError> {
+        let mut rng = WorldRng::new(WorldSeed(1));
+        Wfc::new(catalogue).solve(uvec2(4, 4), BVec2::TRUE, max_backtracks, &mut *rng, |_, _| unreachable!())
+    }
+
+    #[test]
+    fn generated_islands_fit_together() {
+        let catalogue = TileCatalogue::standard();
+        let ocean = uvec2(catalogue.roles.ocean, 0);
+        let size = UVec2::splat(24);
+        for seed in 0..3 {
+            let mut rng = WorldRng::new(WorldSeed(seed));
+            let mut map = HexMap::new(MapShape::new(64, MapBoundary::Bounded), ocean, &catalogue, &mut *rng);
+            Wfc::new(&catalogue).generate(&mut map, -(size / 2).as_ivec2(), size, 10000, &mut *rng).unwrap();
+            // The ring of ocean around the island has to fit it too.
+            for y in -13..13 {
+                for x in -13..13 {
+                    let hex = Hex::from_axial(ivec2(x, y));
+                    assert_eq!(map.mismatches(&catalogue, hex, map.get(hex).tile()), 0, "seed {seed}, cell {x},{y}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wrapping_maps_fit_across_the_seam() {
+        let catalogue = TileCatalogue::standard();
+        let ocean = uvec2(catalogue.roles.ocean, 0);
+        let mut rng = WorldRng::new(WorldSeed(2));
+        let shape = MapShape::new(16, MapBoundary::Wrapping);
+        let mut map = HexMap::new(shape, ocean, &catalogue, &mut *rng);
+        Wfc::new(&catalogue).generate(&mut map, IVec2::ZERO, UVec2::splat(16), 10000, &mut *rng).unwrap();
+        for y in 0..16 {
+            for x in 0..16 {
+                let hex = Hex::from_axial(ivec2(x, y));
+                assert_eq!(map.mismatches(&catalogue, hex, map.get(hex).tile()), 0, "cell {x},{y}");
+            }
+        }
+    }
+
+    #[test]
+    fn contradictions_are_undone() {
+        let (tiles, backtracks) = solve(&catalogue(true), 100).unwrap();
+        assert!(tiles.iter().all(|&tile| tile == uvec2(2, 0)));
+        assert!(backtracks >= 1);
+    }
+
+    #[test]
+    fn backtracking_gives_up() {
+        let error = solve(&catalogue(true), 0).unwrap_err();
+        assert_eq!(error.backtracks, 1);
+        // Without a way out, undoing every choice doesn't help.
+        assert!(solve(&catalogue(false), 100).is_err());
+    }
+
+    #[test]
+    fn empty_catalogues_fail() {
+        let empty: TileCatalogue = ron::de::from_str("(tiles: [])").unwrap();
+        let error = solve(&empty, 100).unwrap_err();
+        assert_eq!((error.cell, error.backtracks), (UVec2::ZERO, 0));
+    }
+}
@@ -0,0 +1,57 @@
+//! The menu shown once the deck has run out, which ends the game.
+
+use bevy::prelude::*;
+
+use crate::{game::Score, menus::{new_game::NewGameOpener, Menu}, theme::widget};
+#[cfg(not(target_family = "wasm"))]
+use crate::game::LoadGame;
+
+pub(super) fn plugin(app: &mut App) {
+    app.add_systems(OnEnter(Menu::GameOver), spawn_game_over_menu);
+}
+
+fn spawn_game_over_menu(mut commands: Commands, score: Res<Score>) {
+    commands.spawn((
+        widget::ui_root("Game Over Menu"),
+        GlobalZIndex(2),
+        StateScoped(Menu::GameOver),
+        #[cfg(not(target_family = "wasm"))]
+        children![
+            widget::header("Game over"),
+            widget::label(format!("Final score {}", score.total)),
+            widget::button("New game", open_new_game_menu),
+            widget::button("Load", load_game),
+            widget::button("Exit", exit_app),
+        ],
+        #[cfg(target_family = "wasm")]
+        children![
+            widget::header("Game over"),
+            widget::label(format!("Final score {}", score.total)),
+            widget::button("New game", open_new_game_menu),
+        ],
+    ));
+}
+
+fn open_new_game_menu(
+    _: Trigger<Pointer<Click>>,
+    mut opener: ResMut<NewGameOpener>,
+    mut next_menu: ResMut<NextState<Menu>>,
+) {
+    opener.0 = Menu::GameOver;
+    next_menu.set(Menu::NewGame);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn load_game(
+    _: Trigger<Pointer<Click>>,
+    mut commands: Commands,
+    mut next_menu: ResMut<NextState<Menu>>,
+) {
+    commands.trigger(LoadGame);
+    next_menu.set(Menu::None);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn exit_app(_: Trigger<Pointer<Click>>, mut app_exit: EventWriter<AppExit>) {
+    app_exit.write(AppExit::Success);
+}
@@ -1,6 +1,8 @@
 //! The game's menus and transitions between them.
 
 mod credits;
+mod game_over;
+mod new_game;
 mod pause;
 mod settings;
 
@@ -11,6 +13,8 @@ pub(super) fn plugin(app: &mut App) {
 
     app.add_plugins((
         credits::plugin,
+        game_over::plugin,
+        new_game::plugin,
         settings::plugin,
         pause::plugin,
     ));
@@ -22,6 +26,8 @@ pub enum Menu {
     #[default]
     None,
     Credits,
+    NewGame,
     Settings,
     Pause,
+    GameOver,
 }
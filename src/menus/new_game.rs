@@ -0,0 +1,186 @@
+//! The new game menu, where the shape of the next map is chosen.
+
+use bevy::{ecs::system::IntoObserverSystem, input::common_conditions::input_just_pressed, prelude::*, ui::Val::*};
+
+use crate::{
+    game::{CommandLineSeed, MapBoundary, MapShape, WorldSeed, MAP_SIZES},
+    menus::Menu,
+    theme::prelude::*,
+};
+
+pub(super) fn plugin(app: &mut App) {
+    app.init_resource::<NewGameOpener>();
+    app.add_systems(OnEnter(Menu::NewGame), spawn_new_game_menu);
+    app.add_systems(
+        Update,
+        go_back.run_if(in_state(Menu::NewGame).and(input_just_pressed(KeyCode::Escape))),
+    );
+
+    app.register_type::<(MapSizeLabel, MapBoundaryLabel)>();
+    app.add_systems(
+        Update,
+        update_shape_labels.run_if(in_state(Menu::NewGame)),
+    );
+}
+
+/// The menu that opened the new game menu, which "Back" returns to.
+#[derive(Resource, Clone, Copy)]
+pub(super) struct NewGameOpener(pub Menu);
+
+impl Default for NewGameOpener {
+    fn default() -> Self {
+        Self(Menu::Pause)
+    }
+}
+
+/// The map shape picked in the menu, which only takes effect once the game starts.
+#[derive(Resource, Deref, DerefMut)]
+struct PendingShape(MapShape);
+
+fn spawn_new_game_menu(mut commands: Commands, shape: Res<MapShape>) {
+    commands.insert_resource(PendingShape(*shape));
+    commands.spawn((
+        widget::ui_root("New Game Menu"),
+        GlobalZIndex(2),
+        StateScoped(Menu::NewGame),
+        children![
+            widget::header("New game"),
+            shape_grid(),
+            widget::button("Start", start_game),
+            widget::button("Back", go_back_on_click),
+        ],
+    ));
+}
+
+fn shape_grid() -> impl Bundle {
+    (
+        Name::new("Map Shape Grid"),
+        Node {
+            display: Display::Grid,
+            row_gap: Px(10.0),
+            column_gap: Px(30.0),
+            grid_template_columns: RepeatedGridTrack::px(2, 400.0),
+            ..default()
+        },
+        children![
+            (
+                widget::label("Map Size"),
+                Node {
+                    justify_self: JustifySelf::End,
+                    ..default()
+                }
+            ),
+            stepper("Map Size Widget", MapSizeLabel, shrink_map, grow_map),
+            (
+                widget::label("Edges"),
+                Node {
+                    justify_self: JustifySelf::End,
+                    ..default()
+                }
+            ),
+            stepper("Map Boundary Widget", MapBoundaryLabel, previous_boundary, next_boundary),
+        ],
+    )
+}
+
+/// A value between a button for going down and one for going up.
+fn stepper<E: Event, B: Bundle, M1, M2>(
+    name: &'static str,
+    label: impl Component,
+    down: impl IntoObserverSystem<E, B, M1>,
+    up: impl IntoObserverSystem<E, B, M2>,
+) -> impl Bundle {
+    (
+        Name::new(name),
+        Node {
+            justify_self: JustifySelf::Start,
+            ..default()
+        },
+        children![
+            widget::button_small("-", down),
+            (
+                Name::new("Current Value"),
+                Node {
+                    padding: UiRect::horizontal(Px(10.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                children![(widget::label(""), label)],
+            ),
+            widget::button_small("+", up),
+        ],
+    )
+}
+
+const BOUNDARIES: [MapBoundary; 3] = [MapBoundary::Wrapping, MapBoundary::Bounded, MapBoundary::Infinite];
+
+fn shrink_map(_: Trigger<Pointer<Click>>, mut shape: ResMut<PendingShape>) {
+    if let Some(&size) = MAP_SIZES.iter().rev().find(|&&size| size < shape.size) {
+        shape.0 = MapShape::new(size, shape.boundary);
+    }
+}
+
+fn grow_map(_: Trigger<Pointer<Click>>, mut shape: ResMut<PendingShape>) {
+    if let Some(&size) = MAP_SIZES.iter().find(|&&size| size > shape.size) {
+        shape.0 = MapShape::new(size, shape.boundary);
+    }
+}
+
+fn previous_boundary(_: Trigger<Pointer<Click>>, mut shape: ResMut<PendingShape>) {
+    let index = BOUNDARIES.iter().position(|&boundary| boundary == shape.boundary).unwrap_or(0);
+    shape.boundary = BOUNDARIES[(index + BOUNDARIES.len() - 1) % BOUNDARIES.len()];
+}
+
+fn next_boundary(_: Trigger<Pointer<Click>>, mut shape: ResMut<PendingShape>) {
+    let index = BOUNDARIES.iter().position(|&boundary| boundary == shape.boundary).unwrap_or(0);
+    shape.boundary = BOUNDARIES[(index + 1) % BOUNDARIES.len()];
+}
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+struct MapSizeLabel;
+
+#[derive(Component, Reflect)]
+#[reflect(Component)]
+struct MapBoundaryLabel;
+
+fn update_shape_labels(
+    shape: Res<PendingShape>,
+    mut size_label: Single<&mut Text, (With<MapSizeLabel>, Without<MapBoundaryLabel>)>,
+    mut boundary_label: Single<&mut Text, With<MapBoundaryLabel>>,
+) {
+    size_label.0 = format!("{0} x {0}", shape.size);
+    boundary_label.0 = match shape.boundary {
+        MapBoundary::Wrapping => "Wrap around",
+        MapBoundary::Bounded => "Ocean",
+        MapBoundary::Infinite => "Infinite",
+    }.to_string();
+}
+
+/// Generates a new map with the chosen shape from a fresh seed, or the one
+/// given on the command line for the first new game.
+fn start_game(
+    _: Trigger<Pointer<Click>>,
+    pending: Res<PendingShape>,
+    mut shape: ResMut<MapShape>,
+    mut seed: ResMut<WorldSeed>,
+    mut command_line_seed: ResMut<CommandLineSeed>,
+    mut next_menu: ResMut<NextState<Menu>>,
+) {
+    *shape = pending.0;
+    *seed = WorldSeed(command_line_seed.0.take().unwrap_or_else(rand::random));
+    info!("World seed: {}", seed.0);
+    next_menu.set(Menu::None);
+}
+
+fn go_back_on_click(
+    _: Trigger<Pointer<Click>>,
+    opener: Res<NewGameOpener>,
+    mut next_menu: ResMut<NextState<Menu>>,
+) {
+    next_menu.set(opener.0);
+}
+
+fn go_back(opener: Res<NewGameOpener>, mut next_menu: ResMut<NextState<Menu>>) {
+    next_menu.set(opener.0);
+}
@@ -2,7 +2,9 @@
 
 use bevy::{input::common_conditions::input_just_pressed, prelude::*};
 
-use crate::{menus::Menu, theme::widget};
+use crate::{game::WorldSeed, menus::{new_game::NewGameOpener, Menu}, theme::widget};
+#[cfg(not(target_family = "wasm"))]
+use crate::game::{LoadGame, SaveGame};
 
 pub(super) fn plugin(app: &mut App) {
     app.add_systems(OnEnter(Menu::Pause), spawn_pause_menu);
@@ -12,7 +14,7 @@ pub(super) fn plugin(app: &mut App) {
     );
 }
 
-fn spawn_pause_menu(mut commands: Commands) {
+fn spawn_pause_menu(mut commands: Commands, seed: Res<WorldSeed>) {
     commands.spawn((
         widget::ui_root("Pause Menu"),
         GlobalZIndex(2),
@@ -20,7 +22,11 @@ fn spawn_pause_menu(mut commands: Commands) {
         #[cfg(not(target_family = "wasm"))]
         children![
             widget::header("Game paused"),
+            widget::label(format!("Seed {}", seed.0)),
             widget::button("Continue", close_menu),
+            widget::button("New game", open_new_game_menu),
+            widget::button("Save", save_game),
+            widget::button("Load", load_game),
             widget::button("Settings", open_settings_menu),
             widget::button("Credits", open_credits_menu),
             widget::button("Exit", exit_app),
@@ -28,13 +34,24 @@ fn spawn_pause_menu(mut commands: Commands) {
         #[cfg(target_family = "wasm")]
         children![
             widget::header("Game paused"),
+            widget::label(format!("Seed {}", seed.0)),
             widget::button("Continue", close_menu),
+            widget::button("New game", open_new_game_menu),
             widget::button("Settings", open_settings_menu),
             widget::button("Credits", open_credits_menu),
         ],
     ));
 }
 
+fn open_new_game_menu(
+    _: Trigger<Pointer<Click>>,
+    mut opener: ResMut<NewGameOpener>,
+    mut next_menu: ResMut<NextState<Menu>>,
+) {
+    opener.0 = Menu::Pause;
+    next_menu.set(Menu::NewGame);
+}
+
 fn open_settings_menu(_: Trigger<Pointer<Click>>, mut next_menu: ResMut<NextState<Menu>>) {
     next_menu.set(Menu::Settings);
 }
@@ -51,6 +68,21 @@ fn open_credits_menu(_: Trigger<Pointer<Click>>, mut next_menu: ResMut<NextState
     next_menu.set(Menu::Credits);
 }
 
+#[cfg(not(target_family = "wasm"))]
+fn save_game(_: Trigger<Pointer<Click>>, mut commands: Commands) {
+    commands.trigger(SaveGame);
+}
+
+#[cfg(not(target_family = "wasm"))]
+fn load_game(
+    _: Trigger<Pointer<Click>>,
+    mut commands: Commands,
+    mut next_menu: ResMut<NextState<Menu>>,
+) {
+    commands.trigger(LoadGame);
+    next_menu.set(Menu::None);
+}
+
 #[cfg(not(target_family = "wasm"))]
 fn exit_app(_: Trigger<Pointer<Click>>, mut app_exit: EventWriter<AppExit>) {
     app_exit.write(AppExit::Success);
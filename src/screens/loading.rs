@@ -3,7 +3,7 @@
 
 use bevy::prelude::*;
 
-use crate::{asset_tracking::ResourceHandles, screens::Screen, theme::prelude::*};
+use crate::{asset_tracking::ResourceHandles, game::TileCatalogue, screens::Screen, theme::prelude::*};
 
 pub(super) fn plugin(app: &mut App) {
     app.add_systems(OnEnter(Screen::Loading), spawn_loading_screen);
@@ -26,6 +26,7 @@ fn enter_gameplay_screen(mut next_screen: ResMut<NextState<Screen>>) {
     next_screen.set(Screen::Gameplay);
 }
 
-fn all_assets_loaded(resource_handles: Res<ResourceHandles>) -> bool {
-    resource_handles.is_all_done()
+fn all_assets_loaded(resource_handles: Res<ResourceHandles>, catalogue: Option<Res<TileCatalogue>>) -> bool {
+    // The tile catalogue also waits for the tile packs, which aren't tracked as resource handles.
+    resource_handles.is_all_done() && catalogue.is_some()
 }